@@ -0,0 +1,1153 @@
+use std::cell::RefCell;
+use std::rc::{Rc, Weak};
+use std::io::{self, Cursor};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::cartridge::Cartridge;
+use crate::irq::{IrqLine, IrqSource};
+use crate::io::IO;
+use crate::savable::Savable;
+
+use crate::{test_bit, modify_bit};
+
+pub const CPU_FREQUENCY: f64 = 1789773.0; // NTSC
+pub const DEFAULT_SAMPLE_RATE: f64 = 44100.0;
+
+static LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20,  2, 40,  4, 80,  6, 160,  8, 60, 10, 14, 12, 26, 14,
+    12,  16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30
+];
+
+static DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0], // 12.5%
+    [0, 1, 1, 0, 0, 0, 0, 0], // 25%
+    [0, 1, 1, 1, 1, 0, 0, 0], // 50%
+    [1, 0, 0, 1, 1, 1, 1, 1]  // 25% negated
+];
+
+static TRIANGLE_TABLE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10,  9,  8,  7,  6,  5,  4,  3,  2,  1,  0,
+     0,  1,  2,  3,  4,  5,  6,  7,  8,  9, 10, 11, 12, 13, 14, 15
+];
+
+/* Periods are in CPU cycles */
+static NOISE_PERIOD_TABLE: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068
+];
+
+/* Periods are in CPU cycles */
+static DMC_RATE_TABLE: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54
+];
+
+/* Frame counter steps, measured in CPU cycles since the sequence started */
+const FRAME_STEP1: u32 = 7457;
+const FRAME_STEP2: u32 = 14913;
+const FRAME_STEP3: u32 = 22371;
+const FRAME_STEP4: u32 = 29829;
+const FRAME_STEP5: u32 = 37281;
+
+/* https://www.nesdev.org/wiki/APU_Envelope */
+struct Envelope {
+    start: bool,
+    loop_flag: bool,
+    constant_volume: bool,
+    volume: u8, /* also the divider period */
+    divider: u8,
+    decay: u8
+}
+
+impl Envelope {
+    fn new() -> Self {
+        Envelope {
+            start: false,
+            loop_flag: false,
+            constant_volume: false,
+            volume: 0,
+            divider: 0,
+            decay: 0
+        }
+    }
+
+    fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.loop_flag {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.constant_volume {
+            self.volume
+        } else {
+            self.decay
+        }
+    }
+}
+
+impl Savable for Envelope {
+    fn save_state(&self, state: &mut Vec<u8>) {
+        state.write_u8(self.start as u8).expect("Unable to save u8");
+        state.write_u8(self.loop_flag as u8).expect("Unable to save u8");
+        state.write_u8(self.constant_volume as u8).expect("Unable to save u8");
+        state.write_u8(self.volume).expect("Unable to save u8");
+        state.write_u8(self.divider).expect("Unable to save u8");
+        state.write_u8(self.decay).expect("Unable to save u8");
+    }
+
+    fn load_state(&mut self, state: &mut Cursor<Vec<u8>>) -> io::Result<()> {
+        self.start = state.read_u8()? != 0;
+        self.loop_flag = state.read_u8()? != 0;
+        self.constant_volume = state.read_u8()? != 0;
+        self.volume = state.read_u8()?;
+        self.divider = state.read_u8()?;
+        self.decay = state.read_u8()?;
+
+        Ok(())
+    }
+}
+
+/* https://www.nesdev.org/wiki/APU_Pulse */
+struct Pulse {
+    /* pulse 1 adds the ones' complement when sweeping downwards, pulse 2 adds the two's complement */
+    ones_complement: bool,
+
+    enabled: bool,
+    duty: u8,
+    sequence_pos: u8,
+
+    timer_period: u16,
+    timer: u16,
+
+    length_counter: u8,
+    length_halt: bool,
+
+    envelope: Envelope,
+
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_divider: u8,
+    sweep_reload: bool
+}
+
+impl Pulse {
+    fn new(ones_complement: bool) -> Self {
+        Pulse {
+            ones_complement: ones_complement,
+
+            enabled: false,
+            duty: 0,
+            sequence_pos: 0,
+
+            timer_period: 0,
+            timer: 0,
+
+            length_counter: 0,
+            length_halt: false,
+
+            envelope: Envelope::new(),
+
+            sweep_enabled: false,
+            sweep_period: 0,
+            sweep_negate: false,
+            sweep_shift: 0,
+            sweep_divider: 0,
+            sweep_reload: false
+        }
+    }
+
+    fn write_register(&mut self, register: u16, data: u8) {
+        match register {
+            0 => { // DDLC VVVV
+                self.duty = data >> 6;
+                self.length_halt = test_bit!(data, 5);
+                self.envelope.loop_flag = test_bit!(data, 5);
+                self.envelope.constant_volume = test_bit!(data, 4);
+                self.envelope.volume = data & 0b00001111;
+            }
+            1 => { // EPPP NSSS
+                self.sweep_enabled = test_bit!(data, 7);
+                self.sweep_period = (data >> 4) & 0b111;
+                self.sweep_negate = test_bit!(data, 3);
+                self.sweep_shift = data & 0b111;
+                self.sweep_reload = true;
+            }
+            2 => { // TTTT TTTT
+                self.timer_period = (self.timer_period & 0xFF00) | (data as u16);
+            }
+            3 => { // LLLL LTTT
+                self.timer_period = (self.timer_period & 0x00FF) | (((data & 0b111) as u16) << 8);
+                if self.enabled {
+                    self.length_counter = LENGTH_TABLE[(data >> 3) as usize];
+                }
+                self.sequence_pos = 0;
+                self.envelope.start = true;
+            }
+            _ => {}
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.length_counter = 0;
+        }
+    }
+
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            self.sequence_pos = (self.sequence_pos + 1) & 0b111;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_length(&mut self) {
+        if !self.length_halt && self.length_counter > 0 {
+            self.length_counter -= 1;
+        }
+    }
+
+    fn clock_sweep(&mut self) {
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.sweep_muting() {
+            self.timer_period = self.sweep_target();
+        }
+
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    fn sweep_target(&self) -> u16 {
+        let change = self.timer_period >> self.sweep_shift;
+
+        if self.sweep_negate {
+            if self.ones_complement {
+                self.timer_period.saturating_sub(change + 1)
+            } else {
+                self.timer_period.saturating_sub(change)
+            }
+        } else {
+            self.timer_period + change
+        }
+    }
+
+    // The channel is silenced whenever the current period is too small or the target period overflows,
+    // even if the sweep unit is disabled
+    fn sweep_muting(&self) -> bool {
+        self.timer_period < 8 || self.sweep_target() > 0x7FF
+    }
+
+    fn output(&self) -> u8 {
+        if self.length_counter == 0 || self.sweep_muting() || DUTY_TABLE[self.duty as usize][self.sequence_pos as usize] == 0 {
+            return 0;
+        }
+        self.envelope.output()
+    }
+}
+
+impl Savable for Pulse {
+    fn save_state(&self, state: &mut Vec<u8>) {
+        state.write_u8(self.enabled as u8).expect("Unable to save u8");
+        state.write_u8(self.duty).expect("Unable to save u8");
+        state.write_u8(self.sequence_pos).expect("Unable to save u8");
+        state.write_u16::<LittleEndian>(self.timer_period).expect("Unable to save u16");
+        state.write_u16::<LittleEndian>(self.timer).expect("Unable to save u16");
+        state.write_u8(self.length_counter).expect("Unable to save u8");
+        state.write_u8(self.length_halt as u8).expect("Unable to save u8");
+        self.envelope.save_state(state);
+        state.write_u8(self.sweep_enabled as u8).expect("Unable to save u8");
+        state.write_u8(self.sweep_period).expect("Unable to save u8");
+        state.write_u8(self.sweep_negate as u8).expect("Unable to save u8");
+        state.write_u8(self.sweep_shift).expect("Unable to save u8");
+        state.write_u8(self.sweep_divider).expect("Unable to save u8");
+        state.write_u8(self.sweep_reload as u8).expect("Unable to save u8");
+    }
+
+    fn load_state(&mut self, state: &mut Cursor<Vec<u8>>) -> io::Result<()> {
+        self.enabled = state.read_u8()? != 0;
+        self.duty = state.read_u8()?;
+        self.sequence_pos = state.read_u8()?;
+        self.timer_period = state.read_u16::<LittleEndian>()?;
+        self.timer = state.read_u16::<LittleEndian>()?;
+        self.length_counter = state.read_u8()?;
+        self.length_halt = state.read_u8()? != 0;
+        self.envelope.load_state(state)?;
+        self.sweep_enabled = state.read_u8()? != 0;
+        self.sweep_period = state.read_u8()?;
+        self.sweep_negate = state.read_u8()? != 0;
+        self.sweep_shift = state.read_u8()?;
+        self.sweep_divider = state.read_u8()?;
+        self.sweep_reload = state.read_u8()? != 0;
+
+        Ok(())
+    }
+}
+
+/* https://www.nesdev.org/wiki/APU_Triangle */
+struct Triangle {
+    enabled: bool,
+    sequence_pos: u8,
+
+    timer_period: u16,
+    timer: u16,
+
+    length_counter: u8,
+
+    /* also the length counter halt flag */
+    control: bool,
+    linear_reload_value: u8,
+    linear_counter: u8,
+    linear_reload: bool
+}
+
+impl Triangle {
+    fn new() -> Self {
+        Triangle {
+            enabled: false,
+            sequence_pos: 0,
+
+            timer_period: 0,
+            timer: 0,
+
+            length_counter: 0,
+
+            control: false,
+            linear_reload_value: 0,
+            linear_counter: 0,
+            linear_reload: false
+        }
+    }
+
+    fn write_register(&mut self, register: u16, data: u8) {
+        match register {
+            0 => { // CRRR RRRR
+                self.control = test_bit!(data, 7);
+                self.linear_reload_value = data & 0b01111111;
+            }
+            2 => { // LLLL LLLL
+                self.timer_period = (self.timer_period & 0xFF00) | (data as u16);
+            }
+            3 => { // LLLL LHHH
+                self.timer_period = (self.timer_period & 0x00FF) | (((data & 0b111) as u16) << 8);
+                if self.enabled {
+                    self.length_counter = LENGTH_TABLE[(data >> 3) as usize];
+                }
+                self.linear_reload = true;
+            }
+            _ => {}
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.length_counter = 0;
+        }
+    }
+
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            if self.length_counter > 0 && self.linear_counter > 0 {
+                self.sequence_pos = (self.sequence_pos + 1) & 0b11111;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_length(&mut self) {
+        if !self.control && self.length_counter > 0 {
+            self.length_counter -= 1;
+        }
+    }
+
+    fn clock_linear(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        TRIANGLE_TABLE[self.sequence_pos as usize]
+    }
+}
+
+impl Savable for Triangle {
+    fn save_state(&self, state: &mut Vec<u8>) {
+        state.write_u8(self.enabled as u8).expect("Unable to save u8");
+        state.write_u8(self.sequence_pos).expect("Unable to save u8");
+        state.write_u16::<LittleEndian>(self.timer_period).expect("Unable to save u16");
+        state.write_u16::<LittleEndian>(self.timer).expect("Unable to save u16");
+        state.write_u8(self.length_counter).expect("Unable to save u8");
+        state.write_u8(self.control as u8).expect("Unable to save u8");
+        state.write_u8(self.linear_reload_value).expect("Unable to save u8");
+        state.write_u8(self.linear_counter).expect("Unable to save u8");
+        state.write_u8(self.linear_reload as u8).expect("Unable to save u8");
+    }
+
+    fn load_state(&mut self, state: &mut Cursor<Vec<u8>>) -> io::Result<()> {
+        self.enabled = state.read_u8()? != 0;
+        self.sequence_pos = state.read_u8()?;
+        self.timer_period = state.read_u16::<LittleEndian>()?;
+        self.timer = state.read_u16::<LittleEndian>()?;
+        self.length_counter = state.read_u8()?;
+        self.control = state.read_u8()? != 0;
+        self.linear_reload_value = state.read_u8()?;
+        self.linear_counter = state.read_u8()?;
+        self.linear_reload = state.read_u8()? != 0;
+
+        Ok(())
+    }
+}
+
+/* https://www.nesdev.org/wiki/APU_Noise */
+struct Noise {
+    enabled: bool,
+
+    mode: bool,
+    shift_register: u16,
+
+    timer_period: u16,
+    timer: u16,
+
+    length_counter: u8,
+    length_halt: bool,
+
+    envelope: Envelope
+}
+
+impl Noise {
+    fn new() -> Self {
+        Noise {
+            enabled: false,
+
+            mode: false,
+            shift_register: 1,
+
+            timer_period: NOISE_PERIOD_TABLE[0],
+            timer: 0,
+
+            length_counter: 0,
+            length_halt: false,
+
+            envelope: Envelope::new()
+        }
+    }
+
+    fn write_register(&mut self, register: u16, data: u8) {
+        match register {
+            0 => { // --LC VVVV
+                self.length_halt = test_bit!(data, 5);
+                self.envelope.loop_flag = test_bit!(data, 5);
+                self.envelope.constant_volume = test_bit!(data, 4);
+                self.envelope.volume = data & 0b00001111;
+            }
+            2 => { // M--- PPPP
+                self.mode = test_bit!(data, 7);
+                self.timer_period = NOISE_PERIOD_TABLE[(data & 0b00001111) as usize];
+            }
+            3 => { // LLLL L---
+                if self.enabled {
+                    self.length_counter = LENGTH_TABLE[(data >> 3) as usize];
+                }
+                self.envelope.start = true;
+            }
+            _ => {}
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.length_counter = 0;
+        }
+    }
+
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period - 1;
+
+            let other_bit = if self.mode { 6 } else { 1 };
+            let feedback = test_bit!(self.shift_register, 0) != test_bit!(self.shift_register, other_bit);
+
+            self.shift_register >>= 1;
+            modify_bit!(self.shift_register, 14, feedback);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_length(&mut self) {
+        if !self.length_halt && self.length_counter > 0 {
+            self.length_counter -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.length_counter == 0 || test_bit!(self.shift_register, 0) {
+            return 0;
+        }
+        self.envelope.output()
+    }
+}
+
+impl Savable for Noise {
+    fn save_state(&self, state: &mut Vec<u8>) {
+        state.write_u8(self.enabled as u8).expect("Unable to save u8");
+        state.write_u8(self.mode as u8).expect("Unable to save u8");
+        state.write_u16::<LittleEndian>(self.shift_register).expect("Unable to save u16");
+        state.write_u16::<LittleEndian>(self.timer_period).expect("Unable to save u16");
+        state.write_u16::<LittleEndian>(self.timer).expect("Unable to save u16");
+        state.write_u8(self.length_counter).expect("Unable to save u8");
+        state.write_u8(self.length_halt as u8).expect("Unable to save u8");
+        self.envelope.save_state(state);
+    }
+
+    fn load_state(&mut self, state: &mut Cursor<Vec<u8>>) -> io::Result<()> {
+        self.enabled = state.read_u8()? != 0;
+        self.mode = state.read_u8()? != 0;
+        self.shift_register = state.read_u16::<LittleEndian>()?;
+        self.timer_period = state.read_u16::<LittleEndian>()?;
+        self.timer = state.read_u16::<LittleEndian>()?;
+        self.length_counter = state.read_u8()?;
+        self.length_halt = state.read_u8()? != 0;
+        self.envelope.load_state(state)?;
+
+        // the period only ever comes from the table, and clock_timer() would count down past zero with a zero one
+        if !NOISE_PERIOD_TABLE.contains(&self.timer_period) {
+            let message = format!("Invalid noise timer period: {}", self.timer_period);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+        }
+
+        Ok(())
+    }
+}
+
+/* https://www.nesdev.org/wiki/APU_DMC */
+struct Dmc {
+    irq_enabled: bool,
+    loop_flag: bool,
+
+    timer_period: u16,
+    timer: u16,
+
+    /* output unit */
+    output_level: u8,
+    shift_register: u8,
+    bits_remaining: u8,
+    silence: bool,
+
+    /* memory reader */
+    sample_addr: u16,
+    sample_length: u16,
+    current_addr: u16,
+    bytes_remaining: u16,
+    sample_buffer: Option<u8>
+}
+
+impl Dmc {
+    fn new() -> Self {
+        Dmc {
+            irq_enabled: false,
+            loop_flag: false,
+
+            timer_period: DMC_RATE_TABLE[0],
+            timer: 0,
+
+            output_level: 0,
+            shift_register: 0,
+            bits_remaining: 8,
+            silence: true,
+
+            sample_addr: 0xC000,
+            sample_length: 1,
+            current_addr: 0xC000,
+            bytes_remaining: 0,
+            sample_buffer: None
+        }
+    }
+
+    fn write_register(&mut self, register: u16, data: u8) {
+        match register {
+            0 => { // IL-- RRRR
+                self.irq_enabled = test_bit!(data, 7);
+                self.loop_flag = test_bit!(data, 6);
+                self.timer_period = DMC_RATE_TABLE[(data & 0b00001111) as usize];
+            }
+            1 => { // -DDD DDDD
+                self.output_level = data & 0b01111111;
+            }
+            2 => { // AAAA AAAA
+                self.sample_addr = 0xC000 | ((data as u16) << 6);
+            }
+            3 => { // LLLL LLLL
+                self.sample_length = ((data as u16) << 4) | 1;
+            }
+            _ => {}
+        }
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current_addr = self.sample_addr;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    // Returns the address of the next sample byte if the memory reader wants to fill the sample buffer
+    fn fetch_addr(&self) -> Option<u16> {
+        if self.sample_buffer.is_none() && self.bytes_remaining > 0 {
+            Some(self.current_addr)
+        } else {
+            None
+        }
+    }
+
+    // Returns true if the end of the sample was reached and an interrupt should be raised
+    fn fill_buffer(&mut self, data: u8) -> bool {
+        self.sample_buffer = Some(data);
+
+        // the address wraps around to $8000 instead of $0000
+        self.current_addr = if self.current_addr == 0xFFFF {
+            0x8000
+        } else {
+            self.current_addr + 1
+        };
+
+        self.bytes_remaining -= 1;
+
+        if self.bytes_remaining == 0 {
+            if self.loop_flag {
+                self.restart();
+            } else {
+                return self.irq_enabled;
+            }
+        }
+
+        false
+    }
+
+    fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+
+        self.timer = self.timer_period - 1;
+
+        if !self.silence {
+            if test_bit!(self.shift_register, 0) {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+        }
+
+        self.shift_register >>= 1;
+        self.bits_remaining -= 1;
+
+        if self.bits_remaining == 0 {
+            // a new output cycle starts
+            self.bits_remaining = 8;
+
+            match self.sample_buffer.take() {
+                Some(data) => {
+                    self.silence = false;
+                    self.shift_register = data;
+                }
+                None => {
+                    self.silence = true;
+                }
+            }
+        }
+    }
+
+    fn output(&self) -> u8 {
+        self.output_level
+    }
+}
+
+impl Savable for Dmc {
+    fn save_state(&self, state: &mut Vec<u8>) {
+        state.write_u8(self.irq_enabled as u8).expect("Unable to save u8");
+        state.write_u8(self.loop_flag as u8).expect("Unable to save u8");
+        state.write_u16::<LittleEndian>(self.timer_period).expect("Unable to save u16");
+        state.write_u16::<LittleEndian>(self.timer).expect("Unable to save u16");
+        state.write_u8(self.output_level).expect("Unable to save u8");
+        state.write_u8(self.shift_register).expect("Unable to save u8");
+        state.write_u8(self.bits_remaining).expect("Unable to save u8");
+        state.write_u8(self.silence as u8).expect("Unable to save u8");
+        state.write_u16::<LittleEndian>(self.sample_addr).expect("Unable to save u16");
+        state.write_u16::<LittleEndian>(self.sample_length).expect("Unable to save u16");
+        state.write_u16::<LittleEndian>(self.current_addr).expect("Unable to save u16");
+        state.write_u16::<LittleEndian>(self.bytes_remaining).expect("Unable to save u16");
+        state.write_u8(self.sample_buffer.is_some() as u8).expect("Unable to save u8");
+        state.write_u8(self.sample_buffer.unwrap_or(0)).expect("Unable to save u8");
+    }
+
+    fn load_state(&mut self, state: &mut Cursor<Vec<u8>>) -> io::Result<()> {
+        self.irq_enabled = state.read_u8()? != 0;
+        self.loop_flag = state.read_u8()? != 0;
+        self.timer_period = state.read_u16::<LittleEndian>()?;
+        self.timer = state.read_u16::<LittleEndian>()?;
+        self.output_level = state.read_u8()?;
+        self.shift_register = state.read_u8()?;
+        self.bits_remaining = state.read_u8()?;
+        self.silence = state.read_u8()? != 0;
+        self.sample_addr = state.read_u16::<LittleEndian>()?;
+        self.sample_length = state.read_u16::<LittleEndian>()?;
+        self.current_addr = state.read_u16::<LittleEndian>()?;
+        self.bytes_remaining = state.read_u16::<LittleEndian>()?;
+        let buffer_full = state.read_u8()? != 0;
+        let buffer = state.read_u8()?;
+        self.sample_buffer = if buffer_full { Some(buffer) } else { None };
+
+        // same for these two, clock_timer() counts them down past zero otherwise
+        if !DMC_RATE_TABLE.contains(&self.timer_period) {
+            let message = format!("Invalid DMC timer period: {}", self.timer_period);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+        }
+        if self.bits_remaining == 0 || self.bits_remaining > 8 {
+            let message = format!("Invalid number of DMC bits remaining: {}", self.bits_remaining);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+        }
+
+        Ok(())
+    }
+}
+
+/* First order filter; see https://www.nesdev.org/wiki/APU_Mixer for the filter chain of the real console */
+struct Filter {
+    alpha: f32,
+    high_pass: bool,
+    prev_in: f32,
+    prev_out: f32
+}
+
+impl Filter {
+    fn high_pass(sample_rate: f64, cutoff: f64) -> Self {
+        let rc = 1.0 / (2.0 * std::f64::consts::PI * cutoff);
+        let dt = 1.0 / sample_rate;
+        Filter {
+            alpha: (rc / (rc + dt)) as f32,
+            high_pass: true,
+            prev_in: 0.0,
+            prev_out: 0.0
+        }
+    }
+
+    fn low_pass(sample_rate: f64, cutoff: f64) -> Self {
+        let rc = 1.0 / (2.0 * std::f64::consts::PI * cutoff);
+        let dt = 1.0 / sample_rate;
+        Filter {
+            alpha: (dt / (rc + dt)) as f32,
+            high_pass: false,
+            prev_in: 0.0,
+            prev_out: 0.0
+        }
+    }
+
+    fn process(&mut self, sample: f32) -> f32 {
+        let out = if self.high_pass {
+            self.alpha * (self.prev_out + sample - self.prev_in)
+        } else {
+            self.prev_out + self.alpha * (sample - self.prev_out)
+        };
+        self.prev_in = sample;
+        self.prev_out = out;
+        out
+    }
+}
+
+pub struct APU {
+    cart: Weak<RefCell<Cartridge>>, /* for DMC sample fetches */
+    irq: Weak<RefCell<IrqLine>>,
+
+    pulse1: Pulse,
+    pulse2: Pulse,
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+
+    // FRAME COUNTER ($4017)
+    five_step_mode: bool,
+    irq_inhibit: bool,
+    frame_cycle: u32,
+
+    // pulse, noise and DMC timers are clocked on every other CPU cycle
+    odd_cycle: bool,
+
+    // Lookup tables for the nonlinear mixer
+    pulse_table: [f32; 31],
+    tnd_table: [f32; 203],
+
+    sample_rate: f64,
+    cycles_per_sample: f64,
+    sample_clock: f64,
+    sample_sum: f32,
+    sample_count: u32,
+    filters: [Filter; 3],
+
+    samples: Vec<f32>,
+
+    // CPU cycles stolen by DMC sample fetches which haven't been accounted for yet
+    pub stall_cycles: u64
+}
+
+impl APU {
+    pub fn new(cart: Weak<RefCell<Cartridge>>, irq: Weak<RefCell<IrqLine>>) -> Self {
+        let mut pulse_table = [0.0; 31];
+        for (n, entry) in pulse_table.iter_mut().enumerate().skip(1) {
+            *entry = 95.52 / (8128.0 / (n as f32) + 100.0);
+        }
+
+        let mut tnd_table = [0.0; 203];
+        for (n, entry) in tnd_table.iter_mut().enumerate().skip(1) {
+            *entry = 163.67 / (24329.0 / (n as f32) + 100.0);
+        }
+
+        APU {
+            cart: cart.clone(),
+            irq: irq.clone(),
+
+            pulse1: Pulse::new(true),
+            pulse2: Pulse::new(false),
+            triangle: Triangle::new(),
+            noise: Noise::new(),
+            dmc: Dmc::new(),
+
+            five_step_mode: false,
+            irq_inhibit: false,
+            frame_cycle: 0,
+
+            odd_cycle: false,
+
+            pulse_table: pulse_table,
+            tnd_table: tnd_table,
+
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            cycles_per_sample: CPU_FREQUENCY / DEFAULT_SAMPLE_RATE,
+            sample_clock: 0.0,
+            sample_sum: 0.0,
+            sample_count: 0,
+            filters: APU::make_filters(DEFAULT_SAMPLE_RATE),
+
+            samples: Vec::new(),
+
+            stall_cycles: 0
+        }
+    }
+
+    fn cart(&self) -> Rc<RefCell<Cartridge>> {
+        self.cart.upgrade().expect("Cartridge lost for apu")
+    }
+
+    fn irq(&self) -> Rc<RefCell<IrqLine>> {
+        self.irq.upgrade().expect("IRQ line lost for apu")
+    }
+
+    fn make_filters(sample_rate: f64) -> [Filter; 3] {
+        [
+            Filter::high_pass(sample_rate, 90.0),
+            Filter::high_pass(sample_rate, 440.0),
+            Filter::low_pass(sample_rate, 14000.0)
+        ]
+    }
+
+    pub fn reset(&mut self) {
+        self.pulse1 = Pulse::new(true);
+        self.pulse2 = Pulse::new(false);
+        self.triangle = Triangle::new();
+        self.noise = Noise::new();
+        self.dmc = Dmc::new();
+
+        self.five_step_mode = false;
+        self.irq_inhibit = false;
+        self.frame_cycle = 0;
+
+        self.odd_cycle = false;
+
+        self.sample_clock = 0.0;
+        self.sample_sum = 0.0;
+        self.sample_count = 0;
+        self.filters = APU::make_filters(self.sample_rate);
+
+        self.samples.clear();
+
+        self.stall_cycles = 0;
+    }
+
+    /// Changes the output sample rate. Frontends can nudge this slightly to keep their audio queue from
+    /// running dry or overflowing.
+    pub fn set_sample_rate(&mut self, sample_rate: f64) {
+        if (sample_rate - self.sample_rate).abs() > 1000.0 {
+            // only rebuild the filters on large changes so that rate control doesn't cause clicks
+            self.filters = APU::make_filters(sample_rate);
+        }
+        self.sample_rate = sample_rate;
+        self.cycles_per_sample = CPU_FREQUENCY / sample_rate;
+    }
+
+    pub fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    /// Returns all samples produced since the last call. Samples are mono and range from -1.0 to 1.0.
+    pub fn drain_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+
+    // Advances the APU by one CPU cycle
+    pub fn tick(&mut self) {
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+
+        if self.odd_cycle {
+            self.pulse1.clock_timer();
+            self.pulse2.clock_timer();
+        }
+        self.odd_cycle = !self.odd_cycle;
+
+        if let Some(addr) = self.dmc.fetch_addr() {
+            let data = self.cart().borrow_mut().read_byte(addr);
+            if self.dmc.fill_buffer(data) {
+                self.irq().borrow_mut().assert(IrqSource::DMC);
+            }
+            self.stall_cycles += 4;
+        }
+
+        self.clock_frame_counter();
+        self.mix();
+    }
+
+    /* https://www.nesdev.org/wiki/APU_Frame_Counter */
+    fn clock_frame_counter(&mut self) {
+        self.frame_cycle += 1;
+
+        match self.frame_cycle {
+            FRAME_STEP1 | FRAME_STEP3 => {
+                self.clock_quarter_frame();
+            }
+            FRAME_STEP2 => {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+            }
+            _ => {}
+        }
+
+        if !self.five_step_mode {
+            if self.frame_cycle >= FRAME_STEP4 - 1 && !self.irq_inhibit {
+                self.irq().borrow_mut().assert(IrqSource::FrameCounter);
+            }
+            if self.frame_cycle == FRAME_STEP4 {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+            }
+            if self.frame_cycle == FRAME_STEP4 + 1 {
+                self.frame_cycle = 0;
+            }
+        } else {
+            if self.frame_cycle == FRAME_STEP5 {
+                self.clock_quarter_frame();
+                self.clock_half_frame();
+            }
+            if self.frame_cycle == FRAME_STEP5 + 1 {
+                self.frame_cycle = 0;
+            }
+        }
+    }
+
+    // Envelopes and the triangle's linear counter
+    fn clock_quarter_frame(&mut self) {
+        self.pulse1.envelope.clock();
+        self.pulse2.envelope.clock();
+        self.triangle.clock_linear();
+        self.noise.envelope.clock();
+    }
+
+    // Length counters and sweep units
+    fn clock_half_frame(&mut self) {
+        self.pulse1.clock_length();
+        self.pulse1.clock_sweep();
+        self.pulse2.clock_length();
+        self.pulse2.clock_sweep();
+        self.triangle.clock_length();
+        self.noise.clock_length();
+    }
+
+    /* https://www.nesdev.org/wiki/APU_Mixer */
+    fn mix(&mut self) {
+        let pulse = self.pulse1.output() + self.pulse2.output();
+        let tnd = 3 * self.triangle.output() + 2 * self.noise.output() + self.dmc.output();
+
+        self.sample_sum += self.pulse_table[pulse as usize] + self.tnd_table[tnd as usize];
+        self.sample_count += 1;
+
+        self.sample_clock += 1.0;
+        if self.sample_clock >= self.cycles_per_sample {
+            self.sample_clock -= self.cycles_per_sample;
+
+            // average everything we've mixed since the previous sample
+            let mut sample = self.sample_sum / (self.sample_count as f32);
+            for filter in self.filters.iter_mut() {
+                sample = filter.process(sample);
+            }
+
+            self.samples.push(sample.clamp(-1.0, 1.0));
+
+            self.sample_sum = 0.0;
+            self.sample_count = 0;
+        }
+    }
+
+    /*
+    Status ($4015) read
+      7  bit  0
+      ---- ----
+      IF-D NT21
+      |||| ||||
+      |||| |||+- Pulse 1 length counter > 0
+      |||| ||+-- Pulse 2 length counter > 0
+      |||| |+--- Triangle length counter > 0
+      |||| +---- Noise length counter > 0
+      |||+------ DMC active
+      ||+------- Open bus
+      |+-------- Frame interrupt
+      +--------- DMC interrupt
+    */
+    pub fn read_status(&mut self) -> u8 {
+        let data = self.peek_status();
+
+        // reading this register acknowledges the frame interrupt (but not the DMC interrupt)
+        self.irq().borrow_mut().acknowledge(IrqSource::FrameCounter);
+
+        data
+    }
+
+    // $4015 as a read would see it, without acknowledging the frame interrupt
+    pub fn peek_status(&self) -> u8 {
+        let mut data: u8 = 0;
+
+        modify_bit!(data, 0, self.pulse1.length_counter > 0);
+        modify_bit!(data, 1, self.pulse2.length_counter > 0);
+        modify_bit!(data, 2, self.triangle.length_counter > 0);
+        modify_bit!(data, 3, self.noise.length_counter > 0);
+        modify_bit!(data, 4, self.dmc.bytes_remaining > 0);
+
+        let irq = self.irq();
+        let irq = irq.borrow();
+
+        modify_bit!(data, 6, irq.is_asserted(IrqSource::FrameCounter));
+        modify_bit!(data, 7, irq.is_asserted(IrqSource::DMC));
+
+        data
+    }
+
+    pub fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4000..=0x4003 => self.pulse1.write_register(addr - 0x4000, data),
+            0x4004..=0x4007 => self.pulse2.write_register(addr - 0x4004, data),
+            0x4008..=0x400B => self.triangle.write_register(addr - 0x4008, data),
+            0x400C..=0x400F => self.noise.write_register(addr - 0x400C, data),
+            0x4010..=0x4013 => {
+                self.dmc.write_register(addr - 0x4010, data);
+
+                // clearing the IRQ enabled flag also acknowledges the interrupt
+                if addr == 0x4010 && !self.dmc.irq_enabled {
+                    self.irq().borrow_mut().acknowledge(IrqSource::DMC);
+                }
+            }
+            0x4015 => { // ---D NT21
+                self.pulse1.set_enabled(test_bit!(data, 0));
+                self.pulse2.set_enabled(test_bit!(data, 1));
+                self.triangle.set_enabled(test_bit!(data, 2));
+                self.noise.set_enabled(test_bit!(data, 3));
+                self.dmc.set_enabled(test_bit!(data, 4));
+                self.irq().borrow_mut().acknowledge(IrqSource::DMC);
+            }
+            0x4017 => { // MI-- ----
+                self.five_step_mode = test_bit!(data, 7);
+                self.irq_inhibit = test_bit!(data, 6);
+
+                if self.irq_inhibit {
+                    self.irq().borrow_mut().acknowledge(IrqSource::FrameCounter);
+                }
+
+                // the sequencer is restarted; in 5-step mode all units get clocked immediately
+                self.frame_cycle = 0;
+                if self.five_step_mode {
+                    self.clock_quarter_frame();
+                    self.clock_half_frame();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Savable for APU {
+    fn save_state(&self, state: &mut Vec<u8>) {
+        self.pulse1.save_state(state);
+        self.pulse2.save_state(state);
+        self.triangle.save_state(state);
+        self.noise.save_state(state);
+        self.dmc.save_state(state);
+
+        state.write_u8(self.five_step_mode as u8).expect("Unable to save u8");
+        state.write_u8(self.irq_inhibit as u8).expect("Unable to save u8");
+        state.write_u32::<LittleEndian>(self.frame_cycle).expect("Unable to save u32");
+        state.write_u8(self.odd_cycle as u8).expect("Unable to save u8");
+    }
+
+    fn load_state(&mut self, state: &mut Cursor<Vec<u8>>) -> io::Result<()> {
+        self.pulse1.load_state(state)?;
+        self.pulse2.load_state(state)?;
+        self.triangle.load_state(state)?;
+        self.noise.load_state(state)?;
+        self.dmc.load_state(state)?;
+
+        self.five_step_mode = state.read_u8()? != 0;
+        self.irq_inhibit = state.read_u8()? != 0;
+        self.frame_cycle = state.read_u32::<LittleEndian>()?;
+        self.odd_cycle = state.read_u8()? != 0;
+
+        self.samples.clear();
+        self.stall_cycles = 0;
+
+        Ok(())
+    }
+}
@@ -1,12 +1,14 @@
 use std::cell::RefCell;
 use std::rc::{Rc, Weak};
-use std::io::Cursor;
+use std::io::{self, Cursor};
 
 use byteorder::{ReadBytesExt, WriteBytesExt};
 
 use crate::ppu::PPU;
+use crate::apu::APU;
 use crate::cartridge::Cartridge;
 use crate::joypad::Joypad;
+use crate::debugger::MemoryAccess;
 
 use crate::io::IO;
 use crate::savable::Savable;
@@ -47,29 +49,41 @@ const IO_REGS_COUNT: usize = 0x20;
 pub struct Bus {
     cart: Weak<RefCell<Cartridge>>,
     ppu: Weak<RefCell<PPU>>,
+    apu: Weak<RefCell<APU>>,
     joypad: Weak<RefCell<Joypad>>,
 
     ram: Box<[u8; RAM_SIZE]>,
     io_regs: Box<[u8; IO_REGS_COUNT]>,
 
     pub init_dma: bool,
-    pub dma_start_addr: u16
+    pub dma_start_addr: u16,
+
+    // set by the emulator while a read/write breakpoint is armed
+    pub(crate) watch_accesses: bool,
+    pub(crate) accesses: Vec<MemoryAccess>,
+    fetching: bool /* the CPU is reading an opcode or operand through PC */
 }
 
 impl Bus {
     pub fn new(weak_cart: Weak<RefCell<Cartridge>>,
                weak_ppu: Weak<RefCell<PPU>>,
+               weak_apu: Weak<RefCell<APU>>,
                weak_joypad: Weak<RefCell<Joypad>>) -> Self {
         Bus {
             cart: weak_cart.clone(),
             ppu: weak_ppu.clone(),
+            apu: weak_apu.clone(),
             joypad: weak_joypad.clone(),
 
             ram: box_array![0; RAM_SIZE],
             io_regs: box_array![0; IO_REGS_COUNT],
 
             init_dma: false,
-            dma_start_addr: 0
+            dma_start_addr: 0,
+
+            watch_accesses: false,
+            accesses: Vec::new(),
+            fetching: false
         }
     }
 
@@ -81,6 +95,10 @@ impl Bus {
         self.ppu.upgrade().expect("PPU lost for bus")
     }
 
+    pub fn apu(&self) -> Rc<RefCell<APU>> {
+        self.apu.upgrade().expect("APU lost for bus")
+    }
+
     pub fn joypad(&self) -> Rc<RefCell<Joypad>> {
         self.joypad.upgrade().expect("Joypad lost for bus")
     }
@@ -89,6 +107,43 @@ impl Bus {
         self.ram = box_array![0; RAM_SIZE];
         self.io_regs = box_array![0; IO_REGS_COUNT];
     }
+
+    // Returns what read_byte would, without clearing vblank, advancing $2007, acknowledging interrupts or shifting the joypad
+    pub fn peek_byte(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x1FFF => self.ram[mirror!(0x0000, addr, RAM_SIZE)],
+            0x2000..=0x3FFF => self.ppu().borrow().peek_register(mirror!(0x2000, addr, PPU_REG_COUNT)),
+            0x4000..=0x401F => {
+                match addr {
+                    0x4015 => self.apu().borrow().peek_status(),
+                    0x4016 => self.joypad().borrow().peek(),
+                    _ => self.io_regs[(addr - 0x4000) as usize]
+                }
+            }
+            0x4020..=0xFFFF => self.cart().borrow().peek_byte(addr)
+        }
+    }
+
+    pub fn peek_word(&self, addr: u16) -> u16 {
+        let lo = self.peek_byte(addr) as u16;
+        let hi = self.peek_byte(addr.wrapping_add(1)) as u16;
+        (hi << 8) | lo
+    }
+
+    // Same as read_byte/read_word, but the accesses are marked as instruction fetches for the debugger
+    pub(crate) fn fetch_byte(&mut self, addr: u16) -> u8 {
+        self.fetching = true;
+        let data = self.read_byte(addr);
+        self.fetching = false;
+        data
+    }
+
+    pub(crate) fn fetch_word(&mut self, addr: u16) -> u16 {
+        self.fetching = true;
+        let data = self.read_word(addr);
+        self.fetching = false;
+        data
+    }
 }
 
 /*
@@ -102,18 +157,25 @@ CPU Memory Map (16bit buswidth, 0-FFFFh)
 */
 impl IO for Bus {
     fn read_byte(&mut self, addr: u16) -> u8 {
-        match addr {
+        let data = match addr {
             0x0000..=0x1FFF => self.ram[mirror!(0x0000, addr, RAM_SIZE)],
             0x2000..=0x3FFF => self.ppu().borrow_mut().read_register(mirror!(0x2000, addr, PPU_REG_COUNT)),
             0x4000..=0x401F => {
-                if addr == 0x4016 {
-                    return self.joypad().borrow_mut().read()
+                match addr {
+                    0x4015 => self.apu().borrow_mut().read_status(),
+                    0x4016 => self.joypad().borrow_mut().read(),
+                    _ => self.io_regs[(addr - 0x4000) as usize]
                 }
-                return self.io_regs[(addr - 0x4000) as usize]
             },
             0x4020..=0xFFFF => self.cart().borrow_mut().read_byte(addr),
             _ => panic!("Address out of bounds: {:04X}", addr)
+        };
+
+        if self.watch_accesses {
+            self.accesses.push(MemoryAccess { addr: addr, data: data, write: false, fetch: self.fetching });
         }
+
+        data
     }
 
     fn read_word(&mut self, addr: u16) -> u16 {
@@ -123,6 +185,10 @@ impl IO for Bus {
     }
 
     fn write_byte(&mut self, addr: u16, data: u8) {
+        if self.watch_accesses {
+            self.accesses.push(MemoryAccess { addr: addr, data: data, write: true, fetch: false });
+        }
+
         match addr {
             0x0000..=0x1FFF => { self.ram[mirror!(0x0000, addr, RAM_SIZE)] = data; }
             0x2000..=0x3FFF => { self.ppu().borrow_mut().write_register(mirror!(0x2000, addr, PPU_REG_COUNT), data); }
@@ -140,6 +206,11 @@ impl IO for Bus {
                     return;
                 }
 
+                // APU
+                if addr <= 0x4013 || addr == 0x4015 || addr == 0x4017 {
+                    self.apu().borrow_mut().write_register(addr, data);
+                }
+
                 self.io_regs[(addr - 0x4000) as usize] = data;
             }
             0x4020..=0xFFFF => { self.cart().borrow_mut().write_byte(addr, data); }
@@ -163,12 +234,14 @@ impl Savable for Bus {
         }
     }
 
-    fn load_state(&mut self, state: &mut Cursor<Vec<u8>>) {
+    fn load_state(&mut self, state: &mut Cursor<Vec<u8>>) -> io::Result<()> {
         for i in 0..RAM_SIZE {
-            self.ram[i] = state.read_u8().expect("Unable to load u8");
+            self.ram[i] = state.read_u8()?;
         }
         for i in 0..IO_REGS_COUNT {
-            self.io_regs[i] = state.read_u8().expect("Unable to load u8");
+            self.io_regs[i] = state.read_u8()?;
         }
+
+        Ok(())
     }
 }
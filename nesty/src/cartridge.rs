@@ -1,104 +1,258 @@
-use std::io::Cursor;
+use std::cell::RefCell;
+use std::rc::{Rc, Weak};
+use std::io::{self, Cursor};
+use std::fmt;
 
 use byteorder::{ReadBytesExt, WriteBytesExt};
 
 use crate::io::IO;
+use crate::header::{RomHeader, ConsoleType, HEADER_SIZE, TRAINER_SIZE};
+use crate::irq::{IrqLine, IrqSource};
 use crate::savable::Savable;
-use crate::mapper::{Mirroring, Mapper, PRG_ROM_BANK_SIZE, CHR_ROM_BANK_SIZE};
+use crate::hash::fnv1a;
+use crate::mapper::{Mirroring, NametableTarget, Mapper, PRG_ROM_BANK_SIZE, CHR_ROM_BANK_SIZE};
 
 use crate::mapper::mapper0::Mapper0;
 use crate::mapper::mapper1::Mapper1;
+use crate::mapper::mapper2::Mapper2;
+use crate::mapper::mapper3::Mapper3;
+use crate::mapper::mapper4::Mapper4;
+use crate::mapper::mapper7::Mapper7;
 
 use crate::startup_rom::STARTUP_ROM;
 
-use crate::{test_bit, box_array};
+use crate::box_array;
 
 const INES_IDENT: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
 
 const EXPANSION_AREA_SIZE: usize = 0x1FE0;
 
+/// Reasons why a ROM image can't be loaded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RomError {
+    BadMagic,
+    TruncatedHeader,
+    TruncatedPrgRom { expected: usize, found: usize },
+    TruncatedChrRom { expected: usize, found: usize },
+    UnsupportedMapper { mapper: u16, submapper: u8 },
+    UnsupportedFormat(&'static str)
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RomError::BadMagic => write!(f, "File is not in iNES file format"),
+            RomError::TruncatedHeader => write!(f, "File is too small to contain an iNES header"),
+            RomError::TruncatedPrgRom { expected, found } => {
+                write!(f, "PRG ROM is truncated: expected {} bytes but found {}", expected, found)
+            }
+            RomError::TruncatedChrRom { expected, found } => {
+                write!(f, "CHR ROM is truncated: expected {} bytes but found {}", expected, found)
+            }
+            RomError::UnsupportedMapper { mapper, submapper } => {
+                write!(f, "Mapper {} (submapper {}) is not supported yet sorry my man", mapper, submapper)
+            }
+            RomError::UnsupportedFormat(reason) => write!(f, "Unsupported ROM format: {}", reason)
+        }
+    }
+}
+
+impl std::error::Error for RomError {}
+
 pub struct Cartridge {
+    irq: Weak<RefCell<IrqLine>>, /* for mappers with interrupt counters */
+
+    header: RomHeader,
+    hash: u64, /* of the whole ROM file, to tell games apart */
     mapper: Box<dyn Mapper>,
     expansion_area: Box<[u8; EXPANSION_AREA_SIZE]>
 }
 
 impl Cartridge {
-    pub fn new() -> Self {
-        let (mapper, _) = Cartridge::parse_metadata(STARTUP_ROM.to_vec());
+    pub fn new(irq: Weak<RefCell<IrqLine>>) -> Self {
+        let (mapper, header) = Cartridge::parse_metadata(STARTUP_ROM.to_vec(), irq.clone())
+            .expect("Startup ROM is invalid");
 
         Cartridge {
-            mapper: mapper.unwrap(),
+            irq: irq.clone(),
+
+            header: header,
+            hash: fnv1a(&STARTUP_ROM),
+            mapper: mapper,
             expansion_area: box_array![0; EXPANSION_AREA_SIZE]
         }
     }
 
-    pub fn load(&mut self, rom: Vec<u8>) -> Result<String, String> {
-        let (mapper, mapno) = Cartridge::parse_metadata(rom);
+    // Nothing is touched unless the whole image is valid, so the current game keeps running on failure
+    pub fn load(&mut self, rom: Vec<u8>) -> Result<(), RomError> {
+        let hash = fnv1a(&rom);
+        let (mapper, header) = Cartridge::parse_metadata(rom, self.irq.clone())?;
 
-        if mapper.is_none() {
-            return Err(format!("Mapper {} is not supported yet sorry my man", mapno))
-        }
+        self.header = header;
+        self.hash = hash;
+        self.mapper = mapper;
+
+        // the previous cartridge's interrupt goes away together with it
+        self.irq().borrow_mut().acknowledge(IrqSource::Mapper);
 
-        self.mapper = mapper.unwrap();
+        Ok(())
+    }
 
-        Ok("".to_string())
+    fn irq(&self) -> Rc<RefCell<IrqLine>> {
+        self.irq.upgrade().expect("IRQ line lost for cartridge")
     }
 
     pub fn reset(&mut self) {
         self.mapper.reset();
     }
 
+    // Same as read_byte, minus the side effects
+    pub fn peek_byte(&self, addr: u16) -> u8 {
+        match addr {
+            /* Accessed by PPU */
+            0x0000..=0x1FFF => self.mapper.ppu_peek_byte(addr),
+
+            /* Accessed by CPU */
+            0x4020..=0x5FFF => self.expansion_area[(addr - 0x4020) as usize],
+            0x6000..=0xFFFF => self.mapper.cpu_peek_byte(addr),
+            _ => panic!("Address out of bounds: {:04X}", addr)
+        }
+    }
+
+    pub fn prg_rom(&self) -> &[u8] {
+        self.mapper.prg_rom()
+    }
+
+    pub fn header(&self) -> &RomHeader {
+        &self.header
+    }
+
+    pub fn rom_hash(&self) -> u64 {
+        self.hash
+    }
+
+    /* The PRG RAM, if the cartridge keeps it alive with a battery (the header says so) */
+    pub fn battery_ram(&self) -> Option<&[u8]> {
+        if self.header.battery {
+            self.mapper.sram()
+        } else {
+            None
+        }
+    }
+
+    // Only takes data of exactly the right size, so a .sav of some other game can't end up half loaded
+    pub fn load_battery_ram(&mut self, data: &[u8]) -> bool {
+        if !self.header.battery {
+            return false;
+        }
+
+        match self.mapper.sram_mut() {
+            Some(sram) if sram.len() == data.len() => {
+                sram.copy_from_slice(data);
+                true
+            }
+            _ => false
+        }
+    }
+
     pub fn mirroring(&self) -> Mirroring {
         self.mapper.mirroring()
     }
 
-    fn parse_metadata(rom: Vec<u8>) -> (Option<Box<dyn Mapper>>, u8) {
-        if &rom[0..4] != INES_IDENT {
-            panic!("File is not in iNES file format");
+    pub fn nametable_target(&self, addr: u16) -> NametableTarget {
+        self.mapper.nametable_target(addr)
+    }
+
+    pub fn nametable_read_byte(&self, addr: u16) -> u8 {
+        self.mapper.nametable_read_byte(addr)
+    }
+
+    pub fn nametable_write_byte(&mut self, addr: u16, data: u8) {
+        self.mapper.nametable_write_byte(addr, data);
+    }
+
+    pub fn notify_ppu_address(&mut self, addr: u16) {
+        self.mapper.notify_ppu_address(addr);
+    }
+
+    fn parse_metadata(rom: Vec<u8>, irq: Weak<RefCell<IrqLine>>) -> Result<(Box<dyn Mapper>, RomHeader), RomError> {
+        if rom.len() < INES_IDENT.len() || rom[0..4] != INES_IDENT {
+            return Err(RomError::BadMagic);
         }
 
-        if ((rom[7] >> 2) & 0b11) != 0 {
-            panic!("NES2.0 format is not supported");
+        if rom.len() < HEADER_SIZE {
+            return Err(RomError::TruncatedHeader);
         }
 
-        let mirroring_type = if test_bit!(rom[6], 0) {
+        let mut header_bytes = [0; HEADER_SIZE];
+        header_bytes.copy_from_slice(&rom[0..HEADER_SIZE]);
+
+        let header = RomHeader::parse(&header_bytes);
+
+        // bank sizes are powers of two; usize::is_multiple_of would need Rust 1.87
+        if header.prg_rom_size < PRG_ROM_BANK_SIZE || header.prg_rom_size & (PRG_ROM_BANK_SIZE - 1) != 0 {
+            return Err(RomError::UnsupportedFormat("PRG ROM size must be a multiple of 16 KB"));
+        }
+
+        if header.chr_rom_size & (CHR_ROM_BANK_SIZE - 1) != 0 {
+            return Err(RomError::UnsupportedFormat("CHR ROM size must be a multiple of 8 KB"));
+        }
+
+        if let ConsoleType::Extended(_) = header.console_type {
+            return Err(RomError::UnsupportedFormat("extended console types are not emulated"));
+        }
+
+        let mirroring_type = if header.four_screen {
+            Mirroring::FourScreen
+        } else if header.vertical_mirroring {
             Mirroring::Vertical
         } else {
             Mirroring::Horizontial
         };
 
-        let prg_rom_banks = rom[4] as usize;
-        let chr_rom_banks = rom[5] as usize;
+        let prg_rom_size = header.prg_rom_size;
+        let chr_rom_size = header.chr_rom_size;
+
+        let prg_rom_banks = prg_rom_size / PRG_ROM_BANK_SIZE;
+        let chr_rom_banks = chr_rom_size / CHR_ROM_BANK_SIZE;
 
         let use_chr_ram = chr_rom_banks == 0;
 
-        let prg_rom_size = prg_rom_banks * PRG_ROM_BANK_SIZE;
-        let chr_rom_size = if use_chr_ram {
-            CHR_ROM_BANK_SIZE
-        } else {
-            chr_rom_banks * CHR_ROM_BANK_SIZE
-        };
+        let prg_rom_start = HEADER_SIZE + if header.trainer { TRAINER_SIZE } else { 0 };
 
-        let trainer_exists = test_bit!(rom[6], 2);
+        let available = rom.len().saturating_sub(prg_rom_start);
+        if available < prg_rom_size {
+            return Err(RomError::TruncatedPrgRom { expected: prg_rom_size, found: available });
+        }
 
-        let prg_rom_start = 0x10 + if trainer_exists { 512 } else { 0 };
         let chr_rom_start = prg_rom_start + prg_rom_size;
 
+        let available = rom.len() - chr_rom_start;
+        if available < chr_rom_size {
+            return Err(RomError::TruncatedChrRom { expected: chr_rom_size, found: available });
+        }
+
         let prg_rom = rom[prg_rom_start..(prg_rom_start + prg_rom_size)].to_vec();
         let chr_rom = if use_chr_ram {
-            vec![0; chr_rom_size]
+            vec![0; CHR_ROM_BANK_SIZE]
         } else {
             rom[chr_rom_start..(chr_rom_start + chr_rom_size)].to_vec()
         };
 
-        let mapper_type = (rom[6] >> 4) | (rom[7] & 0b11110000);
-        let mapper: Option<Box<dyn Mapper>> = match mapper_type {
+        let mapper: Option<Box<dyn Mapper>> = match header.mapper {
             0 => Some(Box::new(Mapper0::new(mirroring_type, prg_rom_size, prg_rom, chr_rom))),
             1 => Some(Box::new(Mapper1::new(mirroring_type, prg_rom_banks, chr_rom_banks, prg_rom, chr_rom))),
+            2 => Some(Box::new(Mapper2::new(mirroring_type, prg_rom_banks, chr_rom_banks, prg_rom, chr_rom))),
+            3 => Some(Box::new(Mapper3::new(mirroring_type, prg_rom_size, chr_rom_banks, prg_rom, chr_rom))),
+            4 => Some(Box::new(Mapper4::new(mirroring_type, prg_rom_banks, chr_rom_banks, prg_rom, chr_rom, irq))),
+            7 => Some(Box::new(Mapper7::new(prg_rom_banks, chr_rom_banks, prg_rom, chr_rom))),
             _ => None
         };
 
-        (mapper, mapper_type)
+        match mapper {
+            Some(mapper) => Ok((mapper, header)),
+            None => Err(RomError::UnsupportedMapper { mapper: header.mapper, submapper: header.submapper })
+        }
     }
 }
 
@@ -153,10 +307,12 @@ impl Savable for Cartridge {
         }
     }
 
-    fn load_state(&mut self, state: &mut Cursor<Vec<u8>>) {
-        self.mapper.load_state(state);
+    fn load_state(&mut self, state: &mut Cursor<Vec<u8>>) -> io::Result<()> {
+        self.mapper.load_state(state)?;
         for i in 0..EXPANSION_AREA_SIZE {
-            self.expansion_area[i] = state.read_u8().expect("Unable to load u8");
+            self.expansion_area[i] = state.read_u8()?;
         }
+
+        Ok(())
     }
 }
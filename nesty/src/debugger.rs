@@ -0,0 +1,451 @@
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+use crate::bus::Bus;
+use crate::m6502::M6502;
+use crate::ppu::PPU;
+
+const JSR: u8 = 0x20;
+const RTI: u8 = 0x40;
+const RTS: u8 = 0x60;
+
+pub const PPU_REGISTER_NAMES: [&str; 8] = [
+    "PPUCTRL", "PPUMASK", "PPUSTATUS", "OAMADDR", "OAMDATA", "PPUSCROLL", "PPUADDR", "PPUDATA"
+];
+
+/// Why `Emulator::tick`/`Emulator::update` gave control back before the frame was over.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StopReason {
+    Breakpoint(usize), /* id of the breakpoint that fired */
+    Step,              /* step, step over or step out finished */
+    Scanline(i32),
+    Frame,
+    Halted             /* the CPU ran into a KIL opcode */
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Register {
+    A,
+    X,
+    Y,
+    P,
+    SP,
+    PC
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual
+}
+
+impl Comparison {
+    fn compare(&self, lhs: u16, rhs: u16) -> bool {
+        match self {
+            Comparison::Equal => lhs == rhs,
+            Comparison::NotEqual => lhs != rhs,
+            Comparison::Less => lhs < rhs,
+            Comparison::LessOrEqual => lhs <= rhs,
+            Comparison::Greater => lhs > rhs,
+            Comparison::GreaterOrEqual => lhs >= rhs
+        }
+    }
+}
+
+/// Extra test a breakpoint has to pass before it fires.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Condition {
+    Register { register: Register, comparison: Comparison, value: u16 },
+    Memory { addr: u16, comparison: Comparison, value: u8 }
+}
+
+impl Condition {
+    // Memory is peeked, so checking a condition never disturbs the machine
+    pub fn holds(&self, cpu: &M6502, bus: &Bus) -> bool {
+        match *self {
+            Condition::Register { register, comparison, value } => {
+                let current = match register {
+                    Register::A => cpu.a() as u16,
+                    Register::X => cpu.x() as u16,
+                    Register::Y => cpu.y() as u16,
+                    Register::P => cpu.p() as u16,
+                    Register::SP => cpu.sp() as u16,
+                    Register::PC => cpu.pc()
+                };
+                comparison.compare(current, value)
+            }
+            Condition::Memory { addr, comparison, value } => {
+                comparison.compare(bus.peek_byte(addr) as u16, value as u16)
+            }
+        }
+    }
+}
+
+/*
+Conditions as typed by a person or a script: REG OP VALUE or [ADDR] OP VALUE, e.g.
+
+    X!=0
+    [00FF]<$10
+
+Registers are A, X, Y, P, SP and PC, operators ==, !=, <, <=, > and >=, numbers are hex.
+*/
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        const OPERATORS: [(&str, Comparison); 6] = [
+            ("==", Comparison::Equal),
+            ("!=", Comparison::NotEqual),
+            ("<=", Comparison::LessOrEqual),
+            (">=", Comparison::GreaterOrEqual),
+            ("<", Comparison::Less),
+            (">", Comparison::Greater)
+        ];
+
+        // the leftmost operator wins, and the longer one when "<" and "<=" start at the same place
+        let (i, operator, comparison) = OPERATORS
+            .iter()
+            .filter_map(|&(operator, comparison)| text.find(operator).map(|i| (i, operator, comparison)))
+            .min_by_key(|&(i, operator, _)| (i, std::cmp::Reverse(operator.len())))
+            .ok_or_else(|| format!("No comparison in condition '{}'", text))?;
+
+        let lhs = text[..i].trim().to_uppercase();
+        let rhs = text[(i + operator.len())..].trim();
+
+        if let Some(addr) = lhs.strip_prefix('[').and_then(|lhs| lhs.strip_suffix(']')) {
+            return Ok(Condition::Memory { addr: parse_hex(addr)?, comparison: comparison, value: parse_hex(rhs)? });
+        }
+
+        let register = match lhs.as_str() {
+            "A" => Register::A,
+            "X" => Register::X,
+            "Y" => Register::Y,
+            "P" => Register::P,
+            "SP" => Register::SP,
+            "PC" => Register::PC,
+            _ => return Err(format!("Unknown register '{}'", lhs))
+        };
+
+        Ok(Condition::Register { register: register, comparison: comparison, value: parse_hex(rhs)? })
+    }
+}
+
+/// Parses a hex number, with or without a `$` or `0x` prefix.
+pub fn parse_hex<T: TryFrom<u32>>(text: &str) -> Result<T, String> {
+    let digits = text.trim_start_matches('$').trim_start_matches("0x");
+
+    u32::from_str_radix(digits, 16)
+        .ok()
+        .and_then(|value| T::try_from(value).ok())
+        .ok_or_else(|| format!("Bad hex number '{}'", text))
+}
+
+/// Parses an address range written as START-END, or a single address.
+pub fn parse_range(text: &str) -> Result<(u16, u16), String> {
+    match text.split_once('-') {
+        Some((start, end)) => Ok((parse_hex(start)?, parse_hex(end)?)),
+        None => {
+            let addr = parse_hex(text)?;
+            Ok((addr, addr))
+        }
+    }
+}
+
+/// Parses a PPU register given as 0-7, $2000-$2007 (or any mirror) or by name (PPUCTRL, PPUSTATUS, ...).
+pub fn parse_ppu_register(text: &str) -> Result<u8, String> {
+    let upper = text.to_uppercase();
+
+    if let Some(i) = PPU_REGISTER_NAMES.iter().position(|&name| name == upper) {
+        return Ok(i as u8);
+    }
+
+    match parse_hex::<u16>(text)? {
+        register @ 0..=7 => Ok(register as u8),
+        addr @ 0x2000..=0x3FFF => Ok((addr & 0x7) as u8),
+        _ => Err(format!("'{}' is not a PPU register", text))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BreakpointKind {
+    Execute(u16),                        /* before the instruction at this address runs */
+    Read { start: u16, end: u16 },       /* after an instruction read data (not its opcode or operands) from start..=end */
+    Write { start: u16, end: u16 },      /* after an instruction wrote to start..=end */
+    PpuRegisterRead(u8),                 /* 0-7 for $2000-$2007 and their mirrors */
+    PpuRegisterWrite(u8)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Breakpoint {
+    pub id: usize,
+    pub kind: BreakpointKind,
+    pub condition: Option<Condition>,
+    pub enabled: bool
+}
+
+/// A CPU bus access recorded while the debugger watches memory.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct MemoryAccess {
+    pub addr: u16,
+    pub data: u8,
+    pub write: bool,
+    pub fetch: bool /* an opcode or operand byte read through PC */
+}
+
+impl BreakpointKind {
+    fn matches_access(&self, access: &MemoryAccess) -> bool {
+        let ppu_register = match access.addr {
+            0x2000..=0x3FFF => Some((access.addr & 0x7) as u8),
+            _ => None
+        };
+
+        match *self {
+            BreakpointKind::Execute(_) => false,
+            BreakpointKind::Read { start, end } => !access.write && !access.fetch && (start..=end).contains(&access.addr),
+            BreakpointKind::Write { start, end } => access.write && (start..=end).contains(&access.addr),
+            BreakpointKind::PpuRegisterRead(register) => !access.write && ppu_register == Some(register & 0x7),
+            BreakpointKind::PpuRegisterWrite(register) => access.write && ppu_register == Some(register & 0x7)
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum RunMode {
+    Run,
+    StepInto,
+    StepOver { return_addr: u16, sp: u8 },
+    StepOut { sp: u8 },
+    RunToScanline(i32),
+    RunToNextFrame
+}
+
+/*
+Breakpoints and stepping shared by every frontend.
+The emulator asks the debugger before and after each instruction whether it should stop;
+with no breakpoints and no step in progress, it stays out of the way.
+*/
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    next_id: usize,
+
+    mode: RunMode,
+
+    // PC of the execute breakpoint we last stopped at, so resuming doesn't hit it again straight away
+    resume_pc: Option<u16>,
+
+    // opcode of the instruction being executed, and the scanline the PPU was on after the previous tick
+    opcode: u8,
+    scanline: i32,
+
+    was_halted: bool
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Debugger::new()
+    }
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger {
+            breakpoints: Vec::new(),
+            next_id: 0,
+
+            mode: RunMode::Run,
+
+            resume_pc: None,
+
+            opcode: 0,
+            scanline: 0,
+
+            was_halted: false
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, kind: BreakpointKind, condition: Option<Condition>) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.breakpoints.push(Breakpoint {
+            id: id,
+            kind: kind,
+            condition: condition,
+            enabled: true
+        });
+
+        id
+    }
+
+    pub fn remove_breakpoint(&mut self, id: usize) -> bool {
+        let count = self.breakpoints.len();
+        self.breakpoints.retain(|bp| bp.id != id);
+        self.breakpoints.len() != count
+    }
+
+    pub fn set_breakpoint_enabled(&mut self, id: usize, enabled: bool) -> bool {
+        match self.breakpoints.iter_mut().find(|bp| bp.id == id) {
+            Some(bp) => {
+                bp.enabled = enabled;
+                true
+            }
+            None => false
+        }
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    /* Cancels any step in progress; breakpoints stay armed */
+    pub fn resume(&mut self) {
+        self.mode = RunMode::Run;
+    }
+
+    pub fn step_into(&mut self) {
+        self.mode = RunMode::StepInto;
+    }
+
+    // Runs a JSR until it returns; any other instruction is stepped into
+    pub fn step_over(&mut self, cpu: &M6502, bus: &Bus) {
+        self.mode = if bus.peek_byte(cpu.pc()) == JSR {
+            RunMode::StepOver { return_addr: cpu.pc().wrapping_add(3), sp: cpu.sp() }
+        } else {
+            RunMode::StepInto
+        };
+    }
+
+    // Runs until the current subroutine (or interrupt handler) returns to its caller
+    pub fn step_out(&mut self, cpu: &M6502) {
+        self.mode = RunMode::StepOut { sp: cpu.sp() };
+    }
+
+    // Stops as soon as the PPU enters the scanline (-1 is the pre-render line); a full frame later if it's already on it
+    pub fn run_to_scanline(&mut self, ppu: &PPU, scanline: i32) {
+        self.scanline = ppu.scanline();
+        self.mode = RunMode::RunToScanline(scanline);
+    }
+
+    /* Stops when the PPU wraps around to the pre-render scanline */
+    pub fn run_to_next_frame(&mut self, ppu: &PPU) {
+        self.scanline = ppu.scanline();
+        self.mode = RunMode::RunToNextFrame;
+    }
+
+    pub(crate) fn is_active(&self) -> bool {
+        self.mode != RunMode::Run || !self.breakpoints.is_empty()
+    }
+
+    pub(crate) fn watches_memory(&self) -> bool {
+        self.breakpoints.iter().any(|bp| bp.enabled && !matches!(bp.kind, BreakpointKind::Execute(_)))
+    }
+
+    fn fires(bp: &Breakpoint, cpu: &M6502, bus: &Bus) -> bool {
+        // a match rather than Option::is_none_or, which needs Rust 1.82
+        bp.enabled && match bp.condition {
+            Some(condition) => condition.holds(cpu, bus),
+            None => true
+        }
+    }
+
+    // Called when the CPU is about to execute the instruction at PC
+    pub(crate) fn before_instruction(&mut self, cpu: &M6502, bus: &Bus) -> Option<StopReason> {
+        self.opcode = bus.peek_byte(cpu.pc());
+
+        if self.resume_pc.take() == Some(cpu.pc()) || cpu.halted() {
+            return None;
+        }
+
+        let pc = cpu.pc();
+        let hit = self.breakpoints
+            .iter()
+            .find(|bp| bp.kind == BreakpointKind::Execute(pc) && Debugger::fires(bp, cpu, bus));
+
+        if let Some(bp) = hit {
+            self.resume_pc = Some(pc);
+            self.mode = RunMode::Run;
+            return Some(StopReason::Breakpoint(bp.id));
+        }
+
+        None
+    }
+
+    // Checked on every tick, even with the debugger idle, so a jammed CPU is reported once instead of silently spinning
+    pub(crate) fn check_halted(&mut self, halted: bool) -> Option<StopReason> {
+        let was_halted = self.was_halted;
+        self.was_halted = halted;
+
+        if halted && !was_halted {
+            self.mode = RunMode::Run;
+            Some(StopReason::Halted)
+        } else {
+            None
+        }
+    }
+
+    // The CPU starts over after a reset, so running into a KIL again is reported again
+    pub(crate) fn forget_halt(&mut self) {
+        self.was_halted = false;
+    }
+
+    /*
+    Called at the end of every tick, once the PPU has caught up with the CPU.
+    executed is false for ticks spent on OAM DMA, which only count towards scanline and frame targets.
+    */
+    pub(crate) fn after_tick(&mut self, cpu: &M6502, bus: &Bus, accesses: &[MemoryAccess], scanline: i32, executed: bool) -> Option<StopReason> {
+        let prev_scanline = self.scanline;
+        self.scanline = scanline;
+
+        let reason = match self.mode {
+            RunMode::RunToScanline(target) if scanline == target && prev_scanline != target => Some(StopReason::Scanline(target)),
+            RunMode::RunToNextFrame if scanline < prev_scanline => Some(StopReason::Frame),
+            _ if executed => self.after_instruction(cpu, bus, accesses),
+            _ => None
+        };
+
+        if reason.is_some() {
+            self.mode = RunMode::Run;
+        }
+
+        reason
+    }
+
+    fn after_instruction(&mut self, cpu: &M6502, bus: &Bus, accesses: &[MemoryAccess]) -> Option<StopReason> {
+        let hit = self.breakpoints
+            .iter()
+            .find(|bp| accesses.iter().any(|access| bp.kind.matches_access(access)) && Debugger::fires(bp, cpu, bus));
+
+        if let Some(bp) = hit {
+            return Some(StopReason::Breakpoint(bp.id));
+        }
+
+        match self.mode {
+            RunMode::Run => None,
+            RunMode::StepInto => Some(StopReason::Step),
+            RunMode::StepOver { return_addr, sp } => {
+                if cpu.pc() == return_addr && cpu.sp() >= sp {
+                    Some(StopReason::Step)
+                } else {
+                    None
+                }
+            }
+            RunMode::StepOut { sp } => {
+                // nested calls and interrupts return with SP at or below where it was when we started
+                if (self.opcode == RTS || self.opcode == RTI) && cpu.sp() > sp {
+                    Some(StopReason::Step)
+                } else {
+                    None
+                }
+            }
+            RunMode::RunToScanline(_) | RunMode::RunToNextFrame => None
+        }
+    }
+}
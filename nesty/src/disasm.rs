@@ -0,0 +1,275 @@
+use std::fmt;
+
+use crate::bus::Bus;
+use crate::cartridge::Cartridge;
+use crate::m6502::AddressingMode;
+use crate::opcodes::{OPCODES, instruction_length};
+
+use crate::mapper::PRG_ROM_BANK_SIZE;
+
+const NMI_VECTOR: u16 = 0xFFFA;
+const RESET_VECTOR: u16 = 0xFFFC;
+const IRQ_VECTOR: u16 = 0xFFFE;
+
+/// One decoded 6502 instruction.
+#[derive(Clone, Debug)]
+pub struct Instruction {
+    pub address: u16,
+    pub bytes: Vec<u8>, /* opcode followed by the operand bytes */
+    pub mnemonic: &'static str,
+    pub mode: AddressingMode,
+    pub operand: u16, /* the operand as written; the target address for branches */
+    pub official: bool
+}
+
+impl Instruction {
+    pub fn opcode(&self) -> u8 {
+        self.bytes[0]
+    }
+
+    pub fn size(&self) -> u16 {
+        self.bytes.len() as u16
+    }
+
+    pub fn operand_text(&self) -> String {
+        let nn = self.operand as u8;
+        let nnnn = self.operand;
+
+        match self.mode {
+            AddressingMode::Implied => String::new(),
+            AddressingMode::Accumulator => "A".to_string(),
+            AddressingMode::Immediate => format!("#${:02X}", nn),
+            AddressingMode::ZeroPage => format!("${:02X}", nn),
+            AddressingMode::ZeroPageX => format!("${:02X},X", nn),
+            AddressingMode::ZeroPageY => format!("${:02X},Y", nn),
+            AddressingMode::Absolute | AddressingMode::Relative => format!("${:04X}", nnnn),
+            AddressingMode::AbsoluteX | AddressingMode::AbsoluteXEc => format!("${:04X},X", nnnn),
+            AddressingMode::AbsoluteY | AddressingMode::AbsoluteYEc => format!("${:04X},Y", nnnn),
+            AddressingMode::Indirect => format!("(${:04X})", nnnn),
+            AddressingMode::IndirectX => format!("(${:02X},X)", nn),
+            AddressingMode::IndirectY | AddressingMode::IndirectYEc => format!("(${:02X}),Y", nn)
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let operand = self.operand_text();
+
+        if operand.is_empty() {
+            write!(f, "{}", self.mnemonic)
+        } else {
+            write!(f, "{} {}", self.mnemonic, operand)
+        }
+    }
+}
+
+/// A disassembled instruction, labelled when one of the interrupt vectors points at it.
+#[derive(Clone, Debug)]
+pub struct Line {
+    pub label: Option<&'static str>,
+    pub instruction: Instruction
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(label) = self.label {
+            writeln!(f, "{}:", label)?;
+        }
+
+        let bytes = self.instruction.bytes
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect::<Vec<String>>()
+            .join(" ");
+
+        write!(f, "{:04X}  {:<8}  {}", self.instruction.address, bytes, self.instruction)
+    }
+}
+
+// Decodes from any byte source; bytes past the end of the source read as 0
+fn decode_with<F: Fn(u16) -> u8>(addr: u16, read: F) -> Instruction {
+    let opcode = read(addr);
+    let info = OPCODES[opcode as usize];
+    let length = instruction_length(info.mode);
+
+    let bytes: Vec<u8> = (0..length).map(|i| read(addr.wrapping_add(i))).collect();
+
+    let operand = match length {
+        2 => bytes[1] as u16,
+        3 => (bytes[1] as u16) | ((bytes[2] as u16) << 8),
+        _ => 0
+    };
+
+    let operand = if let AddressingMode::Relative = info.mode {
+        addr.wrapping_add(2).wrapping_add(operand as u8 as i8 as u16)
+    } else {
+        operand
+    };
+
+    Instruction {
+        address: addr,
+        bytes: bytes,
+        mnemonic: info.mnemonic,
+        mode: info.mode,
+        operand: operand,
+        official: info.official
+    }
+}
+
+/// Decodes the instruction at addr as the CPU currently sees it, without side effects.
+pub fn decode(bus: &Bus, addr: u16) -> Instruction {
+    decode_with(addr, |a| bus.peek_byte(a))
+}
+
+fn vector_labels<F: Fn(u16) -> u8>(read: F) -> [(u16, &'static str); 3] {
+    let vector = |addr: u16| (read(addr) as u16) | ((read(addr + 1) as u16) << 8);
+
+    [
+        (vector(NMI_VECTOR), "NMI"),
+        (vector(RESET_VECTOR), "RESET"),
+        (vector(IRQ_VECTOR), "IRQ")
+    ]
+}
+
+fn label_for(labels: &[(u16, &'static str); 3], addr: u16) -> Option<&'static str> {
+    labels.iter().find(|(target, _)| *target == addr).map(|(_, label)| *label)
+}
+
+/// Disassembles start..=end as mapped right now.
+pub fn disassemble(bus: &Bus, start: u16, end: u16) -> Vec<Line> {
+    let labels = vector_labels(|a| bus.peek_byte(a));
+
+    let mut lines = Vec::new();
+    let mut addr = start as u32;
+
+    while addr <= end as u32 {
+        let instruction = decode(bus, addr as u16);
+        addr += instruction.size() as u32;
+
+        lines.push(Line {
+            label: label_for(&labels, instruction.address),
+            instruction: instruction
+        });
+    }
+
+    lines
+}
+
+/*
+Disassembles a whole 16 KB PRG ROM bank straight from the ROM, whether it's banked in or not.
+base is the CPU address the bank is meant to be seen at (usually $8000 or $C000).
+The vectors are taken from the end of the last bank, which is where almost every mapper fixes them at power on.
+*/
+pub fn disassemble_prg_bank(cart: &Cartridge, bank: usize, base: u16) -> Vec<Line> {
+    let prg_rom = cart.prg_rom();
+    let banks = prg_rom.len() / PRG_ROM_BANK_SIZE;
+
+    if bank >= banks {
+        return Vec::new();
+    }
+
+    let data = &prg_rom[(bank * PRG_ROM_BANK_SIZE)..((bank + 1) * PRG_ROM_BANK_SIZE)];
+    let last = &prg_rom[((banks - 1) * PRG_ROM_BANK_SIZE)..];
+
+    let labels = vector_labels(|a| last[(a as usize) & (PRG_ROM_BANK_SIZE - 1)]);
+    let read = |a: u16| {
+        let offset = a.wrapping_sub(base) as usize;
+        if offset < data.len() { data[offset] } else { 0 }
+    };
+
+    let mut lines = Vec::new();
+    let mut offset = 0;
+
+    while offset < data.len() {
+        let instruction = decode_with(base.wrapping_add(offset as u16), read);
+        offset += instruction.size() as usize;
+
+        lines.push(Line {
+            label: label_for(&labels, instruction.address),
+            instruction: instruction
+        });
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Decodes `bytes` placed at addr, with zeros everywhere else
+    fn decode_bytes(addr: u16, bytes: &[u8]) -> Instruction {
+        decode_with(addr, |a| {
+            let offset = a.wrapping_sub(addr) as usize;
+            if offset < bytes.len() { bytes[offset] } else { 0 }
+        })
+    }
+
+    #[test]
+    fn addressing_modes() {
+        let cases: [(&[u8], AddressingMode, &str); 13] = [
+            (&[0xEA], AddressingMode::Implied, "NOP"),
+            (&[0x0A], AddressingMode::Accumulator, "ASL A"),
+            (&[0xA9, 0x12], AddressingMode::Immediate, "LDA #$12"),
+            (&[0xA5, 0x12], AddressingMode::ZeroPage, "LDA $12"),
+            (&[0xB5, 0x12], AddressingMode::ZeroPageX, "LDA $12,X"),
+            (&[0xB6, 0x12], AddressingMode::ZeroPageY, "LDX $12,Y"),
+            (&[0xAD, 0x34, 0x12], AddressingMode::Absolute, "LDA $1234"),
+            (&[0x9D, 0x34, 0x12], AddressingMode::AbsoluteX, "STA $1234,X"),
+            (&[0x99, 0x34, 0x12], AddressingMode::AbsoluteY, "STA $1234,Y"),
+            (&[0x6C, 0x34, 0x12], AddressingMode::Indirect, "JMP ($1234)"),
+            (&[0xA1, 0x12], AddressingMode::IndirectX, "LDA ($12,X)"),
+            (&[0x91, 0x12], AddressingMode::IndirectY, "STA ($12),Y"),
+            (&[0xD0, 0x10], AddressingMode::Relative, "BNE $8012")
+        ];
+
+        for (bytes, mode, text) in cases.iter() {
+            let instruction = decode_bytes(0x8000, bytes);
+
+            assert_eq!(instruction.mode, *mode, "{}", text);
+            assert_eq!(instruction.bytes, bytes.to_vec(), "{}", text);
+            assert_eq!(instruction.size() as usize, bytes.len(), "{}", text);
+            assert_eq!(instruction.to_string(), *text);
+            assert!(instruction.official, "{}", text);
+        }
+
+        // the extra cycle variants only differ in timing
+        assert_eq!(decode_bytes(0x8000, &[0xBD, 0x34, 0x12]).to_string(), "LDA $1234,X");
+        assert_eq!(decode_bytes(0x8000, &[0xB9, 0x34, 0x12]).to_string(), "LDA $1234,Y");
+        assert_eq!(decode_bytes(0x8000, &[0xB1, 0x12]).to_string(), "LDA ($12),Y");
+    }
+
+    #[test]
+    fn branch_targets() {
+        assert_eq!(decode_bytes(0xC000, &[0xD0, 0x05]).operand, 0xC007); /* forward */
+        assert_eq!(decode_bytes(0xC000, &[0xD0, 0xFB]).operand, 0xBFFD); /* backward */
+        assert_eq!(decode_bytes(0xC000, &[0xD0, 0xFE]).operand, 0xC000); /* onto itself */
+
+        // past either end of the address space
+        assert_eq!(decode_bytes(0xFFF0, &[0xF0, 0x20]).operand, 0x0012);
+        assert_eq!(decode_bytes(0x0000, &[0xF0, 0xFC]).operand, 0xFFFE);
+    }
+
+    #[test]
+    fn operands_wrap_around_the_address_space() {
+        let instruction = decode_with(0xFFFF, |a| match a {
+            0xFFFF => 0xAD,
+            0x0000 => 0x34,
+            0x0001 => 0x12,
+            _ => 0
+        });
+
+        assert_eq!(instruction.bytes, vec![0xAD, 0x34, 0x12]);
+        assert_eq!(instruction.operand, 0x1234);
+    }
+
+    #[test]
+    fn unofficial_opcodes() {
+        for bytes in [&[0x02][..], &[0x04, 0x12], &[0xA7, 0x12], &[0xEB, 0x12], &[0x1A]].iter() {
+            assert!(!decode_bytes(0x8000, bytes).official, "{:02X}", bytes[0]);
+        }
+
+        assert_eq!(decode_bytes(0x8000, &[0xA7, 0x12]).to_string(), "LAX $12");
+    }
+}
@@ -1,45 +1,61 @@
 use std::cell::{RefCell, RefMut};
 use std::rc::Rc;
-use std::io::Cursor;
+use std::io::{self, Cursor};
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 
 use crate::m6502::M6502;
 use crate::ppu::PPU;
+use crate::apu::APU;
 use crate::dma::DMA;
-use crate::cartridge::Cartridge;
+use crate::cartridge::{Cartridge, RomError};
 use crate::bus::Bus;
 use crate::joypad::Joypad;
+use crate::irq::IrqLine;
+use crate::debugger::{Debugger, StopReason};
 
 use crate::savable::Savable;
+use crate::state::{StateWriter, StateReader, StateError};
 
 pub const CYCLES_PER_FRAME: u64 = 29781; // how many CPU cycles required to render one frame
 
 pub struct Emulator {
+    irq: Rc<RefCell<IrqLine>>, /* shared by everything that can raise an interrupt */
     cart: Rc<RefCell<Cartridge>>,
     bus: Rc<RefCell<Bus>>, /* requirs access to cartridge, ppu, and joypad */
     cpu: Rc<RefCell<M6502>>, /* requires access to bus */
     ppu: Rc<RefCell<PPU>>, /* requires access to cartridge */
+    apu: Rc<RefCell<APU>>, /* requires access to cartridge and irq line */
     dma: Rc<RefCell<DMA>>, /* requires access to cpu, ppu, and bus */
     joypad: Rc<RefCell<Joypad>>,
 
-    prev_total_cycles: u64
+    debugger: Debugger,
+
+    prev_total_cycles: u64,
+    frame_cycles: u64 /* cycles already run of the current frame, kept when update() stops early */
 }
 
 impl Emulator {
     pub fn new() -> Self {
-        let cart_ref = Rc::new(RefCell::new(Cartridge::new()));
+        let irq_ref = Rc::new(RefCell::new(IrqLine::new()));
+        let weak_irq = Rc::downgrade(&irq_ref);
+
+        let cart_ref = Rc::new(RefCell::new(Cartridge::new(weak_irq.clone())));
         let weak_cart = Rc::downgrade(&cart_ref);
 
         let ppu_ref = Rc::new(RefCell::new(PPU::new(weak_cart.clone())));
         let weak_ppu = Rc::downgrade(&ppu_ref);
 
+        let apu_ref = Rc::new(RefCell::new(APU::new(weak_cart.clone(), weak_irq.clone())));
+        let weak_apu = Rc::downgrade(&apu_ref);
+
         let joypad_ref = Rc::new(RefCell::new(Joypad::new()));
         let weak_joypad = Rc::downgrade(&joypad_ref);
 
         let bus_ref = Rc::new(RefCell::new(Bus::new(
             weak_cart.clone(),
             weak_ppu.clone(),
+            weak_apu.clone(),
             weak_joypad.clone()
         )));
         let weak_bus = Rc::downgrade(&bus_ref);
@@ -54,17 +70,26 @@ impl Emulator {
         ));
 
         Emulator {
+            irq: irq_ref,
             cart: cart_ref,
             bus: bus_ref,
             cpu: cpu_ref,
             ppu: ppu_ref,
+            apu: apu_ref,
             dma: dma_ref,
             joypad: joypad_ref,
 
-            prev_total_cycles: 0
+            debugger: Debugger::new(),
+
+            prev_total_cycles: 0,
+            frame_cycles: 0
         }
     }
 
+    pub fn irq(&self) -> RefMut<'_, IrqLine> {
+        self.irq.borrow_mut()
+    }
+
     pub fn cart(&self) -> RefMut<'_, Cartridge> {
         self.cart.borrow_mut()
     }
@@ -81,6 +106,10 @@ impl Emulator {
         self.ppu.borrow_mut()
     }
 
+    pub fn apu(&self) -> RefMut<'_, APU> {
+        self.apu.borrow_mut()
+    }
+
     pub fn dma(&self) -> RefMut<'_, DMA> {
         self.dma.borrow_mut()
     }
@@ -89,31 +118,103 @@ impl Emulator {
         self.joypad.borrow_mut()
     }
 
-    pub fn load_rom(&mut self, rom: Vec<u8>) -> Result<String, String> {
+    pub fn debugger(&mut self) -> &mut Debugger {
+        &mut self.debugger
+    }
+
+    pub fn step_into(&mut self) {
+        self.debugger.step_into();
+    }
+
+    pub fn step_over(&mut self) {
+        self.debugger.step_over(&self.cpu.borrow(), &self.bus.borrow());
+    }
+
+    pub fn step_out(&mut self) {
+        self.debugger.step_out(&self.cpu.borrow());
+    }
+
+    pub fn run_to_scanline(&mut self, scanline: i32) {
+        self.debugger.run_to_scanline(&self.ppu.borrow(), scanline);
+    }
+
+    pub fn run_to_next_frame(&mut self) {
+        self.debugger.run_to_next_frame(&self.ppu.borrow());
+    }
+
+    pub fn load_rom(&mut self, rom: Vec<u8>) -> Result<(), RomError> {
         return self.cart().load(rom);
     }
 
+    /* Battery backed RAM, for keeping save games around between sessions; None if the cartridge has no battery */
+    pub fn battery_ram(&self) -> Option<Vec<u8>> {
+        self.cart().battery_ram().map(|sram| sram.to_vec())
+    }
+
+    // Call after load_rom and before running; returns false if the game has no battery or the size doesn't match
+    pub fn load_battery_ram(&mut self, data: &[u8]) -> bool {
+        self.cart().load_battery_ram(data)
+    }
+
+    pub fn rom_hash(&self) -> u64 {
+        self.cart().rom_hash()
+    }
+
     pub fn reset(&mut self) {
+        self.irq().reset();
         self.cart().reset();
         self.bus().reset();
         self.cpu().reset();
         self.ppu().reset();
+        self.apu().reset();
         self.joypad().reset();
+        self.debugger.forget_halt();
     }
 
-    pub fn tick(&mut self) -> u64 {
+    /* Pressing the reset button; unlike reset() (power cycling), RAM and the mapper's state survive */
+    pub fn soft_reset(&mut self) {
+        self.irq().reset();
+        self.cpu().soft_reset();
+        self.ppu().soft_reset();
+        self.apu().write_register(0x4015, 0); // silences every channel
+        self.debugger.forget_halt();
+    }
+
+    /*
+    Runs one instruction (or one step of an OAM DMA) and returns the CPU cycles it took.
+    When the debugger wants to stop, the reason comes back as well; an execute breakpoint stops before
+    its instruction runs, in which case no cycles pass. Calling tick again carries on from there.
+    */
+    pub fn tick(&mut self) -> (u64, Option<StopReason>) {
         if self.ppu().nmi {
             self.cpu().nmi();
             self.ppu().nmi = false;
+        } else if self.irq().active() {
+            // level triggered, so it's taken again and again until the source acknowledges it
+            self.cpu().irq();
         }
 
-        // TODO IRQ here
+        let debugging = self.debugger.is_active();
+        let executed = !self.dma().active;
 
-        if self.dma().active {
+        if !executed {
             // cpu is stalled during dma transfer
             self.dma().do_transfer();
         } else {
+            if debugging {
+                let reason = self.debugger.before_instruction(&self.cpu.borrow(), &self.bus.borrow());
+                if reason.is_some() {
+                    return (0, reason);
+                }
+            }
+
+            let watch = debugging && self.debugger.watches_memory();
+            self.bus().watch_accesses = watch;
+
             self.cpu().tick();
+            if watch {
+                self.bus().watch_accesses = false;
+            }
 
             if self.bus().init_dma {
                 self.dma().init_transfer(self.bus().dma_start_addr);
@@ -129,36 +230,133 @@ impl Emulator {
             self.ppu().tick();
             self.ppu().tick();
             self.ppu().tick();
+            self.apu().tick();
         }
 
-        cycles
+        // cpu is stalled while the dmc fetches sample bytes
+        let stall_cycles = self.apu().stall_cycles;
+        if stall_cycles > 0 {
+            self.cpu().total_cycles += stall_cycles;
+            self.apu().stall_cycles = 0;
+        }
+
+        let mut reason = None;
+
+        if executed {
+            let halted = self.cpu().halted();
+            reason = self.debugger.check_halted(halted);
+        }
+
+        if debugging && reason.is_none() {
+            let accesses = std::mem::take(&mut self.bus().accesses);
+            let scanline = self.ppu().scanline();
+
+            reason = self.debugger.after_tick(&self.cpu.borrow(), &self.bus.borrow(), &accesses, scanline, executed);
+        }
+
+        (cycles, reason)
     }
 
-    pub fn update(&mut self) {
-        let mut total: u64 = 0;
+    /* Runs until the end of the frame, or until the debugger stops it; the next call finishes the frame */
+    pub fn update(&mut self) -> Option<StopReason> {
+        while self.frame_cycles < CYCLES_PER_FRAME {
+            let (cycles, reason) = self.tick();
+            self.frame_cycles += cycles;
 
-        while total < CYCLES_PER_FRAME {
-            total += self.tick();
+            if reason.is_some() {
+                return reason;
+            }
         }
+
+        self.frame_cycles = 0;
+        None
     }
 }
 
-impl Savable for Emulator {
-    fn save_state(&self, state: &mut Vec<u8>) {
+impl Emulator {
+    /* Appends a save state of the whole console to state; see state.rs for the format */
+    pub fn save_state(&self, state: &mut Vec<u8>) {
+        let mapper = self.cart().header().mapper;
+        let mut writer = StateWriter::new(state, self.rom_hash(), mapper);
+
+        writer.chunk(b"IRQ ", &*self.irq());
+        writer.chunk(b"CART", &*self.cart());
+        writer.chunk(b"BUS ", &*self.bus());
+        writer.chunk(b"CPU ", &*self.cpu());
+        writer.chunk(b"PPU ", &*self.ppu());
+        writer.chunk(b"APU ", &*self.apu());
+        writer.chunk(b"JOYP", &*self.joypad());
+        writer.chunk_with(b"EMU ", |state| {
+            state.write_u64::<LittleEndian>(self.prev_total_cycles).expect("Unable to save u64");
+        });
+
+        writer.finish();
+    }
+
+    /*
+    Loads a state made by save_state, leaving the cursor right after it.
+    States of other games or format versions are rejected, and a state which turns out to be broken
+    half way through is undone, so on error the emulator carries on as if nothing happened.
+    */
+    pub fn load_state(&mut self, state: &mut Cursor<Vec<u8>>) -> Result<(), StateError> {
+        let mapper = self.cart().header().mapper;
+        let reader = StateReader::parse(state, self.rom_hash(), mapper)?;
+
+        let mut backup = Vec::new();
+        self.save_state(&mut backup);
+
+        if let Err(err) = self.load_chunks(&reader) {
+            let backup = StateReader::parse(&mut Cursor::new(backup), self.rom_hash(), mapper).expect("Unable to parse backup state");
+            self.load_chunks(&backup).expect("Unable to restore backup state");
+            return Err(err);
+        }
+
+        self.frame_cycles = 0;
+        Ok(())
+    }
+
+    /* Bare component data without the container, for in-memory snapshots which never outlive this emulator and ROM */
+    pub(crate) fn save_snapshot(&self, state: &mut Vec<u8>) {
+        self.irq().save_state(state);
         self.cart().save_state(state);
         self.bus().save_state(state);
         self.cpu().save_state(state);
         self.ppu().save_state(state);
+        self.apu().save_state(state);
         self.joypad().save_state(state);
         state.write_u64::<LittleEndian>(self.prev_total_cycles).expect("Unable to save u64");
     }
 
-    fn load_state(&mut self, state: &mut Cursor<Vec<u8>>) {
-        self.cart().load_state(state);
-        self.bus().load_state(state);
-        self.cpu().load_state(state);
-        self.ppu().load_state(state);
-        self.joypad().load_state(state);
-        self.prev_total_cycles = state.read_u64::<LittleEndian>().expect("Unable to load u64");
+    pub(crate) fn load_snapshot(&mut self, state: &mut Cursor<Vec<u8>>) -> io::Result<()> {
+        self.irq().load_state(state)?;
+        self.cart().load_state(state)?;
+        self.bus().load_state(state)?;
+        self.cpu().load_state(state)?;
+        self.ppu().load_state(state)?;
+        self.apu().load_state(state)?;
+        self.joypad().load_state(state)?;
+        self.prev_total_cycles = state.read_u64::<LittleEndian>()?;
+        self.frame_cycles = 0;
+
+        Ok(())
+    }
+
+    fn load_chunks(&mut self, reader: &StateReader) -> Result<(), StateError> {
+        reader.load(b"IRQ ", &mut *self.irq())?;
+        reader.load(b"CART", &mut *self.cart())?;
+        reader.load(b"BUS ", &mut *self.bus())?;
+        reader.load(b"CPU ", &mut *self.cpu())?;
+        reader.load(b"PPU ", &mut *self.ppu())?;
+        reader.load(b"APU ", &mut *self.apu())?;
+        reader.load(b"JOYP", &mut *self.joypad())?;
+
+        let mut prev_total_cycles = 0;
+        reader.load_with(b"EMU ", |state| {
+            prev_total_cycles = state.read_u64::<LittleEndian>()?;
+            Ok(())
+        })?;
+        self.prev_total_cycles = prev_total_cycles;
+
+        Ok(())
     }
 }
@@ -0,0 +1,17 @@
+/*
+64 bit FNV-1a, used wherever a hash has to stay the same between builds and platforms:
+ROM hashes in save states and battery saves, and the CLI's frame hashes.
+*/
+const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const PRIME: u64 = 0x100000001b3;
+
+pub fn fnv1a(data: &[u8]) -> u64 {
+    let mut hash = OFFSET_BASIS;
+
+    for &byte in data {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(PRIME);
+    }
+
+    hash
+}
@@ -0,0 +1,303 @@
+use crate::test_bit;
+
+pub const HEADER_SIZE: usize = 0x10;
+pub const TRAINER_SIZE: usize = 0x200;
+
+const PRG_ROM_UNIT: usize = 0x4000;
+const CHR_ROM_UNIT: usize = 0x2000;
+const INES_PRG_RAM_UNIT: usize = 0x2000;
+
+/// CPU/PPU timing the game was made for.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Timing {
+    Ntsc,
+    Pal,
+    MultipleRegion,
+    Dendy
+}
+
+/// Which kind of console the cartridge is meant to run on.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ConsoleType {
+    Nes,
+    VsSystem { ppu_type: u8, hardware_type: u8 },
+    Playchoice10,
+    /// Extended console type from byte 13 (Famiclone with decimal mode, VT01, ...)
+    Extended(u8)
+}
+
+/*
+Everything stored in the 16 byte iNES / NES 2.0 header.
+Sizes are in bytes. For plain iNES files the fields NES 2.0 adds are filled in with the usual assumptions
+(8 KB of PRG RAM, 8 KB of CHR RAM when there's no CHR ROM, NTSC, standard controllers).
+https://www.nesdev.org/wiki/INES
+https://www.nesdev.org/wiki/NES_2.0
+*/
+#[derive(Clone, Debug)]
+pub struct RomHeader {
+    pub nes2: bool,
+
+    pub mapper: u16,
+    pub submapper: u8,
+
+    pub vertical_mirroring: bool, /* hardwired nametable layout; horizontal when false */
+    pub four_screen: bool,
+    pub battery: bool,
+    pub trainer: bool,
+
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+
+    pub timing: Timing,
+    pub console_type: ConsoleType,
+    pub misc_roms: u8,
+    pub expansion_device: u8
+}
+
+impl RomHeader {
+    pub fn parse(header: &[u8; HEADER_SIZE]) -> Self {
+        let nes2 = ((header[7] >> 2) & 0b11) == 0b10;
+
+        if nes2 {
+            RomHeader::parse_nes2(header)
+        } else {
+            RomHeader::parse_ines(header)
+        }
+    }
+
+    /* Total file size implied by the header (header + trainer + PRG ROM + CHR ROM) */
+    pub fn rom_file_size(&self) -> usize {
+        HEADER_SIZE + if self.trainer { TRAINER_SIZE } else { 0 } + self.prg_rom_size + self.chr_rom_size
+    }
+
+    fn parse_ines(header: &[u8; HEADER_SIZE]) -> Self {
+        // Old dumping tools wrote their name into bytes 7-15 ("DiskDude!"), so byte 7 can't be trusted when the padding isn't zeroed
+        let flags7 = if header[12..16].iter().any(|&b| b != 0) {
+            0
+        } else {
+            header[7]
+        };
+
+        let chr_rom_size = header[5] as usize * CHR_ROM_UNIT;
+        let battery = test_bit!(header[6], 1);
+        let prg_ram_size = std::cmp::max(header[8] as usize, 1) * INES_PRG_RAM_UNIT;
+
+        RomHeader {
+            nes2: false,
+
+            mapper: ((header[6] >> 4) | (flags7 & 0b11110000)) as u16,
+            submapper: 0,
+
+            vertical_mirroring: test_bit!(header[6], 0),
+            four_screen: test_bit!(header[6], 3),
+            battery: battery,
+            trainer: test_bit!(header[6], 2),
+
+            prg_rom_size: header[4] as usize * PRG_ROM_UNIT,
+            chr_rom_size: chr_rom_size,
+
+            prg_ram_size: if battery { 0 } else { prg_ram_size },
+            prg_nvram_size: if battery { prg_ram_size } else { 0 },
+            chr_ram_size: if chr_rom_size == 0 { CHR_ROM_UNIT } else { 0 },
+            chr_nvram_size: 0,
+
+            timing: if test_bit!(header[9], 0) { Timing::Pal } else { Timing::Ntsc },
+            console_type: match flags7 & 0b11 {
+                1 => ConsoleType::VsSystem { ppu_type: 0, hardware_type: 0 },
+                2 => ConsoleType::Playchoice10,
+                _ => ConsoleType::Nes
+            },
+            misc_roms: 0,
+            expansion_device: 0
+        }
+    }
+
+    fn parse_nes2(header: &[u8; HEADER_SIZE]) -> Self {
+        /*
+            7  bit  0
+            ---- ----
+            CCCC PPPP
+            |||| ||||
+            |||| ++++- PRG ROM size MSB
+            ++++------ CHR ROM size MSB
+        */
+        let prg_rom_size = RomHeader::rom_size(header[4], header[9] & 0x0F, PRG_ROM_UNIT);
+        let chr_rom_size = RomHeader::rom_size(header[5], header[9] >> 4, CHR_ROM_UNIT);
+
+        RomHeader {
+            nes2: true,
+
+            mapper: ((header[6] >> 4) as u16) | ((header[7] & 0b11110000) as u16) | (((header[8] & 0x0F) as u16) << 8),
+            submapper: header[8] >> 4,
+
+            vertical_mirroring: test_bit!(header[6], 0),
+            four_screen: test_bit!(header[6], 3),
+            battery: test_bit!(header[6], 1),
+            trainer: test_bit!(header[6], 2),
+
+            prg_rom_size: prg_rom_size,
+            chr_rom_size: chr_rom_size,
+
+            prg_ram_size: RomHeader::ram_size(header[10] & 0x0F),
+            prg_nvram_size: RomHeader::ram_size(header[10] >> 4),
+            chr_ram_size: RomHeader::ram_size(header[11] & 0x0F),
+            chr_nvram_size: RomHeader::ram_size(header[11] >> 4),
+
+            timing: match header[12] & 0b11 {
+                0 => Timing::Ntsc,
+                1 => Timing::Pal,
+                2 => Timing::MultipleRegion,
+                _ => Timing::Dendy
+            },
+            console_type: match header[7] & 0b11 {
+                0 => ConsoleType::Nes,
+                1 => ConsoleType::VsSystem { ppu_type: header[13] & 0x0F, hardware_type: header[13] >> 4 },
+                2 => ConsoleType::Playchoice10,
+                _ => ConsoleType::Extended(header[13] & 0x0F)
+            },
+            misc_roms: header[14] & 0b11,
+            expansion_device: header[15] & 0b00111111
+        }
+    }
+
+    /*
+    When the MSB nibble is $F the size uses exponent-multiplier notation:
+        7  bit  0
+        ---- ----
+        EEEE EEMM
+        size = 2^E * (MM * 2 + 1) bytes
+    */
+    fn rom_size(lsb: u8, msb: u8, unit: usize) -> usize {
+        if msb == 0x0F {
+            let exponent = (lsb >> 2) as u32;
+            let multiplier = ((lsb & 0b11) as usize) * 2 + 1;
+
+            2usize.checked_pow(exponent).unwrap_or(0).saturating_mul(multiplier)
+        } else {
+            (((msb as usize) << 8) | lsb as usize) * unit
+        }
+    }
+
+    // RAM sizes are shift counts: 64 << n bytes, or nothing when n is 0
+    fn ram_size(shift: u8) -> usize {
+        if shift == 0 {
+            0
+        } else {
+            64 << shift
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(bytes: &[u8]) -> [u8; HEADER_SIZE] {
+        let mut header = [0; HEADER_SIZE];
+        header[0..4].copy_from_slice(b"NES\x1A");
+        header[4..(4 + bytes.len())].copy_from_slice(bytes);
+        header
+    }
+
+    #[test]
+    fn ines() {
+        // mapper 4 ($04 | $00), battery, vertical mirroring, trainer; 8 x 16 KB PRG, 16 x 8 KB CHR, PAL
+        let rom = RomHeader::parse(&header(&[8, 16, 0b0100_0111, 0, 0, 1]));
+
+        assert!(!rom.nes2);
+        assert_eq!(rom.mapper, 4);
+        assert!(rom.vertical_mirroring && rom.battery && rom.trainer && !rom.four_screen);
+        assert_eq!(rom.prg_rom_size, 128 * 1024);
+        assert_eq!(rom.chr_rom_size, 128 * 1024);
+        assert_eq!((rom.prg_ram_size, rom.prg_nvram_size), (0, 0x2000)); /* battery, so it's kept */
+        assert_eq!(rom.chr_ram_size, 0);
+        assert_eq!(rom.timing, Timing::Pal);
+        assert_eq!(rom.console_type, ConsoleType::Nes);
+        assert_eq!(rom.rom_file_size(), HEADER_SIZE + TRAINER_SIZE + 256 * 1024);
+    }
+
+    #[test]
+    fn ines_defaults() {
+        // mapper 66 ($2 | $40), no CHR ROM, PlayChoice-10, 2 x 8 KB PRG RAM
+        let rom = RomHeader::parse(&header(&[2, 0, 0x20, 0x42, 2]));
+
+        assert_eq!(rom.mapper, 66);
+        assert!(!rom.vertical_mirroring && !rom.battery);
+        assert_eq!((rom.prg_ram_size, rom.prg_nvram_size), (0x4000, 0));
+        assert_eq!(rom.chr_ram_size, 0x2000);
+        assert_eq!(rom.timing, Timing::Ntsc);
+        assert_eq!(rom.console_type, ConsoleType::Playchoice10);
+        assert_eq!(rom.rom_file_size(), HEADER_SIZE + 32 * 1024);
+    }
+
+    #[test]
+    fn ines_with_garbage_padding() {
+        // "DiskDude!" in bytes 7-15 would otherwise read as mapper $44 | ...
+        let mut bytes = header(&[1, 1, 0x10]);
+        bytes[7..16].copy_from_slice(b"DiskDude!");
+        let rom = RomHeader::parse(&bytes);
+
+        assert!(!rom.nes2);
+        assert_eq!(rom.mapper, 1);
+        assert_eq!(rom.console_type, ConsoleType::Nes);
+    }
+
+    #[test]
+    fn nes2() {
+        // mapper $1A4 submapper 3, PRG ROM $102 x 16 KB, CHR ROM $003 x 8 KB, Dendy, Vs. System
+        let rom = RomHeader::parse(&header(&[0x02, 0x03, 0x41, 0xA9, 0x31, 0x01, 0x97, 0x0A, 0x03, 0x25, 0x02, 0x01]));
+
+        assert!(rom.nes2);
+        assert_eq!(rom.mapper, 0x1A4);
+        assert_eq!(rom.submapper, 3);
+        assert!(rom.vertical_mirroring && !rom.battery);
+        assert_eq!(rom.prg_rom_size, 0x102 * 0x4000);
+        assert_eq!(rom.chr_rom_size, 3 * 0x2000);
+
+        // shift counts: 64 << 7 = 8 KB PRG RAM, 64 << 9 = 32 KB PRG NVRAM, 64 << 10 = 64 KB CHR RAM, no CHR NVRAM
+        assert_eq!((rom.prg_ram_size, rom.prg_nvram_size), (0x2000, 0x8000));
+        assert_eq!((rom.chr_ram_size, rom.chr_nvram_size), (0x10000, 0));
+
+        assert_eq!(rom.timing, Timing::Dendy);
+        assert_eq!(rom.console_type, ConsoleType::VsSystem { ppu_type: 5, hardware_type: 2 });
+        assert_eq!(rom.misc_roms, 2);
+        assert_eq!(rom.expansion_device, 1);
+        assert_eq!(rom.rom_file_size(), HEADER_SIZE + 0x102 * 0x4000 + 3 * 0x2000);
+    }
+
+    #[test]
+    fn nes2_exponent_sizes() {
+        // PRG: 2^10 * (1 * 2 + 1) = 3 KB, CHR: 2^7 * (0 * 2 + 1) = 128 bytes; extended console type 3
+        let rom = RomHeader::parse(&header(&[0b0010_1001, 0b0001_1100, 0x00, 0x0B, 0x00, 0xFF, 0, 0, 0x00, 0x03]));
+
+        assert!(rom.nes2);
+        assert_eq!(rom.prg_rom_size, 3 * 1024);
+        assert_eq!(rom.chr_rom_size, 128);
+        assert_eq!((rom.prg_ram_size, rom.chr_ram_size), (0, 0));
+        assert_eq!(rom.timing, Timing::Ntsc);
+        assert_eq!(rom.console_type, ConsoleType::Extended(3));
+        assert_eq!(rom.rom_file_size(), HEADER_SIZE + 3 * 1024 + 128);
+    }
+
+    #[test]
+    fn exponent_overflow() {
+        // 2^63 * 7 doesn't fit in 64 bits and saturates instead of wrapping
+        assert_eq!(RomHeader::rom_size(0xFF, 0x0F, PRG_ROM_UNIT), usize::MAX);
+
+        // an MSB nibble below $F is still the plain form
+        assert_eq!(RomHeader::rom_size(0x10, 0x0E, PRG_ROM_UNIT), 0xE10 * PRG_ROM_UNIT);
+    }
+
+    #[test]
+    fn ram_sizes() {
+        assert_eq!(RomHeader::ram_size(0), 0);
+        assert_eq!(RomHeader::ram_size(1), 128);
+        assert_eq!(RomHeader::ram_size(7), 0x2000);
+        assert_eq!(RomHeader::ram_size(15), 0x200000);
+    }
+}
@@ -0,0 +1,69 @@
+use std::io::{self, Cursor};
+
+use byteorder::{ReadBytesExt, WriteBytesExt};
+
+use crate::savable::Savable;
+use crate::{test_bit, modify_bit};
+
+/// Every device which is able to pull the CPU's /IRQ line low.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum IrqSource {
+    FrameCounter = 0,
+    DMC = 1,
+    Mapper = 2
+}
+
+/*
+The /IRQ line is shared by all devices (wired-OR), so it stays active as long as at least one source asserts it.
+Each source is responsible for acknowledging its own interrupt, usually when the game writes to one of its registers.
+https://www.nesdev.org/wiki/IRQ
+*/
+pub struct IrqLine {
+    sources: u8
+}
+
+impl Default for IrqLine {
+    fn default() -> Self {
+        IrqLine::new()
+    }
+}
+
+impl IrqLine {
+    pub fn new() -> Self {
+        IrqLine {
+            sources: 0
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.sources = 0;
+    }
+
+    pub fn assert(&mut self, source: IrqSource) {
+        modify_bit!(self.sources, source as u8, true);
+    }
+
+    pub fn acknowledge(&mut self, source: IrqSource) {
+        modify_bit!(self.sources, source as u8, false);
+    }
+
+    pub fn is_asserted(&self, source: IrqSource) -> bool {
+        test_bit!(self.sources, source as u8)
+    }
+
+    pub fn active(&self) -> bool {
+        self.sources != 0
+    }
+}
+
+impl Savable for IrqLine {
+    fn save_state(&self, state: &mut Vec<u8>) {
+        state.write_u8(self.sources).expect("Unable to save u8");
+    }
+
+    fn load_state(&mut self, state: &mut Cursor<Vec<u8>>) -> io::Result<()> {
+        self.sources = state.read_u8()?;
+
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-use std::io::Cursor;
+use std::io::{self, Cursor};
 
 use byteorder::{ReadBytesExt, WriteBytesExt};
 
@@ -56,6 +56,19 @@ impl Joypad {
         button_state
     }
 
+    // The bit the next read would return, without moving on to the next button
+    pub fn peek(&self) -> u8 {
+        if self.strobe {
+            return test_bit!(self.state, BUTTON_A) as u8;
+        }
+
+        if self.button_index > BUTTON_RIGHT {
+            1
+        } else {
+            test_bit!(self.state, self.button_index) as u8
+        }
+    }
+
     pub fn write(&mut self, data: u8) {
         self.strobe = data & 1 == 1;
 
@@ -80,9 +93,11 @@ impl Savable for Joypad {
         state.write_u8(self.strobe as u8).expect("Unable to save u8");
     }
 
-    fn load_state(&mut self, state: &mut Cursor<Vec<u8>>) {
-        self.state = state.read_u8().expect("Unable to load u8");
-        self.button_index = state.read_u8().expect("Unable to load u8");
-        self.strobe = state.read_u8().expect("Unable to load u8") != 0;
+    fn load_state(&mut self, state: &mut Cursor<Vec<u8>>) -> io::Result<()> {
+        self.state = state.read_u8()?;
+        self.button_index = state.read_u8()?;
+        self.strobe = state.read_u8()? != 0;
+
+        Ok(())
     }
 }
@@ -5,11 +5,23 @@ mod mapper;
 mod io;
 mod dma;
 mod startup_rom;
+mod opcodes;
+mod rle;
 
 pub mod savable;
+pub mod state;
+pub mod hash;
+pub mod rewind;
+pub mod header;
 pub mod cartridge;
 pub mod m6502;
+pub mod trace;
+pub mod disasm;
+pub mod debugger;
+pub mod remote;
 pub mod bus;
 pub mod ppu;
+pub mod apu;
+pub mod irq;
 pub mod joypad;
 pub mod emulator;
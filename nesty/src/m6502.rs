@@ -1,10 +1,11 @@
 use std::cell::RefCell;
 use std::rc::{Rc, Weak};
-use std::io::Cursor;
+use std::io::{self, Cursor};
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 
 use crate::bus::Bus;
+use crate::trace::{self, Tracer};
 
 use crate::io::IO;
 use crate::savable::Savable;
@@ -47,6 +48,10 @@ const RESET_ADDR: u16 = 0xFFFC;
 const IRQ_ADDR: u16 = 0xFFFE;
 const BRK_ADDR: u16 = 0xFFFE;
 
+// Value ORed into A by the unstable XAA and LAX #imm; it depends on the chip and temperature, $EE is the most common
+const UNSTABLE_MAGIC: u8 = 0xEE;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum AddressingMode {
     Accumulator,
     Absolute,
@@ -75,6 +80,16 @@ pub struct M6502 {
     pc:  u16,
     bus: Weak<RefCell<Bus>>,
 
+    // The I flag as seen by the interrupt polling logic. Interrupts are polled before the last cycle of an instruction,
+    // so after CLI, SEI and PLP the change only takes effect after the next instruction.
+    // https://www.nesdev.org/wiki/CPU_interrupts#Delayed_IRQ_response_after_CLI,_SEI,_and_PLP
+    irq_disabled: bool,
+
+    // Set by the KIL opcodes; only a reset brings the CPU back
+    halted: bool,
+
+    tracer: Option<Tracer>,
+
     pub total_cycles: u64
 }
 
@@ -88,6 +103,9 @@ impl M6502 {
             sp:  0,
             pc:  0,
             bus: bus.clone(),
+            irq_disabled: true,
+            halted: false,
+            tracer: None,
             total_cycles: 0
         }
     }
@@ -103,22 +121,40 @@ impl M6502 {
         self.y = 0;
         self.p = 0x34; // normally 0x34 but if running nestest use 0x24 instead
         self.sp = 0xFD;
+        self.irq_disabled = true;
+        self.halted = false;
         // According to https://wiki.nesdev.org/w/index.php/CPU_memory_map, the reset vector is located at $FFFC-$FFFD
         // However, if you are running nestest in an emulator without video, interrupts, etc. implemented, set PC to $C000
-        // to run the "automated" mode.
+        // to run the "automated" mode (see tests/nestest.rs).
         self.pc = self.cpu_read_word(RESET_ADDR);
 
         self.total_cycles += 5; // reset takes the total of 7 cycles
     }
 
+    /*
+    The reset button, as opposed to power on: A, X, Y and memory are left alone
+    and SP moves down by 3 as if the return address and status were pushed.
+    https://www.nesdev.org/wiki/CPU_power_up_state
+    */
+    pub fn soft_reset(&mut self) {
+        self.sp = self.sp.wrapping_sub(3);
+        modify_bit!(self.p, FLAG_I, true);
+        self.irq_disabled = true;
+        self.halted = false;
+        self.pc = self.cpu_read_word(RESET_ADDR);
+
+        self.total_cycles += 7;
+    }
+
     pub fn irq(&mut self) {
         // Check if interrupts are allowed
-        if test_bit!(self.p, FLAG_I) { return; }
+        if self.irq_disabled || self.halted { return; }
 
         self.push_word(self.pc);
         // For more information, see https://www.nesdev.org/wiki/Status_flags#The_B_flag
         self.push_byte(self.p | 0b00100000);
         modify_bit!(self.p, FLAG_I, true);
+        self.irq_disabled = true;
         self.pc = self.cpu_read_word(IRQ_ADDR);
 
         self.total_cycles += 2; // irq takes the total of 7 cycles
@@ -126,13 +162,91 @@ impl M6502 {
 
     // This interrupt cannot be skipped but its overall behaviour is same as irq
     pub fn nmi(&mut self) {
+        if self.halted { return; }
+
         self.push_word(self.pc);
         self.push_byte(self.p | 0b00100000);
         modify_bit!(self.p, FLAG_I, true);
+        self.irq_disabled = true;
         self.pc = self.cpu_read_word(NMI_ADDR);
         self.total_cycles += 2;
     }
 
+    // Pass None to stop tracing; the old tracer is handed back so its ring buffer can still be dumped
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) -> Option<Tracer> {
+        std::mem::replace(&mut self.tracer, tracer)
+    }
+
+    pub fn tracer(&mut self) -> Option<&mut Tracer> {
+        self.tracer.as_mut()
+    }
+
+    fn trace(&mut self) {
+        let bus = self.bus();
+        let bus = bus.borrow();
+        let ppu = bus.ppu();
+        let ppu = ppu.borrow();
+
+        let line = trace::format_line(self, &bus, ppu.scanline(), ppu.cycle());
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.record(line);
+        }
+    }
+
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn a(&self) -> u8 {
+        self.a
+    }
+
+    pub fn x(&self) -> u8 {
+        self.x
+    }
+
+    pub fn y(&self) -> u8 {
+        self.y
+    }
+
+    pub fn p(&self) -> u8 {
+        self.p
+    }
+
+    pub fn sp(&self) -> u8 {
+        self.sp
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn set_a(&mut self, a: u8) {
+        self.a = a;
+    }
+
+    pub fn set_x(&mut self, x: u8) {
+        self.x = x;
+    }
+
+    pub fn set_y(&mut self, y: u8) {
+        self.y = y;
+    }
+
+    pub fn set_sp(&mut self, sp: u8) {
+        self.sp = sp;
+    }
+
+    // For running test roms such as nestest in automation mode
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    pub fn set_p(&mut self, p: u8) {
+        self.p = p;
+        self.irq_disabled = test_bit!(p, FLAG_I);
+    }
+
     pub fn tick(&mut self) {
         macro_rules! do_add {
             /*  for idiots who have no idea how to determine overflow,
@@ -367,6 +481,122 @@ impl M6502 {
             }
         }
 
+        /* Unofficial opcodes; see https://www.nesdev.org/wiki/CPU_unofficial_opcodes */
+
+        macro_rules! rmw {
+            ($mode:expr, $op:ident) => {
+                let addr = self.fetch_address($mode);
+                let mut val = self.cpu_read_byte(addr);
+                $op!(val);
+                self.total_cycles += 1; // dummy write
+                self.cpu_write_byte(addr, val);
+            }
+        }
+
+        macro_rules! slo {
+            ($val:expr) => {
+                shift_reg!($val, true, true);
+                self.a |= $val;
+                self.modify_zn(self.a);
+            }
+        }
+
+        macro_rules! rla {
+            ($val:expr) => {
+                shift_reg!($val, true, false);
+                self.a &= $val;
+                self.modify_zn(self.a);
+            }
+        }
+
+        macro_rules! sre {
+            ($val:expr) => {
+                shift_reg!($val, false, true);
+                self.a ^= $val;
+                self.modify_zn(self.a);
+            }
+        }
+
+        macro_rules! rra {
+            ($val:expr) => {
+                shift_reg!($val, false, false);
+                do_add!($val);
+            }
+        }
+
+        macro_rules! dcp {
+            ($val:expr) => {
+                $val = $val.wrapping_sub(1);
+                modify_bit!(self.p, FLAG_C, self.a >= $val);
+                self.modify_zn(self.a.wrapping_sub($val));
+            }
+        }
+
+        macro_rules! isc {
+            ($val:expr) => {
+                $val = $val.wrapping_add(1);
+                do_add!($val ^ 0xFF);
+            }
+        }
+
+        macro_rules! lax {
+            ($mode:expr) => {
+                let addr = self.fetch_address($mode);
+                self.a = self.cpu_read_byte(addr);
+                self.x = self.a;
+                self.modify_zn(self.a);
+            }
+        }
+
+        macro_rules! nop {
+            ($mode:expr) => {
+                let addr = self.fetch_address($mode);
+                self.cpu_read_byte(addr); // the operand is still read
+            }
+        }
+
+        macro_rules! imm {
+            () => {
+                {
+                    let addr = self.fetch_address(AddressingMode::Immediate);
+                    self.cpu_read_byte(addr)
+                }
+            }
+        }
+
+        /*
+        SHA, SHX, SHY and TAS store the register ANDed with the high byte of the base address plus one.
+        When the indexing crosses a page, that value also replaces the high byte of the target address.
+        */
+        macro_rules! sh {
+            ($val:expr, $index:expr, $indirect:literal) => {
+                let base = if $indirect {
+                    let nn = self.fetch_byte();
+                    self.read_zp16(nn)
+                } else {
+                    self.fetch_word()
+                };
+                let mut addr = base.wrapping_add($index as u16);
+                let data = $val & ((base >> 8) as u8).wrapping_add(1);
+                if page_cross!(base, addr) {
+                    addr = ((data as u16) << 8) | (addr & 0xFF);
+                }
+                self.total_cycles += 1; // dummy read
+                self.cpu_write_byte(addr, data);
+            }
+        }
+
+        if self.halted {
+            self.total_cycles += 1;
+            return;
+        }
+
+        if self.tracer.is_some() {
+            self.trace();
+        }
+
+        let prev_irq_disabled = test_bit!(self.p, FLAG_I);
+
         let opcode = self.fetch_byte();
 
         match opcode {
@@ -521,8 +751,123 @@ impl M6502 {
             0x8A => { /* TXA; 2c */             transfer!(self.x, self.a); self.total_cycles += 1; }
             0x9A => { /* TXS; 2c */             self.sp = self.x; self.total_cycles += 1; }
             0x98 => { /* TYA; 2c */             transfer!(self.y, self.a); self.total_cycles += 1; }
-            _ => todo!("Halted at PC={:04X}; Unimplemented opcode: {:02X}", self.pc - 1, opcode)
+            /* Unofficial opcodes */
+            0x07 => { /* SLO oper; 5c */        rmw!(AddressingMode::ZeroPage, slo); }
+            0x17 => { /* SLO oper,X; 6c */      rmw!(AddressingMode::ZeroPageX, slo); }
+            0x0F => { /* SLO oper; 6c */        rmw!(AddressingMode::Absolute, slo); }
+            0x1F => { /* SLO oper,X; 7c */      rmw!(AddressingMode::AbsoluteXEc, slo); }
+            0x1B => { /* SLO oper,Y; 7c */      rmw!(AddressingMode::AbsoluteYEc, slo); }
+            0x03 => { /* SLO (oper,X); 8c */    rmw!(AddressingMode::IndirectX, slo); }
+            0x13 => { /* SLO (oper),Y; 8c */    rmw!(AddressingMode::IndirectYEc, slo); }
+            0x27 => { /* RLA oper; 5c */        rmw!(AddressingMode::ZeroPage, rla); }
+            0x37 => { /* RLA oper,X; 6c */      rmw!(AddressingMode::ZeroPageX, rla); }
+            0x2F => { /* RLA oper; 6c */        rmw!(AddressingMode::Absolute, rla); }
+            0x3F => { /* RLA oper,X; 7c */      rmw!(AddressingMode::AbsoluteXEc, rla); }
+            0x3B => { /* RLA oper,Y; 7c */      rmw!(AddressingMode::AbsoluteYEc, rla); }
+            0x23 => { /* RLA (oper,X); 8c */    rmw!(AddressingMode::IndirectX, rla); }
+            0x33 => { /* RLA (oper),Y; 8c */    rmw!(AddressingMode::IndirectYEc, rla); }
+            0x47 => { /* SRE oper; 5c */        rmw!(AddressingMode::ZeroPage, sre); }
+            0x57 => { /* SRE oper,X; 6c */      rmw!(AddressingMode::ZeroPageX, sre); }
+            0x4F => { /* SRE oper; 6c */        rmw!(AddressingMode::Absolute, sre); }
+            0x5F => { /* SRE oper,X; 7c */      rmw!(AddressingMode::AbsoluteXEc, sre); }
+            0x5B => { /* SRE oper,Y; 7c */      rmw!(AddressingMode::AbsoluteYEc, sre); }
+            0x43 => { /* SRE (oper,X); 8c */    rmw!(AddressingMode::IndirectX, sre); }
+            0x53 => { /* SRE (oper),Y; 8c */    rmw!(AddressingMode::IndirectYEc, sre); }
+            0x67 => { /* RRA oper; 5c */        rmw!(AddressingMode::ZeroPage, rra); }
+            0x77 => { /* RRA oper,X; 6c */      rmw!(AddressingMode::ZeroPageX, rra); }
+            0x6F => { /* RRA oper; 6c */        rmw!(AddressingMode::Absolute, rra); }
+            0x7F => { /* RRA oper,X; 7c */      rmw!(AddressingMode::AbsoluteXEc, rra); }
+            0x7B => { /* RRA oper,Y; 7c */      rmw!(AddressingMode::AbsoluteYEc, rra); }
+            0x63 => { /* RRA (oper,X); 8c */    rmw!(AddressingMode::IndirectX, rra); }
+            0x73 => { /* RRA (oper),Y; 8c */    rmw!(AddressingMode::IndirectYEc, rra); }
+            0xC7 => { /* DCP oper; 5c */        rmw!(AddressingMode::ZeroPage, dcp); }
+            0xD7 => { /* DCP oper,X; 6c */      rmw!(AddressingMode::ZeroPageX, dcp); }
+            0xCF => { /* DCP oper; 6c */        rmw!(AddressingMode::Absolute, dcp); }
+            0xDF => { /* DCP oper,X; 7c */      rmw!(AddressingMode::AbsoluteXEc, dcp); }
+            0xDB => { /* DCP oper,Y; 7c */      rmw!(AddressingMode::AbsoluteYEc, dcp); }
+            0xC3 => { /* DCP (oper,X); 8c */    rmw!(AddressingMode::IndirectX, dcp); }
+            0xD3 => { /* DCP (oper),Y; 8c */    rmw!(AddressingMode::IndirectYEc, dcp); }
+            0xE7 => { /* ISC oper; 5c */        rmw!(AddressingMode::ZeroPage, isc); }
+            0xF7 => { /* ISC oper,X; 6c */      rmw!(AddressingMode::ZeroPageX, isc); }
+            0xEF => { /* ISC oper; 6c */        rmw!(AddressingMode::Absolute, isc); }
+            0xFF => { /* ISC oper,X; 7c */      rmw!(AddressingMode::AbsoluteXEc, isc); }
+            0xFB => { /* ISC oper,Y; 7c */      rmw!(AddressingMode::AbsoluteYEc, isc); }
+            0xE3 => { /* ISC (oper,X); 8c */    rmw!(AddressingMode::IndirectX, isc); }
+            0xF3 => { /* ISC (oper),Y; 8c */    rmw!(AddressingMode::IndirectYEc, isc); }
+            0xA7 => { /* LAX oper; 3c */        lax!(AddressingMode::ZeroPage); }
+            0xB7 => { /* LAX oper,Y; 4c */      lax!(AddressingMode::ZeroPageY); }
+            0xAF => { /* LAX oper; 4c */        lax!(AddressingMode::Absolute); }
+            0xBF => { /* LAX oper,Y; 4+c */     lax!(AddressingMode::AbsoluteY); }
+            0xA3 => { /* LAX (oper,X); 6c */    lax!(AddressingMode::IndirectX); }
+            0xB3 => { /* LAX (oper),Y; 5+c */   lax!(AddressingMode::IndirectY); }
+            0x87 => { /* SAX oper; 3c */        store!(self.a & self.x, AddressingMode::ZeroPage); }
+            0x97 => { /* SAX oper,Y; 4c */      store!(self.a & self.x, AddressingMode::ZeroPageY); }
+            0x8F => { /* SAX oper; 4c */        store!(self.a & self.x, AddressingMode::Absolute); }
+            0x83 => { /* SAX (oper,X); 6c */    store!(self.a & self.x, AddressingMode::IndirectX); }
+            0x0B | 0x2B => { /* ANC #oper; 2c */
+                self.a &= imm!();
+                self.modify_zn(self.a);
+                modify_bit!(self.p, FLAG_C, test_bit!(self.a, 7));
+            }
+            0x4B => { /* ALR #oper; 2c */
+                self.a &= imm!();
+                shift_reg!(self.a, false, true);
+            }
+            0x6B => { /* ARR #oper; 2c */
+                self.a &= imm!();
+                shift_reg!(self.a, false, false);
+                modify_bit!(self.p, FLAG_C, test_bit!(self.a, 6));
+                modify_bit!(self.p, FLAG_V, test_bit!(self.a, 6) != test_bit!(self.a, 5));
+            }
+            0xCB => { /* AXS #oper; 2c */
+                let m = imm!();
+                let ax = self.a & self.x;
+                modify_bit!(self.p, FLAG_C, ax >= m);
+                self.x = ax.wrapping_sub(m);
+                self.modify_zn(self.x);
+            }
+            0xEB => { /* SBC #oper; 2c */       sbc!(AddressingMode::Immediate); }
+            0x1A | 0x3A | 0x5A | 0x7A | 0xDA | 0xFA => { /* NOP; 2c */ self.total_cycles += 1; }
+            0x80 | 0x82 | 0x89 | 0xC2 | 0xE2 => { /* NOP #oper; 2c */ nop!(AddressingMode::Immediate); }
+            0x04 | 0x44 | 0x64 => { /* NOP oper; 3c */ nop!(AddressingMode::ZeroPage); }
+            0x14 | 0x34 | 0x54 | 0x74 | 0xD4 | 0xF4 => { /* NOP oper,X; 4c */ nop!(AddressingMode::ZeroPageX); }
+            0x0C => { /* NOP oper; 4c */        nop!(AddressingMode::Absolute); }
+            0x1C | 0x3C | 0x5C | 0x7C | 0xDC | 0xFC => { /* NOP oper,X; 4+c */ nop!(AddressingMode::AbsoluteX); }
+
+            /* Unstable ones */
+            0x8B => { /* XAA #oper; 2c */
+                self.a = (self.a | UNSTABLE_MAGIC) & self.x & imm!();
+                self.modify_zn(self.a);
+            }
+            0xAB => { /* LAX #oper; 2c */
+                self.a = (self.a | UNSTABLE_MAGIC) & imm!();
+                self.x = self.a;
+                self.modify_zn(self.a);
+            }
+            0x9F => { /* SHA oper,Y; 5c */      sh!(self.a & self.x, self.y, false); }
+            0x93 => { /* SHA (oper),Y; 6c */    sh!(self.a & self.x, self.y, true); }
+            0x9E => { /* SHX oper,Y; 5c */      sh!(self.x, self.y, false); }
+            0x9C => { /* SHY oper,X; 5c */      sh!(self.y, self.x, false); }
+            0x9B => { /* TAS oper,Y; 5c */      self.sp = self.a & self.x; sh!(self.sp, self.y, false); }
+            0xBB => { /* LAS oper,Y; 4+c */
+                let addr = self.fetch_address(AddressingMode::AbsoluteY);
+                self.sp &= self.cpu_read_byte(addr);
+                self.a = self.sp;
+                self.x = self.sp;
+                self.modify_zn(self.sp);
+            }
+
+            /* KIL freezes the CPU until the console is reset */
+            0x02 | 0x12 | 0x22 | 0x32 | 0x42 | 0x52 | 0x62 | 0x72 | 0x92 | 0xB2 | 0xD2 | 0xF2 => {
+                self.pc = self.pc.wrapping_sub(1);
+                self.halted = true;
+            }
         }
+
+        self.irq_disabled = match opcode {
+            0x58 | 0x78 | 0x28 => prev_irq_disabled, /* CLI, SEI, PLP */
+            _ => test_bit!(self.p, FLAG_I)
+        };
     }
 
     fn pull_word(&mut self) -> u16 {
@@ -644,13 +989,15 @@ impl M6502 {
     }
 
     fn fetch_word(&mut self) -> u16 {
-        let word = self.cpu_read_word(self.pc);
+        self.total_cycles += 2;
+        let word = self.bus().borrow_mut().fetch_word(self.pc);
         self.pc += 2;
         word
     }
 
     fn fetch_byte(&mut self) -> u8 {
-        let byte = self.cpu_read_byte(self.pc);
+        self.total_cycles += 1;
+        let byte = self.bus().borrow_mut().fetch_byte(self.pc);
         self.pc += 1;
         byte
     }
@@ -684,16 +1031,22 @@ impl Savable for M6502 {
         state.write_u8(self.p).expect("Unable to save u8");
         state.write_u8(self.sp).expect("Unable to save u8");
         state.write_u16::<LittleEndian>(self.pc).expect("Unable to save u16");
+        state.write_u8(self.irq_disabled as u8).expect("Unable to save u8");
+        state.write_u8(self.halted as u8).expect("Unable to save u8");
         state.write_u64::<LittleEndian>(self.total_cycles).expect("Unable to save u64");
     }
 
-    fn load_state(&mut self, state: &mut Cursor<Vec<u8>>) {
-        self.a = state.read_u8().expect("Unable to load u8");
-        self.x = state.read_u8().expect("Unable to load u8");
-        self.y = state.read_u8().expect("Unable to load u8");
-        self.p = state.read_u8().expect("Unable to load u8");
-        self.sp = state.read_u8().expect("Unable to load u8");
-        self.pc = state.read_u16::<LittleEndian>().expect("Unable to load u16");
-        self.total_cycles = state.read_u64::<LittleEndian>().expect("Unable to load u64");
+    fn load_state(&mut self, state: &mut Cursor<Vec<u8>>) -> io::Result<()> {
+        self.a = state.read_u8()?;
+        self.x = state.read_u8()?;
+        self.y = state.read_u8()?;
+        self.p = state.read_u8()?;
+        self.sp = state.read_u8()?;
+        self.pc = state.read_u16::<LittleEndian>()?;
+        self.irq_disabled = state.read_u8()? != 0;
+        self.halted = state.read_u8()? != 0;
+        self.total_cycles = state.read_u64::<LittleEndian>()?;
+
+        Ok(())
     }
 }
@@ -1,4 +1,4 @@
-use std::io::Cursor;
+use std::io::{self, Cursor};
 
 use byteorder::{ReadBytesExt, WriteBytesExt};
 
@@ -68,6 +68,18 @@ impl MapperBase for Mapper0 {
     fn mirroring(&self) -> Mirroring {
         return self.mirroring_type;
     }
+
+    fn prg_rom(&self) -> &[u8] {
+        &self.prg_rom
+    }
+
+    fn sram(&self) -> Option<&[u8]> {
+        Some(&self.sram[..])
+    }
+
+    fn sram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.sram[..])
+    }
 }
 
 impl Savable for Mapper0 {
@@ -77,10 +89,12 @@ impl Savable for Mapper0 {
         }
     }
 
-    fn load_state(&mut self, state: &mut Cursor<Vec<u8>>) {
+    fn load_state(&mut self, state: &mut Cursor<Vec<u8>>) -> io::Result<()> {
         for i in 0..SRAM_SIZE {
-            self.sram[i] = state.read_u8().expect("Unable to load u8");
+            self.sram[i] = state.read_u8()?;
         }
+
+        Ok(())
     }
 }
 
@@ -1,4 +1,4 @@
-use std::io::Cursor;
+use std::io::{self, Cursor};
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 
@@ -82,13 +82,19 @@ impl Mapper1 {
                 let mirroring = self.shift_register & 0b00011;
 
                 match mirroring {
+                    0 => {
+                        self.mirroring_type = Mirroring::SingleScreenLower;
+                    }
+                    1 => {
+                        self.mirroring_type = Mirroring::SingleScreenUpper;
+                    }
                     2 => {
                         self.mirroring_type = Mirroring::Vertical;
                     }
                     3 => {
                         self.mirroring_type = Mirroring::Horizontial;
                     }
-                    _ => panic!("This mirroring mode not supported: {}", mirroring)
+                    _ => unreachable!()
                 }
 
                 self.control_register = self.shift_register;
@@ -253,6 +259,18 @@ impl MapperBase for Mapper1 {
     fn mirroring(&self) -> Mirroring {
         return self.mirroring_type;
     }
+
+    fn prg_rom(&self) -> &[u8] {
+        &self.prg_rom
+    }
+
+    fn sram(&self) -> Option<&[u8]> {
+        Some(&self.sram[..])
+    }
+
+    fn sram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.sram[..])
+    }
 }
 
 impl Savable for Mapper1 {
@@ -264,6 +282,15 @@ impl Savable for Mapper1 {
             Mirroring::Horizontial => {
                 state.write_u8(1).expect("Unable to save u8");
             }
+            Mirroring::SingleScreenLower => {
+                state.write_u8(2).expect("Unable to save u8");
+            }
+            Mirroring::SingleScreenUpper => {
+                state.write_u8(3).expect("Unable to save u8");
+            }
+            Mirroring::FourScreen => {
+                state.write_u8(4).expect("Unable to save u8");
+            }
         }
 
         let use_chr_ram = self.chr_rom_banks == 0;
@@ -291,8 +318,8 @@ impl Savable for Mapper1 {
         state.write_u32::<LittleEndian>(self.prg_bank_select as u32).expect("Unable to save u32");
     }
 
-    fn load_state(&mut self, state: &mut Cursor<Vec<u8>>) {
-        let mirroring = state.read_u8().expect("Unable to load u8");
+    fn load_state(&mut self, state: &mut Cursor<Vec<u8>>) -> io::Result<()> {
+        let mirroring = state.read_u8()?;
         match mirroring {
             0 => {
                 self.mirroring_type = Mirroring::Vertical;
@@ -300,30 +327,44 @@ impl Savable for Mapper1 {
             1 => {
                 self.mirroring_type = Mirroring::Horizontial;
             }
-            _ => panic!("Unknown byte when reading mirroring configuration: {}", mirroring)
+            2 => {
+                self.mirroring_type = Mirroring::SingleScreenLower;
+            }
+            3 => {
+                self.mirroring_type = Mirroring::SingleScreenUpper;
+            }
+            4 => {
+                self.mirroring_type = Mirroring::FourScreen;
+            }
+            _ => {
+                let message = format!("Unknown byte when reading mirroring configuration: {}", mirroring);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+            }
         }
 
-        let use_chr_ram = state.read_u8().expect("Unable to load u8") != 0;
+        let use_chr_ram = state.read_u8()? != 0;
         if use_chr_ram {
             for i in 0..self.chr_rom.len() {
-                self.chr_rom[i] = state.read_u8().expect("Unable to load u8");
+                self.chr_rom[i] = state.read_u8()?;
             }
         }
 
         for i in 0..SRAM_SIZE {
-            self.sram[i] = state.read_u8().expect("Unable to load u8");
+            self.sram[i] = state.read_u8()?;
         }
 
-        self.shift_register = state.read_u8().expect("Unable to load u8");
-        self.control_register = state.read_u8().expect("Unable to load u8");
+        self.shift_register = state.read_u8()?;
+        self.control_register = state.read_u8()?;
+
+        self.chr_bank_select_lo = state.read_u32::<LittleEndian>()? as usize;
+        self.chr_bank_select_hi = state.read_u32::<LittleEndian>()? as usize;
+        self.chr_bank_select = state.read_u32::<LittleEndian>()? as usize;
 
-        self.chr_bank_select_lo = state.read_u32::<LittleEndian>().expect("Unable to load u32") as usize;
-        self.chr_bank_select_hi = state.read_u32::<LittleEndian>().expect("Unable to load u32") as usize;
-        self.chr_bank_select = state.read_u32::<LittleEndian>().expect("Unable to load u32") as usize;
+        self.prg_bank_select_lo = state.read_u32::<LittleEndian>()? as usize;
+        self.prg_bank_select_hi = state.read_u32::<LittleEndian>()? as usize;
+        self.prg_bank_select = state.read_u32::<LittleEndian>()? as usize;
 
-        self.prg_bank_select_lo = state.read_u32::<LittleEndian>().expect("Unable to load u32") as usize;
-        self.prg_bank_select_hi = state.read_u32::<LittleEndian>().expect("Unable to load u32") as usize;
-        self.prg_bank_select = state.read_u32::<LittleEndian>().expect("Unable to load u32") as usize;
+        Ok(())
     }
 }
 
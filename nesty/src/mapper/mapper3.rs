@@ -0,0 +1,127 @@
+use std::io::{self, Cursor};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::mapper::{Mirroring, Mapper, MapperBase, CHR_ROM_BANK_SIZE};
+
+use crate::savable::Savable;
+use crate::mirror;
+
+pub struct Mapper3 {
+    mirroring_type: Mirroring,
+
+    prg_rom_size: usize,
+    chr_rom_banks: usize,
+
+    prg_rom: Vec<u8>,
+    chr_rom: Vec<u8>,
+
+    // 8 KB bank at PPU $0000-$1FFF
+    chr_bank_select: usize
+}
+
+impl Mapper3 {
+    pub fn new(mirroring_type: Mirroring, prg_rom_size: usize, chr_rom_banks: usize, prg_rom: Vec<u8>, chr_rom: Vec<u8>) -> Self {
+        Mapper3 {
+            mirroring_type: mirroring_type,
+
+            prg_rom_size: prg_rom_size,
+            chr_rom_banks: chr_rom_banks,
+
+            prg_rom: prg_rom,
+            chr_rom: chr_rom,
+
+            chr_bank_select: 0
+        }
+    }
+}
+
+/* https://www.nesdev.org/wiki/INES_Mapper_003 */
+impl MapperBase for Mapper3 {
+    fn reset(&mut self) {
+        self.chr_bank_select = 0;
+    }
+
+    fn cpu_read_byte(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => 0, // no PRG RAM on these boards
+            0x8000..=0xFFFF => self.prg_rom[mirror!(0x8000, addr, self.prg_rom_size)],
+            _ => panic!("Address out of bounds: {:04X}", addr)
+        }
+    }
+
+    fn cpu_write_byte(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF => {}
+            0x8000..=0xFFFF => {
+                if self.chr_rom_banks > 0 {
+                    self.chr_bank_select = (data as usize) % self.chr_rom_banks;
+                }
+            }
+            _ => panic!("Address out of bounds: {:04X}", addr)
+        }
+    }
+
+    fn ppu_read_byte(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x1FFF => self.chr_rom[self.chr_bank_select * CHR_ROM_BANK_SIZE + (addr as usize)],
+            _ => panic!("Address out of bounds: {:04X}", addr)
+        }
+    }
+
+    fn ppu_write_byte(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x0000..=0x1FFF => {
+                if self.chr_rom_banks == 0 {
+                    // chr ram write
+                    self.chr_rom[addr as usize] = data;
+                }
+            }
+            _ => panic!("Address out of bounds: {:04X}", addr)
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        return self.mirroring_type;
+    }
+
+    fn prg_rom(&self) -> &[u8] {
+        &self.prg_rom
+    }
+}
+
+impl Savable for Mapper3 {
+    fn save_state(&self, state: &mut Vec<u8>) {
+        let use_chr_ram = self.chr_rom_banks == 0;
+
+        state.write_u8(use_chr_ram as u8).expect("Unable to save u8");
+        if use_chr_ram {
+            for i in 0..self.chr_rom.len() {
+                state.write_u8(self.chr_rom[i]).expect("Unable to save u8");
+            }
+        }
+
+        state.write_u32::<LittleEndian>(self.chr_bank_select as u32).expect("Unable to save u32");
+    }
+
+    fn load_state(&mut self, state: &mut Cursor<Vec<u8>>) -> io::Result<()> {
+        let use_chr_ram = state.read_u8()? != 0;
+        if use_chr_ram {
+            for i in 0..self.chr_rom.len() {
+                self.chr_rom[i] = state.read_u8()?;
+            }
+        }
+
+        self.chr_bank_select = state.read_u32::<LittleEndian>()? as usize;
+
+        // with CHR RAM there's only the one bank
+        if self.chr_bank_select >= std::cmp::max(self.chr_rom_banks, 1) {
+            let message = format!("Invalid CHR ROM bank: {}", self.chr_bank_select);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+        }
+
+        Ok(())
+    }
+}
+
+impl Mapper for Mapper3 {}
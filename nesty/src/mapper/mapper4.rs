@@ -0,0 +1,379 @@
+use std::cell::RefCell;
+use std::rc::{Rc, Weak};
+use std::io::{self, Cursor};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::mapper::{Mirroring, Mapper, MapperBase, SRAM_SIZE};
+
+use crate::irq::{IrqLine, IrqSource};
+use crate::savable::Savable;
+use crate::{test_bit, mirror, box_array};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+pub struct Mapper4 {
+    irq: Weak<RefCell<IrqLine>>,
+
+    mirroring_type: Mirroring,
+
+    prg_banks: usize, /* number of 8 KB banks */
+    chr_banks: usize, /* number of 1 KB banks */
+    use_chr_ram: bool,
+
+    prg_rom: Vec<u8>,
+    chr_rom: Vec<u8>,
+
+    sram: Box<[u8; SRAM_SIZE]>,
+
+    /* Bank select ($8000-$9FFE, even)
+        7  bit  0
+        ---- ----
+        CPMx xRRR
+        |||   |||
+        |||   +++- Specify which bank register to update on next write to Bank Data register
+        |||          000: R0: Select 2 KB CHR bank at PPU $0000-$07FF (or $1000-$17FF)
+        |||          001: R1: Select 2 KB CHR bank at PPU $0800-$0FFF (or $1800-$1FFF)
+        |||          010: R2: Select 1 KB CHR bank at PPU $1000-$13FF (or $0000-$03FF)
+        |||          011: R3: Select 1 KB CHR bank at PPU $1400-$17FF (or $0400-$07FF)
+        |||          100: R4: Select 1 KB CHR bank at PPU $1800-$1BFF (or $0800-$0BFF)
+        |||          101: R5: Select 1 KB CHR bank at PPU $1C00-$1FFF (or $0C00-$0FFF)
+        |||          110: R6: Select 8 KB PRG ROM bank at $8000-$9FFF (or $C000-$DFFF)
+        |||          111: R7: Select 8 KB PRG ROM bank at $A000-$BFFF
+        ||+------- Nothing on the MMC3, see MMC6
+        |+-------- PRG ROM bank mode (0: $8000-$9FFF swappable, $C000-$DFFF fixed to second-last bank;
+        |                             1: $C000-$DFFF swappable, $8000-$9FFF fixed to second-last bank)
+        +--------- CHR A12 inversion (0: two 2 KB banks at $0000-$0FFF, four 1 KB banks at $1000-$1FFF;
+                                      1: two 2 KB banks at $1000-$1FFF, four 1 KB banks at $0000-$0FFF) */
+    bank_select: u8,
+    bank_registers: [usize; 8],
+
+    prg_ram_enabled: bool,
+    prg_ram_write_protect: bool,
+
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+
+    // last seen state of PPU address line A12
+    a12: bool
+}
+
+impl Mapper4 {
+    pub fn new(mirroring_type: Mirroring, prg_rom_banks: usize, chr_rom_banks: usize, prg_rom: Vec<u8>, chr_rom: Vec<u8>, irq: Weak<RefCell<IrqLine>>) -> Self {
+        Mapper4 {
+            irq: irq.clone(),
+
+            mirroring_type: mirroring_type,
+
+            prg_banks: prg_rom_banks * 2,
+            chr_banks: chr_rom.len() / CHR_BANK_SIZE,
+            use_chr_ram: chr_rom_banks == 0,
+
+            prg_rom: prg_rom,
+            chr_rom: chr_rom,
+
+            sram: box_array![0; SRAM_SIZE],
+
+            bank_select: 0,
+            bank_registers: [0, 2, 4, 5, 6, 7, 0, 1],
+
+            prg_ram_enabled: true,
+            prg_ram_write_protect: false,
+
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+
+            a12: false
+        }
+    }
+
+    fn irq(&self) -> Rc<RefCell<IrqLine>> {
+        self.irq.upgrade().expect("IRQ line lost for mapper")
+    }
+
+    fn prg_offset(&self, addr: u16) -> usize {
+        let second_last = self.prg_banks - 2;
+        let prg_mode = test_bit!(self.bank_select, 6);
+
+        let bank = match (addr, prg_mode) {
+            (0x8000..=0x9FFF, false) => self.bank_registers[6],
+            (0x8000..=0x9FFF, true) => second_last,
+            (0xA000..=0xBFFF, _) => self.bank_registers[7],
+            (0xC000..=0xDFFF, false) => second_last,
+            (0xC000..=0xDFFF, true) => self.bank_registers[6],
+            _ => self.prg_banks - 1
+        };
+
+        (bank % self.prg_banks) * PRG_BANK_SIZE + ((addr as usize) & (PRG_BANK_SIZE - 1))
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        // With A12 inversion the two halves of the pattern table are swapped
+        let addr = if test_bit!(self.bank_select, 7) { addr ^ 0x1000 } else { addr };
+
+        let bank = match addr {
+            0x0000..=0x07FF => (self.bank_registers[0] & !1) | ((addr as usize >> 10) & 1),
+            0x0800..=0x0FFF => (self.bank_registers[1] & !1) | ((addr as usize >> 10) & 1),
+            0x1000..=0x13FF => self.bank_registers[2],
+            0x1400..=0x17FF => self.bank_registers[3],
+            0x1800..=0x1BFF => self.bank_registers[4],
+            _ => self.bank_registers[5]
+        };
+
+        (bank % self.chr_banks) * CHR_BANK_SIZE + ((addr as usize) & (CHR_BANK_SIZE - 1))
+    }
+
+    /* https://www.nesdev.org/wiki/MMC3#IRQ_Specifics */
+    fn clock_irq_counter(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq().borrow_mut().assert(IrqSource::Mapper);
+        }
+    }
+}
+
+/* https://www.nesdev.org/wiki/MMC3 */
+impl MapperBase for Mapper4 {
+    fn reset(&mut self) {
+        self.bank_select = 0;
+        self.bank_registers = [0, 2, 4, 5, 6, 7, 0, 1];
+
+        self.prg_ram_enabled = true;
+        self.prg_ram_write_protect = false;
+
+        self.irq_latch = 0;
+        self.irq_counter = 0;
+        self.irq_reload = false;
+        self.irq_enabled = false;
+
+        self.a12 = false;
+    }
+
+    fn cpu_read_byte(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => {
+                if self.prg_ram_enabled {
+                    self.sram[mirror!(0x6000, addr, SRAM_SIZE)]
+                } else {
+                    0 // open bus
+                }
+            }
+            0x8000..=0xFFFF => self.prg_rom[self.prg_offset(addr)],
+            _ => panic!("Address out of bounds: {:04X}", addr)
+        }
+    }
+
+    fn cpu_write_byte(&mut self, addr: u16, data: u8) {
+        let even = !test_bit!(addr, 0);
+
+        match addr {
+            0x6000..=0x7FFF => {
+                if self.prg_ram_enabled && !self.prg_ram_write_protect {
+                    self.sram[mirror!(0x6000, addr, SRAM_SIZE)] = data;
+                }
+            }
+            /* Bank select / Bank data */
+            0x8000..=0x9FFF => {
+                if even {
+                    self.bank_select = data;
+                } else {
+                    self.bank_registers[(self.bank_select & 0b111) as usize] = data as usize;
+                }
+            }
+            /* Mirroring / PRG RAM protect */
+            0xA000..=0xBFFF => {
+                if even {
+                    // boards with four-screen VRAM ignore this register
+                    if !matches!(self.mirroring_type, Mirroring::FourScreen) {
+                        self.mirroring_type = if test_bit!(data, 0) {
+                            Mirroring::Horizontial
+                        } else {
+                            Mirroring::Vertical
+                        };
+                    }
+                } else {
+                    self.prg_ram_enabled = test_bit!(data, 7);
+                    self.prg_ram_write_protect = test_bit!(data, 6);
+                }
+            }
+            /* IRQ latch / IRQ reload */
+            0xC000..=0xDFFF => {
+                if even {
+                    self.irq_latch = data;
+                } else {
+                    self.irq_counter = 0;
+                    self.irq_reload = true;
+                }
+            }
+            /* IRQ disable / IRQ enable */
+            0xE000..=0xFFFF => {
+                if even {
+                    self.irq_enabled = false;
+                    self.irq().borrow_mut().acknowledge(IrqSource::Mapper);
+                } else {
+                    self.irq_enabled = true;
+                }
+            }
+            _ => panic!("Address out of bounds: {:04X}", addr)
+        }
+    }
+
+    fn ppu_read_byte(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x1FFF => self.chr_rom[self.chr_offset(addr)],
+            _ => panic!("Address out of bounds: {:04X}", addr)
+        }
+    }
+
+    fn ppu_write_byte(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x0000..=0x1FFF => {
+                if self.use_chr_ram {
+                    let offset = self.chr_offset(addr);
+                    self.chr_rom[offset] = data;
+                }
+            }
+            _ => panic!("Address out of bounds: {:04X}", addr)
+        }
+    }
+
+    // The scanline counter is clocked on rising edges of PPU A12, which normally happen once per scanline
+    // when the background and sprites use different pattern tables
+    fn notify_ppu_address(&mut self, addr: u16) {
+        let a12 = test_bit!(addr, 12);
+
+        if a12 && !self.a12 {
+            self.clock_irq_counter();
+        }
+
+        self.a12 = a12;
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        return self.mirroring_type;
+    }
+
+    fn prg_rom(&self) -> &[u8] {
+        &self.prg_rom
+    }
+
+    fn sram(&self) -> Option<&[u8]> {
+        Some(&self.sram[..])
+    }
+
+    fn sram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.sram[..])
+    }
+}
+
+impl Savable for Mapper4 {
+    fn save_state(&self, state: &mut Vec<u8>) {
+        match self.mirroring_type {
+            Mirroring::Vertical => {
+                state.write_u8(0).expect("Unable to save u8");
+            }
+            Mirroring::Horizontial => {
+                state.write_u8(1).expect("Unable to save u8");
+            }
+            Mirroring::SingleScreenLower => {
+                state.write_u8(2).expect("Unable to save u8");
+            }
+            Mirroring::SingleScreenUpper => {
+                state.write_u8(3).expect("Unable to save u8");
+            }
+            Mirroring::FourScreen => {
+                state.write_u8(4).expect("Unable to save u8");
+            }
+        }
+
+        if self.use_chr_ram {
+            for i in 0..self.chr_rom.len() {
+                state.write_u8(self.chr_rom[i]).expect("Unable to save u8");
+            }
+        }
+
+        for i in 0..SRAM_SIZE {
+            state.write_u8(self.sram[i]).expect("Unable to save u8");
+        }
+
+        state.write_u8(self.bank_select).expect("Unable to save u8");
+        for i in 0..8 {
+            state.write_u32::<LittleEndian>(self.bank_registers[i] as u32).expect("Unable to save u32");
+        }
+
+        state.write_u8(self.prg_ram_enabled as u8).expect("Unable to save u8");
+        state.write_u8(self.prg_ram_write_protect as u8).expect("Unable to save u8");
+
+        state.write_u8(self.irq_latch).expect("Unable to save u8");
+        state.write_u8(self.irq_counter).expect("Unable to save u8");
+        state.write_u8(self.irq_reload as u8).expect("Unable to save u8");
+        state.write_u8(self.irq_enabled as u8).expect("Unable to save u8");
+
+        state.write_u8(self.a12 as u8).expect("Unable to save u8");
+    }
+
+    fn load_state(&mut self, state: &mut Cursor<Vec<u8>>) -> io::Result<()> {
+        let mirroring = state.read_u8()?;
+        match mirroring {
+            0 => {
+                self.mirroring_type = Mirroring::Vertical;
+            }
+            1 => {
+                self.mirroring_type = Mirroring::Horizontial;
+            }
+            2 => {
+                self.mirroring_type = Mirroring::SingleScreenLower;
+            }
+            3 => {
+                self.mirroring_type = Mirroring::SingleScreenUpper;
+            }
+            4 => {
+                self.mirroring_type = Mirroring::FourScreen;
+            }
+            _ => {
+                let message = format!("Unknown byte when reading mirroring configuration: {}", mirroring);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+            }
+        }
+
+        if self.use_chr_ram {
+            for i in 0..self.chr_rom.len() {
+                self.chr_rom[i] = state.read_u8()?;
+            }
+        }
+
+        for i in 0..SRAM_SIZE {
+            self.sram[i] = state.read_u8()?;
+        }
+
+        self.bank_select = state.read_u8()?;
+        for i in 0..8 {
+            self.bank_registers[i] = state.read_u32::<LittleEndian>()? as usize;
+        }
+
+        self.prg_ram_enabled = state.read_u8()? != 0;
+        self.prg_ram_write_protect = state.read_u8()? != 0;
+
+        self.irq_latch = state.read_u8()?;
+        self.irq_counter = state.read_u8()?;
+        self.irq_reload = state.read_u8()? != 0;
+        self.irq_enabled = state.read_u8()? != 0;
+
+        self.a12 = state.read_u8()? != 0;
+
+        Ok(())
+    }
+}
+
+impl Mapper for Mapper4 {}
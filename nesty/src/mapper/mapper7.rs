@@ -0,0 +1,149 @@
+use std::io::{self, Cursor};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::mapper::{Mirroring, Mapper, MapperBase};
+
+use crate::savable::Savable;
+use crate::test_bit;
+
+const PRG_BANK_SIZE: usize = 0x8000;
+
+pub struct Mapper7 {
+    mirroring_type: Mirroring,
+
+    prg_banks: usize, /* number of 32 KB banks */
+    chr_rom_banks: usize,
+
+    prg_rom: Vec<u8>,
+    chr_rom: Vec<u8>,
+
+    // 32 KB bank at $8000-$FFFF
+    prg_bank_select: usize
+}
+
+impl Mapper7 {
+    pub fn new(prg_rom_banks: usize, chr_rom_banks: usize, prg_rom: Vec<u8>, chr_rom: Vec<u8>) -> Self {
+        Mapper7 {
+            // the nametable is selected by software; the header's mirroring bit is meaningless
+            mirroring_type: Mirroring::SingleScreenLower,
+
+            prg_banks: std::cmp::max(prg_rom_banks / 2, 1),
+            chr_rom_banks: chr_rom_banks,
+
+            prg_rom: prg_rom,
+            chr_rom: chr_rom,
+
+            prg_bank_select: 0
+        }
+    }
+}
+
+/* https://www.nesdev.org/wiki/AxROM */
+impl MapperBase for Mapper7 {
+    fn reset(&mut self) {
+        self.mirroring_type = Mirroring::SingleScreenLower;
+        self.prg_bank_select = 0;
+    }
+
+    fn cpu_read_byte(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => 0, // no PRG RAM on these boards
+            0x8000..=0xFFFF => self.prg_rom[(self.prg_bank_select * PRG_BANK_SIZE + ((addr as usize) & 0x7FFF)) % self.prg_rom.len()],
+            _ => panic!("Address out of bounds: {:04X}", addr)
+        }
+    }
+
+    fn cpu_write_byte(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF => {}
+            /*
+                7  bit  0
+                ---- ----
+                xxxM xPPP
+                   |  |||
+                   |  +++- Select 32 KB PRG ROM bank for CPU $8000-$FFFF
+                   +------ Select 1 KB VRAM page for all 4 nametables
+            */
+            0x8000..=0xFFFF => {
+                self.prg_bank_select = (data as usize & 0b00000111) % self.prg_banks;
+                self.mirroring_type = if test_bit!(data, 4) {
+                    Mirroring::SingleScreenUpper
+                } else {
+                    Mirroring::SingleScreenLower
+                };
+            }
+            _ => panic!("Address out of bounds: {:04X}", addr)
+        }
+    }
+
+    fn ppu_read_byte(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x1FFF => self.chr_rom[addr as usize],
+            _ => panic!("Address out of bounds: {:04X}", addr)
+        }
+    }
+
+    fn ppu_write_byte(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x0000..=0x1FFF => {
+                if self.chr_rom_banks == 0 {
+                    // chr ram write
+                    self.chr_rom[addr as usize] = data;
+                }
+            }
+            _ => panic!("Address out of bounds: {:04X}", addr)
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        return self.mirroring_type;
+    }
+
+    fn prg_rom(&self) -> &[u8] {
+        &self.prg_rom
+    }
+}
+
+impl Savable for Mapper7 {
+    fn save_state(&self, state: &mut Vec<u8>) {
+        state.write_u8(matches!(self.mirroring_type, Mirroring::SingleScreenUpper) as u8).expect("Unable to save u8");
+
+        let use_chr_ram = self.chr_rom_banks == 0;
+
+        state.write_u8(use_chr_ram as u8).expect("Unable to save u8");
+        if use_chr_ram {
+            for i in 0..self.chr_rom.len() {
+                state.write_u8(self.chr_rom[i]).expect("Unable to save u8");
+            }
+        }
+
+        state.write_u32::<LittleEndian>(self.prg_bank_select as u32).expect("Unable to save u32");
+    }
+
+    fn load_state(&mut self, state: &mut Cursor<Vec<u8>>) -> io::Result<()> {
+        self.mirroring_type = if state.read_u8()? != 0 {
+            Mirroring::SingleScreenUpper
+        } else {
+            Mirroring::SingleScreenLower
+        };
+
+        let use_chr_ram = state.read_u8()? != 0;
+        if use_chr_ram {
+            for i in 0..self.chr_rom.len() {
+                self.chr_rom[i] = state.read_u8()?;
+            }
+        }
+
+        self.prg_bank_select = state.read_u32::<LittleEndian>()? as usize;
+
+        if self.prg_bank_select >= self.prg_banks {
+            let message = format!("Invalid PRG ROM bank: {}", self.prg_bank_select);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+        }
+
+        Ok(())
+    }
+}
+
+impl Mapper for Mapper7 {}
@@ -2,6 +2,10 @@ use crate::savable::Savable;
 
 pub mod mapper0;
 pub mod mapper1;
+pub mod mapper2;
+pub mod mapper3;
+pub mod mapper4;
+pub mod mapper7;
 
 pub const PRG_ROM_BANK_SIZE: usize = 0x4000;
 pub const CHR_ROM_BANK_SIZE: usize = 0x2000;
@@ -11,7 +15,19 @@ const SRAM_SIZE: usize = 0x2000;
 #[derive(Copy, Clone)]
 pub enum Mirroring {
     Vertical,
-    Horizontial
+    Horizontial,
+    SingleScreenLower,
+    SingleScreenUpper,
+    FourScreen
+}
+
+/// Where a PPU access to $2000-$2FFF ends up.
+#[derive(Copy, Clone)]
+pub enum NametableTarget {
+    // one of the four 1 KB pages the PPU keeps (the console's 2 KB CIRAM plus the extra 2 KB used by four-screen boards)
+    Ciram(usize),
+    // handled by the mapper through nametable_read_byte/nametable_write_byte (CHR-ROM nametables, ExRAM, fill mode...)
+    Cartridge
 }
 
 pub trait MapperBase {
@@ -20,7 +36,76 @@ pub trait MapperBase {
     fn cpu_write_byte(&mut self, addr: u16, data: u8);
     fn ppu_read_byte(&self, addr: u16) -> u8;
     fn ppu_write_byte(&mut self, addr: u16, data: u8);
+
+    // Reads for debugging tools; mappers whose reads have side effects (e.g. latches triggered by fetches) must override these
+    fn cpu_peek_byte(&self, addr: u16) -> u8 {
+        self.cpu_read_byte(addr)
+    }
+
+    fn ppu_peek_byte(&self, addr: u16) -> u8 {
+        self.ppu_read_byte(addr)
+    }
+
+    // Called whenever the PPU puts a new address on its bus, for mappers which watch it (e.g. MMC3's A12 counter)
+    fn notify_ppu_address(&mut self, _addr: u16) {}
+
     fn mirroring(&self) -> Mirroring;
+
+    // The whole PRG ROM as found in the file, regardless of what's currently banked in
+    fn prg_rom(&self) -> &[u8];
+
+    // The PRG RAM at $6000-$7FFF, for boards which have any; kept across sessions when the cartridge has a battery
+    fn sram(&self) -> Option<&[u8]> {
+        None
+    }
+
+    fn sram_mut(&mut self) -> Option<&mut [u8]> {
+        None
+    }
+
+    // Decides where the nametable access at addr ($2000-$2FFF) goes; by default the CIRAM is laid out according to mirroring()
+    fn nametable_target(&self, addr: u16) -> NametableTarget {
+        let a = (addr as usize) & 0x0FFF;
+
+        match self.mirroring() {
+            /* $2000 equals $2800 and $2400 equals $2C00
+                    +---+---+
+                    | A | B |
+                    +---+---+
+                    | A | B |
+                    +---+---+                        */
+            Mirroring::Vertical => NametableTarget::Ciram((a >> 10) & 1),
+            /* $2000 equals $2400 and $2800 equals $2C00
+                    +---+---+
+                    | A | A |
+                    +---+---+
+                    | B | B |
+                    +---+---+                        */
+            Mirroring::Horizontial => NametableTarget::Ciram((a >> 11) & 1),
+            /* All four nametables refer to the same 1 KB page of VRAM
+                    +---+---+
+                    | A | A |
+                    +---+---+
+                    | A | A |
+                    +---+---+                        */
+            Mirroring::SingleScreenLower => NametableTarget::Ciram(0),
+            Mirroring::SingleScreenUpper => NametableTarget::Ciram(1),
+            /* The cartridge provides the extra 2 KB of VRAM, so every nametable is unique
+                    +---+---+
+                    | A | B |
+                    +---+---+
+                    | C | D |
+                    +---+---+                        */
+            Mirroring::FourScreen => NametableTarget::Ciram(a >> 10)
+        }
+    }
+
+    // Only called for addresses which nametable_target() sent to NametableTarget::Cartridge
+    fn nametable_read_byte(&self, _addr: u16) -> u8 {
+        0
+    }
+
+    fn nametable_write_byte(&mut self, _addr: u16, _data: u8) {}
 }
 
 pub trait Mapper: MapperBase + Savable {}
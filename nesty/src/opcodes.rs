@@ -0,0 +1,301 @@
+use crate::m6502::AddressingMode;
+
+/// What the CPU does for a given opcode byte.
+#[derive(Clone, Copy, Debug)]
+pub struct Opcode {
+    pub mnemonic: &'static str,
+    pub mode: AddressingMode,
+    pub official: bool
+}
+
+const fn op(mnemonic: &'static str, mode: AddressingMode, official: bool) -> Opcode {
+    Opcode {
+        mnemonic: mnemonic,
+        mode: mode,
+        official: official
+    }
+}
+
+// Number of bytes taken by an instruction (opcode included) in the given addressing mode
+pub fn instruction_length(mode: AddressingMode) -> u16 {
+    match mode {
+        AddressingMode::Accumulator | AddressingMode::Implied => 1,
+        AddressingMode::Immediate |
+        AddressingMode::IndirectX |
+        AddressingMode::IndirectY |
+        AddressingMode::IndirectYEc |
+        AddressingMode::Relative |
+        AddressingMode::ZeroPage |
+        AddressingMode::ZeroPageX |
+        AddressingMode::ZeroPageY => 2,
+        AddressingMode::Absolute |
+        AddressingMode::AbsoluteX |
+        AddressingMode::AbsoluteXEc |
+        AddressingMode::AbsoluteY |
+        AddressingMode::AbsoluteYEc |
+        AddressingMode::Indirect => 3
+    }
+}
+
+/*
+Mnemonics of the unofficial opcodes follow nestest.log (ISB instead of ISC, etc.)
+https://www.nesdev.org/wiki/CPU_unofficial_opcodes
+*/
+pub const OPCODES: [Opcode; 256] = [
+    /* 00 */ op("BRK", AddressingMode::Implied, true),
+    /* 01 */ op("ORA", AddressingMode::IndirectX, true),
+    /* 02 */ op("KIL", AddressingMode::Implied, false),
+    /* 03 */ op("SLO", AddressingMode::IndirectX, false),
+    /* 04 */ op("NOP", AddressingMode::ZeroPage, false),
+    /* 05 */ op("ORA", AddressingMode::ZeroPage, true),
+    /* 06 */ op("ASL", AddressingMode::ZeroPage, true),
+    /* 07 */ op("SLO", AddressingMode::ZeroPage, false),
+    /* 08 */ op("PHP", AddressingMode::Implied, true),
+    /* 09 */ op("ORA", AddressingMode::Immediate, true),
+    /* 0A */ op("ASL", AddressingMode::Accumulator, true),
+    /* 0B */ op("ANC", AddressingMode::Immediate, false),
+    /* 0C */ op("NOP", AddressingMode::Absolute, false),
+    /* 0D */ op("ORA", AddressingMode::Absolute, true),
+    /* 0E */ op("ASL", AddressingMode::Absolute, true),
+    /* 0F */ op("SLO", AddressingMode::Absolute, false),
+    /* 10 */ op("BPL", AddressingMode::Relative, true),
+    /* 11 */ op("ORA", AddressingMode::IndirectY, true),
+    /* 12 */ op("KIL", AddressingMode::Implied, false),
+    /* 13 */ op("SLO", AddressingMode::IndirectY, false),
+    /* 14 */ op("NOP", AddressingMode::ZeroPageX, false),
+    /* 15 */ op("ORA", AddressingMode::ZeroPageX, true),
+    /* 16 */ op("ASL", AddressingMode::ZeroPageX, true),
+    /* 17 */ op("SLO", AddressingMode::ZeroPageX, false),
+    /* 18 */ op("CLC", AddressingMode::Implied, true),
+    /* 19 */ op("ORA", AddressingMode::AbsoluteY, true),
+    /* 1A */ op("NOP", AddressingMode::Implied, false),
+    /* 1B */ op("SLO", AddressingMode::AbsoluteY, false),
+    /* 1C */ op("NOP", AddressingMode::AbsoluteX, false),
+    /* 1D */ op("ORA", AddressingMode::AbsoluteX, true),
+    /* 1E */ op("ASL", AddressingMode::AbsoluteX, true),
+    /* 1F */ op("SLO", AddressingMode::AbsoluteX, false),
+    /* 20 */ op("JSR", AddressingMode::Absolute, true),
+    /* 21 */ op("AND", AddressingMode::IndirectX, true),
+    /* 22 */ op("KIL", AddressingMode::Implied, false),
+    /* 23 */ op("RLA", AddressingMode::IndirectX, false),
+    /* 24 */ op("BIT", AddressingMode::ZeroPage, true),
+    /* 25 */ op("AND", AddressingMode::ZeroPage, true),
+    /* 26 */ op("ROL", AddressingMode::ZeroPage, true),
+    /* 27 */ op("RLA", AddressingMode::ZeroPage, false),
+    /* 28 */ op("PLP", AddressingMode::Implied, true),
+    /* 29 */ op("AND", AddressingMode::Immediate, true),
+    /* 2A */ op("ROL", AddressingMode::Accumulator, true),
+    /* 2B */ op("ANC", AddressingMode::Immediate, false),
+    /* 2C */ op("BIT", AddressingMode::Absolute, true),
+    /* 2D */ op("AND", AddressingMode::Absolute, true),
+    /* 2E */ op("ROL", AddressingMode::Absolute, true),
+    /* 2F */ op("RLA", AddressingMode::Absolute, false),
+    /* 30 */ op("BMI", AddressingMode::Relative, true),
+    /* 31 */ op("AND", AddressingMode::IndirectY, true),
+    /* 32 */ op("KIL", AddressingMode::Implied, false),
+    /* 33 */ op("RLA", AddressingMode::IndirectY, false),
+    /* 34 */ op("NOP", AddressingMode::ZeroPageX, false),
+    /* 35 */ op("AND", AddressingMode::ZeroPageX, true),
+    /* 36 */ op("ROL", AddressingMode::ZeroPageX, true),
+    /* 37 */ op("RLA", AddressingMode::ZeroPageX, false),
+    /* 38 */ op("SEC", AddressingMode::Implied, true),
+    /* 39 */ op("AND", AddressingMode::AbsoluteY, true),
+    /* 3A */ op("NOP", AddressingMode::Implied, false),
+    /* 3B */ op("RLA", AddressingMode::AbsoluteY, false),
+    /* 3C */ op("NOP", AddressingMode::AbsoluteX, false),
+    /* 3D */ op("AND", AddressingMode::AbsoluteX, true),
+    /* 3E */ op("ROL", AddressingMode::AbsoluteX, true),
+    /* 3F */ op("RLA", AddressingMode::AbsoluteX, false),
+    /* 40 */ op("RTI", AddressingMode::Implied, true),
+    /* 41 */ op("EOR", AddressingMode::IndirectX, true),
+    /* 42 */ op("KIL", AddressingMode::Implied, false),
+    /* 43 */ op("SRE", AddressingMode::IndirectX, false),
+    /* 44 */ op("NOP", AddressingMode::ZeroPage, false),
+    /* 45 */ op("EOR", AddressingMode::ZeroPage, true),
+    /* 46 */ op("LSR", AddressingMode::ZeroPage, true),
+    /* 47 */ op("SRE", AddressingMode::ZeroPage, false),
+    /* 48 */ op("PHA", AddressingMode::Implied, true),
+    /* 49 */ op("EOR", AddressingMode::Immediate, true),
+    /* 4A */ op("LSR", AddressingMode::Accumulator, true),
+    /* 4B */ op("ALR", AddressingMode::Immediate, false),
+    /* 4C */ op("JMP", AddressingMode::Absolute, true),
+    /* 4D */ op("EOR", AddressingMode::Absolute, true),
+    /* 4E */ op("LSR", AddressingMode::Absolute, true),
+    /* 4F */ op("SRE", AddressingMode::Absolute, false),
+    /* 50 */ op("BVC", AddressingMode::Relative, true),
+    /* 51 */ op("EOR", AddressingMode::IndirectY, true),
+    /* 52 */ op("KIL", AddressingMode::Implied, false),
+    /* 53 */ op("SRE", AddressingMode::IndirectY, false),
+    /* 54 */ op("NOP", AddressingMode::ZeroPageX, false),
+    /* 55 */ op("EOR", AddressingMode::ZeroPageX, true),
+    /* 56 */ op("LSR", AddressingMode::ZeroPageX, true),
+    /* 57 */ op("SRE", AddressingMode::ZeroPageX, false),
+    /* 58 */ op("CLI", AddressingMode::Implied, true),
+    /* 59 */ op("EOR", AddressingMode::AbsoluteY, true),
+    /* 5A */ op("NOP", AddressingMode::Implied, false),
+    /* 5B */ op("SRE", AddressingMode::AbsoluteY, false),
+    /* 5C */ op("NOP", AddressingMode::AbsoluteX, false),
+    /* 5D */ op("EOR", AddressingMode::AbsoluteX, true),
+    /* 5E */ op("LSR", AddressingMode::AbsoluteX, true),
+    /* 5F */ op("SRE", AddressingMode::AbsoluteX, false),
+    /* 60 */ op("RTS", AddressingMode::Implied, true),
+    /* 61 */ op("ADC", AddressingMode::IndirectX, true),
+    /* 62 */ op("KIL", AddressingMode::Implied, false),
+    /* 63 */ op("RRA", AddressingMode::IndirectX, false),
+    /* 64 */ op("NOP", AddressingMode::ZeroPage, false),
+    /* 65 */ op("ADC", AddressingMode::ZeroPage, true),
+    /* 66 */ op("ROR", AddressingMode::ZeroPage, true),
+    /* 67 */ op("RRA", AddressingMode::ZeroPage, false),
+    /* 68 */ op("PLA", AddressingMode::Implied, true),
+    /* 69 */ op("ADC", AddressingMode::Immediate, true),
+    /* 6A */ op("ROR", AddressingMode::Accumulator, true),
+    /* 6B */ op("ARR", AddressingMode::Immediate, false),
+    /* 6C */ op("JMP", AddressingMode::Indirect, true),
+    /* 6D */ op("ADC", AddressingMode::Absolute, true),
+    /* 6E */ op("ROR", AddressingMode::Absolute, true),
+    /* 6F */ op("RRA", AddressingMode::Absolute, false),
+    /* 70 */ op("BVS", AddressingMode::Relative, true),
+    /* 71 */ op("ADC", AddressingMode::IndirectY, true),
+    /* 72 */ op("KIL", AddressingMode::Implied, false),
+    /* 73 */ op("RRA", AddressingMode::IndirectY, false),
+    /* 74 */ op("NOP", AddressingMode::ZeroPageX, false),
+    /* 75 */ op("ADC", AddressingMode::ZeroPageX, true),
+    /* 76 */ op("ROR", AddressingMode::ZeroPageX, true),
+    /* 77 */ op("RRA", AddressingMode::ZeroPageX, false),
+    /* 78 */ op("SEI", AddressingMode::Implied, true),
+    /* 79 */ op("ADC", AddressingMode::AbsoluteY, true),
+    /* 7A */ op("NOP", AddressingMode::Implied, false),
+    /* 7B */ op("RRA", AddressingMode::AbsoluteY, false),
+    /* 7C */ op("NOP", AddressingMode::AbsoluteX, false),
+    /* 7D */ op("ADC", AddressingMode::AbsoluteX, true),
+    /* 7E */ op("ROR", AddressingMode::AbsoluteX, true),
+    /* 7F */ op("RRA", AddressingMode::AbsoluteX, false),
+    /* 80 */ op("NOP", AddressingMode::Immediate, false),
+    /* 81 */ op("STA", AddressingMode::IndirectX, true),
+    /* 82 */ op("NOP", AddressingMode::Immediate, false),
+    /* 83 */ op("SAX", AddressingMode::IndirectX, false),
+    /* 84 */ op("STY", AddressingMode::ZeroPage, true),
+    /* 85 */ op("STA", AddressingMode::ZeroPage, true),
+    /* 86 */ op("STX", AddressingMode::ZeroPage, true),
+    /* 87 */ op("SAX", AddressingMode::ZeroPage, false),
+    /* 88 */ op("DEY", AddressingMode::Implied, true),
+    /* 89 */ op("NOP", AddressingMode::Immediate, false),
+    /* 8A */ op("TXA", AddressingMode::Implied, true),
+    /* 8B */ op("XAA", AddressingMode::Immediate, false),
+    /* 8C */ op("STY", AddressingMode::Absolute, true),
+    /* 8D */ op("STA", AddressingMode::Absolute, true),
+    /* 8E */ op("STX", AddressingMode::Absolute, true),
+    /* 8F */ op("SAX", AddressingMode::Absolute, false),
+    /* 90 */ op("BCC", AddressingMode::Relative, true),
+    /* 91 */ op("STA", AddressingMode::IndirectY, true),
+    /* 92 */ op("KIL", AddressingMode::Implied, false),
+    /* 93 */ op("SHA", AddressingMode::IndirectY, false),
+    /* 94 */ op("STY", AddressingMode::ZeroPageX, true),
+    /* 95 */ op("STA", AddressingMode::ZeroPageX, true),
+    /* 96 */ op("STX", AddressingMode::ZeroPageY, true),
+    /* 97 */ op("SAX", AddressingMode::ZeroPageY, false),
+    /* 98 */ op("TYA", AddressingMode::Implied, true),
+    /* 99 */ op("STA", AddressingMode::AbsoluteY, true),
+    /* 9A */ op("TXS", AddressingMode::Implied, true),
+    /* 9B */ op("TAS", AddressingMode::AbsoluteY, false),
+    /* 9C */ op("SHY", AddressingMode::AbsoluteX, false),
+    /* 9D */ op("STA", AddressingMode::AbsoluteX, true),
+    /* 9E */ op("SHX", AddressingMode::AbsoluteY, false),
+    /* 9F */ op("SHA", AddressingMode::AbsoluteY, false),
+    /* A0 */ op("LDY", AddressingMode::Immediate, true),
+    /* A1 */ op("LDA", AddressingMode::IndirectX, true),
+    /* A2 */ op("LDX", AddressingMode::Immediate, true),
+    /* A3 */ op("LAX", AddressingMode::IndirectX, false),
+    /* A4 */ op("LDY", AddressingMode::ZeroPage, true),
+    /* A5 */ op("LDA", AddressingMode::ZeroPage, true),
+    /* A6 */ op("LDX", AddressingMode::ZeroPage, true),
+    /* A7 */ op("LAX", AddressingMode::ZeroPage, false),
+    /* A8 */ op("TAY", AddressingMode::Implied, true),
+    /* A9 */ op("LDA", AddressingMode::Immediate, true),
+    /* AA */ op("TAX", AddressingMode::Implied, true),
+    /* AB */ op("LAX", AddressingMode::Immediate, false),
+    /* AC */ op("LDY", AddressingMode::Absolute, true),
+    /* AD */ op("LDA", AddressingMode::Absolute, true),
+    /* AE */ op("LDX", AddressingMode::Absolute, true),
+    /* AF */ op("LAX", AddressingMode::Absolute, false),
+    /* B0 */ op("BCS", AddressingMode::Relative, true),
+    /* B1 */ op("LDA", AddressingMode::IndirectY, true),
+    /* B2 */ op("KIL", AddressingMode::Implied, false),
+    /* B3 */ op("LAX", AddressingMode::IndirectY, false),
+    /* B4 */ op("LDY", AddressingMode::ZeroPageX, true),
+    /* B5 */ op("LDA", AddressingMode::ZeroPageX, true),
+    /* B6 */ op("LDX", AddressingMode::ZeroPageY, true),
+    /* B7 */ op("LAX", AddressingMode::ZeroPageY, false),
+    /* B8 */ op("CLV", AddressingMode::Implied, true),
+    /* B9 */ op("LDA", AddressingMode::AbsoluteY, true),
+    /* BA */ op("TSX", AddressingMode::Implied, true),
+    /* BB */ op("LAS", AddressingMode::AbsoluteY, false),
+    /* BC */ op("LDY", AddressingMode::AbsoluteX, true),
+    /* BD */ op("LDA", AddressingMode::AbsoluteX, true),
+    /* BE */ op("LDX", AddressingMode::AbsoluteY, true),
+    /* BF */ op("LAX", AddressingMode::AbsoluteY, false),
+    /* C0 */ op("CPY", AddressingMode::Immediate, true),
+    /* C1 */ op("CMP", AddressingMode::IndirectX, true),
+    /* C2 */ op("NOP", AddressingMode::Immediate, false),
+    /* C3 */ op("DCP", AddressingMode::IndirectX, false),
+    /* C4 */ op("CPY", AddressingMode::ZeroPage, true),
+    /* C5 */ op("CMP", AddressingMode::ZeroPage, true),
+    /* C6 */ op("DEC", AddressingMode::ZeroPage, true),
+    /* C7 */ op("DCP", AddressingMode::ZeroPage, false),
+    /* C8 */ op("INY", AddressingMode::Implied, true),
+    /* C9 */ op("CMP", AddressingMode::Immediate, true),
+    /* CA */ op("DEX", AddressingMode::Implied, true),
+    /* CB */ op("AXS", AddressingMode::Immediate, false),
+    /* CC */ op("CPY", AddressingMode::Absolute, true),
+    /* CD */ op("CMP", AddressingMode::Absolute, true),
+    /* CE */ op("DEC", AddressingMode::Absolute, true),
+    /* CF */ op("DCP", AddressingMode::Absolute, false),
+    /* D0 */ op("BNE", AddressingMode::Relative, true),
+    /* D1 */ op("CMP", AddressingMode::IndirectY, true),
+    /* D2 */ op("KIL", AddressingMode::Implied, false),
+    /* D3 */ op("DCP", AddressingMode::IndirectY, false),
+    /* D4 */ op("NOP", AddressingMode::ZeroPageX, false),
+    /* D5 */ op("CMP", AddressingMode::ZeroPageX, true),
+    /* D6 */ op("DEC", AddressingMode::ZeroPageX, true),
+    /* D7 */ op("DCP", AddressingMode::ZeroPageX, false),
+    /* D8 */ op("CLD", AddressingMode::Implied, true),
+    /* D9 */ op("CMP", AddressingMode::AbsoluteY, true),
+    /* DA */ op("NOP", AddressingMode::Implied, false),
+    /* DB */ op("DCP", AddressingMode::AbsoluteY, false),
+    /* DC */ op("NOP", AddressingMode::AbsoluteX, false),
+    /* DD */ op("CMP", AddressingMode::AbsoluteX, true),
+    /* DE */ op("DEC", AddressingMode::AbsoluteX, true),
+    /* DF */ op("DCP", AddressingMode::AbsoluteX, false),
+    /* E0 */ op("CPX", AddressingMode::Immediate, true),
+    /* E1 */ op("SBC", AddressingMode::IndirectX, true),
+    /* E2 */ op("NOP", AddressingMode::Immediate, false),
+    /* E3 */ op("ISB", AddressingMode::IndirectX, false),
+    /* E4 */ op("CPX", AddressingMode::ZeroPage, true),
+    /* E5 */ op("SBC", AddressingMode::ZeroPage, true),
+    /* E6 */ op("INC", AddressingMode::ZeroPage, true),
+    /* E7 */ op("ISB", AddressingMode::ZeroPage, false),
+    /* E8 */ op("INX", AddressingMode::Implied, true),
+    /* E9 */ op("SBC", AddressingMode::Immediate, true),
+    /* EA */ op("NOP", AddressingMode::Implied, true),
+    /* EB */ op("SBC", AddressingMode::Immediate, false),
+    /* EC */ op("CPX", AddressingMode::Absolute, true),
+    /* ED */ op("SBC", AddressingMode::Absolute, true),
+    /* EE */ op("INC", AddressingMode::Absolute, true),
+    /* EF */ op("ISB", AddressingMode::Absolute, false),
+    /* F0 */ op("BEQ", AddressingMode::Relative, true),
+    /* F1 */ op("SBC", AddressingMode::IndirectY, true),
+    /* F2 */ op("KIL", AddressingMode::Implied, false),
+    /* F3 */ op("ISB", AddressingMode::IndirectY, false),
+    /* F4 */ op("NOP", AddressingMode::ZeroPageX, false),
+    /* F5 */ op("SBC", AddressingMode::ZeroPageX, true),
+    /* F6 */ op("INC", AddressingMode::ZeroPageX, true),
+    /* F7 */ op("ISB", AddressingMode::ZeroPageX, false),
+    /* F8 */ op("SED", AddressingMode::Implied, true),
+    /* F9 */ op("SBC", AddressingMode::AbsoluteY, true),
+    /* FA */ op("NOP", AddressingMode::Implied, false),
+    /* FB */ op("ISB", AddressingMode::AbsoluteY, false),
+    /* FC */ op("NOP", AddressingMode::AbsoluteX, false),
+    /* FD */ op("SBC", AddressingMode::AbsoluteX, true),
+    /* FE */ op("INC", AddressingMode::AbsoluteX, true),
+    /* FF */ op("ISB", AddressingMode::AbsoluteX, false),
+];
@@ -1,11 +1,11 @@
 use std::cell::RefCell;
 use std::rc::{Rc, Weak};
-use std::io::Cursor;
+use std::io::{self, Cursor, Write};
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 
 use crate::cartridge::Cartridge;
-use crate::mapper::Mirroring;
+use crate::mapper::NametableTarget;
 use crate::io::IO;
 use crate::savable::Savable;
 
@@ -230,6 +230,66 @@ impl PPU {
         self.nmi = false;
     }
 
+    /* The reset button leaves VRAM, OAM and v alone. https://www.nesdev.org/wiki/PPU_power_up_state */
+    pub fn soft_reset(&mut self) {
+        self.control.set_raw(0);
+        self.mask.set_raw(0);
+
+        self.prev_data = 0;
+
+        self.temp_vram_address.set_raw(0);
+        self.fine_x = 0;
+        self.addr_latch = false;
+
+        self.odd_frame = false;
+    }
+
+    /* -1 is the pre-render scanline */
+    pub fn scanline(&self) -> i32 {
+        self.scanline
+    }
+
+    pub fn cycle(&self) -> u32 {
+        self.cycle
+    }
+
+    /* PPUCTRL and PPUMASK are write-only, so debuggers read them from here */
+    pub fn control(&self) -> u8 {
+        self.control.raw()
+    }
+
+    pub fn mask(&self) -> u8 {
+        self.mask.raw()
+    }
+
+    // Internal scroll registers: v, t, fine x and the shared $2005/$2006 write latch (w)
+    pub fn vram_address(&self) -> u16 {
+        self.vram_address.raw()
+    }
+
+    pub fn temp_vram_address(&self) -> u16 {
+        self.temp_vram_address.raw()
+    }
+
+    pub fn fine_x(&self) -> u8 {
+        self.fine_x
+    }
+
+    pub fn addr_latch(&self) -> bool {
+        self.addr_latch
+    }
+
+    /* The current frame as a binary PPM (P6) image, which needs no encoder and opens almost anywhere */
+    pub fn write_ppm<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write!(writer, "P6\n{} {}\n255\n", WIDTH, HEIGHT)?;
+
+        for pixel in self.pixels.chunks(4) {
+            writer.write_all(&pixel[0..3])?;
+        }
+
+        Ok(())
+    }
+
     pub fn tick(&mut self) {
         match self.scanline {
             -1..=239 => { /* Pre render + visible scanline */
@@ -277,6 +337,18 @@ impl PPU {
                 if self.cycle == 328 || self.cycle == 336 {
                     self.inc_scrollx();
                 }
+
+                // The scanline renderer reads all the pattern data at once, so tell the cartridge when a real PPU would
+                // switch between pattern tables: sprites are fetched from cycle 257 and the background from cycle 321
+                if self.rendering_on() && (self.cycle == 257 || self.cycle == 321) {
+                    let pattstart = if self.cycle == 257 {
+                        if self.control.sprite_size() || self.control.sprite_pattern() { PT1_START } else { PT0_START }
+                    } else {
+                        if self.control.bkgd_pattern() { PT1_START } else { PT0_START }
+                    };
+
+                    self.cart().borrow_mut().notify_ppu_address(pattstart);
+                }
             }
             240 => {      /* Post render scanline */
             }
@@ -636,6 +708,7 @@ impl PPU {
 
                 data = self.prev_data; // reads from nametable are delayed by one cycle
                 self.prev_data = self.read_byte(addr);
+                self.cart().borrow_mut().notify_ppu_address(addr);
 
                 // the current address was in the palette range
                 if addr >= 0x3F00 {
@@ -650,6 +723,58 @@ impl PPU {
         data
     }
 
+    // What reading the register would return, without clearing vblank or moving the VRAM address
+    pub fn peek_register(&self, register: usize) -> u8 {
+        match register {
+            0x2 => { // PPU STATUS
+                let mut data = self.prev_data;
+                modify_bit!(data, 5, self.status.sprite_overflow());
+                modify_bit!(data, 6, self.status.sprite_zero_hit());
+                modify_bit!(data, 7, self.status.vblank());
+                data
+            }
+            0x4 => self.oam[self.oam_addr as usize], // OAM DATA
+            0x7 => { // PPU DATA
+                let addr = self.vram_address.raw() & 0x3FFF;
+
+                // only palette reads skip the read buffer
+                if addr >= 0x3F00 {
+                    self.peek_byte(addr)
+                } else {
+                    self.prev_data
+                }
+            }
+            _ => 0
+        }
+    }
+
+    // Reads the PPU address space without side effects
+    pub fn peek_byte(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x1FFF => self.cart().borrow().peek_byte(addr),
+            0x2000..=0x3EFF => {
+                let addr = 0x2000 + (mirror!(0x2000, addr, NAMETABLE_SIZE * 4) as u16);
+                let target = self.cart().borrow().nametable_target(addr);
+
+                match target {
+                    NametableTarget::Ciram(page) => self.nametable[page][mirror!(0x2000, addr, NAMETABLE_SIZE)],
+                    NametableTarget::Cartridge => self.cart().borrow().nametable_read_byte(addr)
+                }
+            }
+            0x3F00..=0x3FFF => {
+                let addr = mirror!(0x3F00, addr, PALETTE_RAM_SIZE);
+
+                // Addresses $3F10/$3F14/$3F18/$3F1C are mirrors of $3F00/$3F04/$3F08/$3F0C
+                if addr % 0x04 == 0 {
+                    self.palette_ram[addr & 0b00001111]
+                } else {
+                    self.palette_ram[addr]
+                }
+            }
+            _ => panic!("Address out of bounds: {:04X}", addr)
+        }
+    }
+
     pub fn write_register(&mut self, register: usize, data: u8) {
         match register {
             0x0 => { // PPU CONTROL
@@ -703,6 +828,7 @@ impl PPU {
                                                    (data as u16));
                     // v: <...all bits...> <- t: <...all bits...>
                     self.vram_address.set_raw(self.temp_vram_address.raw());
+                    self.cart().borrow_mut().notify_ppu_address(self.vram_address.raw());
                 }
 
                 self.addr_latch = !self.addr_latch;
@@ -711,6 +837,7 @@ impl PPU {
                 let mut addr: u16 = self.vram_address.raw();
 
                 self.write_byte(addr, data);
+                self.cart().borrow_mut().notify_ppu_address(addr);
 
                 addr += if self.control.vram_increment_downwards() { 32 } else { 1 };
                 self.vram_address.set_raw(addr);
@@ -784,49 +911,7 @@ impl IO for PPU {
     fn read_byte(&mut self, addr: u16) -> u8 {
         match addr {
             0x0000..=0x1FFF => self.cart().borrow_mut().read_byte(addr),
-            0x2000..=0x3EFF => {
-                let a = mirror!(0x2000, addr, NAMETABLE_SIZE * 4);
-                let nt_addr = mirror!(0x2000, addr, NAMETABLE_SIZE);
-
-                match self.cart().borrow().mirroring() {
-                    /* $2000 equals $2800 and $2400 equals $2C00
-                            +---+---+
-                            | A | B |
-                            +---+---+
-                            | A | B |
-                            +---+---+                        */
-                    Mirroring::Vertical => {
-                        if (a >= 0x000 && a < 0x400) || (a >= 0x800 && a < 0xC00) {
-                            return self.nametable[0][nt_addr];
-                        } else {
-                            return self.nametable[1][nt_addr];
-                        }
-                    }
-                    /* $2000 equals $2400 and $2800 equals $2C00
-                            +---+---+
-                            | A | A |
-                            +---+---+
-                            | B | B |
-                            +---+---+                        */
-                    Mirroring::Horizontial => {
-                        if a >= 0x000 && a < 0x800 {
-                            return self.nametable[0][nt_addr];
-                        } else {
-                            return self.nametable[1][nt_addr];
-                        }
-                    }
-                }
-            }
-            0x3F00..=0x3FFF => {
-                let addr = mirror!(0x3F00, addr, PALETTE_RAM_SIZE);
-
-                // Addresses $3F10/$3F14/$3F18/$3F1C are mirrors of $3F00/$3F04/$3F08/$3F0C
-                if addr % 0x04 == 0 {
-                    return self.palette_ram[addr & 0b00001111];
-                } else {
-                    return self.palette_ram[addr];
-                }
-            }
+            0x2000..=0x3FFF => self.peek_byte(addr),
             _ => panic!("Address out of bounds: {:04X}", addr)
         }
     }
@@ -843,35 +928,15 @@ impl IO for PPU {
                 self.cart().borrow_mut().write_byte(addr, data);
             }
             0x2000..=0x3EFF => {
-                let a = mirror!(0x2000, addr, NAMETABLE_SIZE * 4);
-                let nt_addr = mirror!(0x2000, addr, NAMETABLE_SIZE);
-
-                match self.cart().borrow().mirroring() {
-                    /* $2000 equals $2800 and $2400 equals $2C00
-                            +---+---+
-                            | A | B |
-                            +---+---+
-                            | A | B |
-                            +---+---+                        */
-                    Mirroring::Vertical => {
-                        if (a >= 0x000 && a < 0x400) || (a >= 0x800 && a < 0xC00) {
-                            self.nametable[0][nt_addr] = data;
-                        } else {
-                            self.nametable[1][nt_addr] = data;
-                        }
+                let addr = 0x2000 + (mirror!(0x2000, addr, NAMETABLE_SIZE * 4) as u16);
+                let target = self.cart().borrow().nametable_target(addr);
+
+                match target {
+                    NametableTarget::Ciram(page) => {
+                        self.nametable[page][mirror!(0x2000, addr, NAMETABLE_SIZE)] = data;
                     }
-                    /* $2000 equals $2400 and $2800 equals $2C00
-                            +---+---+
-                            | A | A |
-                            +---+---+
-                            | B | B |
-                            +---+---+                        */
-                    Mirroring::Horizontial => {
-                        if a >= 0x000 && a < 0x800 {
-                            self.nametable[0][nt_addr] = data;
-                        } else {
-                            self.nametable[1][nt_addr] = data;
-                        }
+                    NametableTarget::Cartridge => {
+                        self.cart().borrow_mut().nametable_write_byte(addr, data);
                     }
                 }
             }
@@ -928,35 +993,37 @@ impl Savable for PPU {
         state.write_u8(self.nmi as u8).expect("Unable to save u8");
     }
 
-    fn load_state(&mut self, state: &mut Cursor<Vec<u8>>) {
+    fn load_state(&mut self, state: &mut Cursor<Vec<u8>>) -> io::Result<()> {
         for nt in 0..4 {
             for i in 0..NAMETABLE_SIZE {
-                self.nametable[nt][i] = state.read_u8().expect("Unable to load u8");
+                self.nametable[nt][i] = state.read_u8()?;
             }
         }
         for i in 0..PALETTE_RAM_SIZE {
-            self.palette_ram[i] = state.read_u8().expect("Unable to load u8");
+            self.palette_ram[i] = state.read_u8()?;
         }
 
-        self.control.set_raw(state.read_u8().expect("Unable to load u8"));
-        self.mask.set_raw(state.read_u8().expect("Unable to load u8"));
-        self.status.set_raw(state.read_u8().expect("Unable to load u8"));
+        self.control.set_raw(state.read_u8()?);
+        self.mask.set_raw(state.read_u8()?);
+        self.status.set_raw(state.read_u8()?);
 
-        self.oam_addr = state.read_u8().expect("Unable to load u8");
-        self.prev_data = state.read_u8().expect("Unable to load u8");
+        self.oam_addr = state.read_u8()?;
+        self.prev_data = state.read_u8()?;
         for i in 0..OAM_SIZE {
-            self.oam[i] = state.read_u8().expect("Unable to load u8");
+            self.oam[i] = state.read_u8()?;
         }
 
-        self.vram_address.set_raw(state.read_u16::<LittleEndian>().expect("Unable to load u16"));
-        self.temp_vram_address.set_raw(state.read_u16::<LittleEndian>().expect("Unable to load u16"));
-        self.fine_x = state.read_u8().expect("Unable to load u8");
-        self.addr_latch = state.read_u8().expect("Unable to load u8") != 0;
+        self.vram_address.set_raw(state.read_u16::<LittleEndian>()?);
+        self.temp_vram_address.set_raw(state.read_u16::<LittleEndian>()?);
+        self.fine_x = state.read_u8()?;
+        self.addr_latch = state.read_u8()? != 0;
+
+        self.scanline = state.read_i32::<LittleEndian>()?;
+        self.cycle = state.read_u32::<LittleEndian>()?;
+        self.odd_frame = state.read_u8()? != 0;
 
-        self.scanline = state.read_i32::<LittleEndian>().expect("Unable to load i32");
-        self.cycle = state.read_u32::<LittleEndian>().expect("Unable to load u32");
-        self.odd_frame = state.read_u8().expect("Unable to load u8") != 0;
+        self.nmi = state.read_u8()? != 0;
 
-        self.nmi = state.read_u8().expect("Unable to load u8") != 0;
+        Ok(())
     }
 }
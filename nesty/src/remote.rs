@@ -0,0 +1,332 @@
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Cursor, ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+
+use crate::emulator::Emulator;
+use crate::debugger::{BreakpointKind, Condition, StopReason, parse_hex, parse_ppu_register, parse_range};
+
+use crate::io::IO;
+
+/*
+A small line based debug protocol, so scripts and editor plugins can drive the emulator from outside.
+
+The server only listens on 127.0.0.1 and talks to one client at a time. Every request is one line and gets
+exactly one line back, starting with "ok" or "error". When the emulator stops on its own (breakpoint, finished step,
+...) an unsolicited "stopped <reason>" line is sent.
+
+    read ADDR [LEN]          ok 4C F5 C5        (bytes as the CPU sees them, without side effects)
+    write ADDR BYTE...       ok                 (goes through the bus, so register writes do happen)
+    regs                     ok A=00 X=00 Y=00 P=24 SP=FD PC=C000 CYC=7 SCANLINE=0 DOT=21
+    setreg REG VALUE         ok                 (A, X, Y, P, SP or PC)
+    break exec ADDR [if COND]
+    break read|write A[-B] [if COND]
+    break ppuread|ppuwrite REG [if COND]
+                             ok ID
+    delete ID                ok
+    breakpoints              ok 0:exec:C000 1:write:0300-03FF ...
+    step | next | out | frame | scanline N | continue
+                             ok                 (then "stopped ..." once it gets there)
+    pause                    ok
+    screenshot PATH          ok                 (binary PPM)
+    savestate PATH           ok
+    loadstate PATH           ok
+    reset                    ok
+
+Numbers are hex except for scanlines and breakpoint ids. Conditions use the debugger syntax, e.g. "A>=$80".
+The emulator starts paused once a client connects, so nothing runs away before breakpoints are set.
+*/
+pub struct RemoteServer {
+    listener: TcpListener,
+    client: Option<Client>,
+
+    paused: bool
+}
+
+struct Client {
+    stream: TcpStream,
+    buffer: Vec<u8>
+}
+
+impl RemoteServer {
+    pub fn bind(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        listener.set_nonblocking(true)?;
+
+        Ok(RemoteServer {
+            listener: listener,
+            client: None,
+
+            paused: false
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /* Frontends skip emulation while this is set, but keep polling */
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn connected(&self) -> bool {
+        self.client.is_some()
+    }
+
+    // Accepts a client and runs whatever requests arrived since the last call; never blocks
+    pub fn poll(&mut self, emu: &mut Emulator) {
+        if self.client.is_none() {
+            if let Ok((stream, _)) = self.listener.accept() {
+                if stream.set_nonblocking(true).is_ok() {
+                    self.client = Some(Client { stream: stream, buffer: Vec::new() });
+                    self.paused = true;
+                }
+            }
+        }
+
+        let lines = match self.client.as_mut() {
+            Some(client) => client.read_lines(),
+            None => return
+        };
+
+        let lines = match lines {
+            Ok(lines) => lines,
+            Err(_) => {
+                self.disconnect(emu);
+                return;
+            }
+        };
+
+        for line in lines {
+            let response = match self.handle(emu, line.trim()) {
+                Ok(response) if response.is_empty() => "ok".to_string(),
+                Ok(response) => format!("ok {}", response),
+                Err(err) => format!("error {}", err)
+            };
+
+            self.send(emu, &response);
+        }
+    }
+
+    /* Called by the frontend when Emulator::update/tick stopped */
+    pub fn stopped(&mut self, emu: &mut Emulator, reason: StopReason) {
+        self.paused = true;
+
+        let reason = match reason {
+            StopReason::Breakpoint(id) => format!("breakpoint {}", id),
+            StopReason::Step => "step".to_string(),
+            StopReason::Scanline(scanline) => format!("scanline {}", scanline),
+            StopReason::Frame => "frame".to_string(),
+            StopReason::Halted => "halted".to_string()
+        };
+
+        self.send(emu, &format!("stopped {} pc={:04X}", reason, emu.cpu().pc()));
+    }
+
+    fn send(&mut self, emu: &mut Emulator, line: &str) {
+        let result = match self.client.as_mut() {
+            Some(client) => client.write_line(line),
+            None => return
+        };
+
+        if result.is_err() {
+            self.disconnect(emu);
+        }
+    }
+
+    // Whatever the client set up goes away with it, and the game carries on
+    fn disconnect(&mut self, emu: &mut Emulator) {
+        self.client = None;
+        self.paused = false;
+
+        emu.debugger().clear_breakpoints();
+        emu.debugger().resume();
+    }
+
+    fn handle(&mut self, emu: &mut Emulator, line: &str) -> Result<String, String> {
+        let (line, condition) = match line.find(" if ") {
+            Some(i) => (&line[..i], Some(line[(i + 4)..].trim().parse::<Condition>()?)),
+            None => (line, None)
+        };
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let arg = |i: usize| words.get(i).copied().ok_or_else(|| "Missing argument".to_string());
+
+        if condition.is_some() && words.first() != Some(&"break") {
+            return Err("Only breakpoints take a condition".to_string());
+        }
+
+        match words.first().copied().unwrap_or("") {
+            "read" => {
+                let addr: u16 = parse_hex(arg(1)?)?;
+                let len: u16 = if words.len() > 2 { parse_hex(arg(2)?)? } else { 1 };
+
+                let bus = emu.bus();
+                let bytes: Vec<String> = (0..len).map(|i| format!("{:02X}", bus.peek_byte(addr.wrapping_add(i)))).collect();
+                Ok(bytes.join(" "))
+            }
+            "write" => {
+                let addr: u16 = parse_hex(arg(1)?)?;
+                let data = words[2..]
+                    .iter()
+                    .map(|word| parse_hex::<u8>(word))
+                    .collect::<Result<Vec<u8>, String>>()?;
+
+                let mut bus = emu.bus();
+                for (i, byte) in data.iter().enumerate() {
+                    bus.write_byte(addr.wrapping_add(i as u16), *byte);
+                }
+                Ok(String::new())
+            }
+            "regs" => {
+                let cpu = emu.cpu();
+                let ppu = emu.ppu();
+                Ok(format!(
+                    "A={:02X} X={:02X} Y={:02X} P={:02X} SP={:02X} PC={:04X} CYC={} SCANLINE={} DOT={}",
+                    cpu.a(), cpu.x(), cpu.y(), cpu.p(), cpu.sp(), cpu.pc(), cpu.total_cycles, ppu.scanline(), ppu.cycle()
+                ))
+            }
+            "setreg" => {
+                let value: u16 = parse_hex(arg(2)?)?;
+                let mut cpu = emu.cpu();
+
+                match arg(1)?.to_uppercase().as_str() {
+                    "PC" => cpu.set_pc(value),
+                    register => {
+                        let value = u8::try_from(value).map_err(|_| format!("{} is 8 bit", register))?;
+                        match register {
+                            "A" => cpu.set_a(value),
+                            "X" => cpu.set_x(value),
+                            "Y" => cpu.set_y(value),
+                            "P" => cpu.set_p(value),
+                            "SP" => cpu.set_sp(value),
+                            _ => return Err(format!("Unknown register '{}'", register))
+                        }
+                    }
+                }
+                Ok(String::new())
+            }
+            "break" => {
+                let kind = match arg(1)? {
+                    "exec" => BreakpointKind::Execute(parse_hex(arg(2)?)?),
+                    "read" => {
+                        let (start, end) = parse_range(arg(2)?)?;
+                        BreakpointKind::Read { start: start, end: end }
+                    }
+                    "write" => {
+                        let (start, end) = parse_range(arg(2)?)?;
+                        BreakpointKind::Write { start: start, end: end }
+                    }
+                    "ppuread" => BreakpointKind::PpuRegisterRead(parse_ppu_register(arg(2)?)?),
+                    "ppuwrite" => BreakpointKind::PpuRegisterWrite(parse_ppu_register(arg(2)?)?),
+                    other => return Err(format!("Unknown breakpoint type '{}'", other))
+                };
+                Ok(emu.debugger().add_breakpoint(kind, condition).to_string())
+            }
+            "delete" => {
+                let id = arg(1)?.parse::<usize>().map_err(|_| "Bad breakpoint id".to_string())?;
+                if emu.debugger().remove_breakpoint(id) {
+                    Ok(String::new())
+                } else {
+                    Err(format!("No breakpoint {}", id))
+                }
+            }
+            "breakpoints" => {
+                let list: Vec<String> = emu.debugger()
+                    .breakpoints()
+                    .iter()
+                    .map(|bp| format!("{}:{}", bp.id, describe(bp.kind)))
+                    .collect();
+                Ok(list.join(" "))
+            }
+            "step" => self.resume(|| emu.step_into()),
+            "next" => self.resume(|| emu.step_over()),
+            "out" => self.resume(|| emu.step_out()),
+            "frame" => self.resume(|| emu.run_to_next_frame()),
+            "scanline" => {
+                let scanline = arg(1)?.parse::<i32>().map_err(|_| "Bad scanline".to_string())?;
+                self.resume(|| emu.run_to_scanline(scanline))
+            }
+            "continue" => self.resume(|| emu.debugger().resume()),
+            "pause" => {
+                emu.debugger().resume();
+                self.paused = true;
+                Ok(String::new())
+            }
+            "screenshot" => {
+                let mut file = BufWriter::new(File::create(arg(1)?).map_err(|err| err.to_string())?);
+                emu.ppu().write_ppm(&mut file).map_err(|err| err.to_string())?;
+                Ok(String::new())
+            }
+            "savestate" => {
+                let mut state = Vec::new();
+                emu.save_state(&mut state);
+                fs::write(arg(1)?, state).map_err(|err| err.to_string())?;
+                Ok(String::new())
+            }
+            "loadstate" => {
+                let state = fs::read(arg(1)?).map_err(|err| err.to_string())?;
+                emu.load_state(&mut Cursor::new(state)).map_err(|err| err.to_string())?;
+                Ok(String::new())
+            }
+            "reset" => {
+                emu.reset();
+                Ok(String::new())
+            }
+            "" => Err("Empty request".to_string()),
+            other => Err(format!("Unknown request '{}'", other))
+        }
+    }
+
+    fn resume<F: FnOnce()>(&mut self, arm: F) -> Result<String, String> {
+        arm();
+        self.paused = false;
+        Ok(String::new())
+    }
+}
+
+// Same words the break request uses
+fn describe(kind: BreakpointKind) -> String {
+    match kind {
+        BreakpointKind::Execute(addr) => format!("exec:{:04X}", addr),
+        BreakpointKind::Read { start, end } => format!("read:{:04X}-{:04X}", start, end),
+        BreakpointKind::Write { start, end } => format!("write:{:04X}-{:04X}", start, end),
+        BreakpointKind::PpuRegisterRead(register) => format!("ppuread:{}", register),
+        BreakpointKind::PpuRegisterWrite(register) => format!("ppuwrite:{}", register)
+    }
+}
+
+impl Client {
+    // Complete lines received so far; Err when the client went away
+    fn read_lines(&mut self) -> io::Result<Vec<String>> {
+        let mut chunk = [0u8; 1024];
+
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Err(io::Error::new(ErrorKind::UnexpectedEof, "Client disconnected")),
+                Ok(n) => self.buffer.extend_from_slice(&chunk[..n]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err)
+            }
+        }
+
+        let mut lines = Vec::new();
+
+        while let Some(i) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=i).collect();
+            lines.push(String::from_utf8_lossy(&line).into_owned());
+        }
+
+        Ok(lines)
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        // replies are small, so just wait for the socket instead of buffering them
+        self.stream.set_nonblocking(false)?;
+        let result = writeln!(self.stream, "{}", line);
+        self.stream.set_nonblocking(true)?;
+        result
+    }
+}
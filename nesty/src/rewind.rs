@@ -0,0 +1,197 @@
+use std::collections::VecDeque;
+use std::io::Cursor;
+
+use crate::emulator::Emulator;
+use crate::rle;
+
+// Every this many snapshots a full one is kept, the ones in between only store what changed since it
+const KEYFRAME_INTERVAL: usize = 60;
+
+// Memory is freed a keyframe and its deltas at a time, so they shouldn't take up more than this share of it
+const MAX_GROUP_SHARE: usize = 4;
+
+struct Snapshot {
+    keyframe: bool,
+    data: Vec<u8> /* run length encoded; for deltas it's the snapshot XORed with its keyframe, so mostly zeros */
+}
+
+/*
+Rewind history: a snapshot of the console every few frames, kept in a ring which drops the oldest ones once
+it uses more than the given amount of memory. The frontend calls capture() after every frame it runs, and
+step_back() once per frame while the rewind key is held.
+*/
+pub struct Rewind {
+    interval: u32,
+    max_bytes: usize,
+
+    snapshots: VecDeque<Snapshot>,
+    used_bytes: usize,
+    frames: u32,
+
+    rom_hash: u64,
+    keyframe: Option<Vec<u8>> /* the newest keyframe decoded, to make deltas against */
+}
+
+impl Rewind {
+    pub fn new(interval: u32, max_bytes: usize) -> Self {
+        Rewind {
+            interval: interval.max(1),
+            max_bytes: max_bytes,
+
+            snapshots: VecDeque::new(),
+            used_bytes: 0,
+            frames: 0,
+
+            rom_hash: 0,
+            keyframe: None
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+        self.used_bytes = 0;
+        self.frames = 0;
+        self.keyframe = None;
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    pub fn used_bytes(&self) -> usize {
+        self.used_bytes
+    }
+
+    /* Call at the end of every frame; only every interval-th call takes a snapshot */
+    pub fn capture(&mut self, emu: &Emulator) {
+        // history of another game is no use
+        if emu.rom_hash() != self.rom_hash {
+            self.clear();
+            self.rom_hash = emu.rom_hash();
+        }
+
+        self.frames += 1;
+        if self.frames < self.interval {
+            return;
+        }
+        self.frames = 0;
+
+        let mut state = Vec::new();
+        emu.save_snapshot(&mut state);
+
+        let since_keyframe = self.snapshots.iter().rev().position(|snapshot| snapshot.keyframe);
+        let group_bytes: usize = match since_keyframe {
+            Some(n) => self.snapshots.iter().rev().take(n + 1).map(|snapshot| snapshot.data.len()).sum(),
+            None => 0
+        };
+
+        let use_delta = matches!(since_keyframe, Some(n) if n + 1 < KEYFRAME_INTERVAL)
+            && group_bytes < self.max_bytes / MAX_GROUP_SHARE
+            && self.decode_keyframe();
+
+        let delta = match &self.keyframe {
+            Some(keyframe) if use_delta && keyframe.len() == state.len() => {
+                Some(state.iter().zip(keyframe.iter()).map(|(a, b)| a ^ b).collect::<Vec<u8>>())
+            }
+            _ => None
+        };
+
+        let snapshot = match delta {
+            Some(delta) => Snapshot { keyframe: false, data: rle::encode(&delta) },
+            None => {
+                let data = rle::encode(&state);
+                self.keyframe = Some(state);
+                Snapshot { keyframe: true, data: data }
+            }
+        };
+
+        self.used_bytes += snapshot.data.len();
+        self.snapshots.push_back(snapshot);
+
+        // the oldest keyframe goes together with its deltas, but the newest one stays no matter what
+        while self.used_bytes > self.max_bytes && self.snapshots.iter().filter(|snapshot| snapshot.keyframe).count() > 1 {
+            self.pop_front();
+            while self.snapshots.front().is_some_and(|snapshot| !snapshot.keyframe) {
+                self.pop_front();
+            }
+        }
+    }
+
+    /*
+    Goes back one snapshot (interval frames). The PPU's picture isn't part of a snapshot, so an older one is restored
+    and run up to the snapshot we went back to, which draws the screen on the way. A frame of update() doesn't line
+    up with the PPU's, so that takes two frames to cover the whole picture.
+    Returns false once there's no more history.
+    */
+    pub fn step_back(&mut self, emu: &mut Emulator) -> bool {
+        let replay = 2usize.div_ceil(self.interval as usize); /* snapshots to run through */
+
+        if self.snapshots.len() < replay + 2 || emu.rom_hash() != self.rom_hash {
+            return false;
+        }
+
+        self.pop_back();
+
+        let state = match self.decode(self.snapshots.len() - 1 - replay) {
+            Some(state) => state,
+            None => {
+                self.clear();
+                return false;
+            }
+        };
+        emu.load_snapshot(&mut Cursor::new(state)).expect("Unable to restore rewind snapshot");
+
+        for _i in 0..(replay * self.interval as usize) {
+            // stop reasons are for debuggers, not for us
+            while emu.update().is_some() {}
+        }
+
+        self.frames = 0;
+        true
+    }
+
+    fn pop_front(&mut self) {
+        if let Some(snapshot) = self.snapshots.pop_front() {
+            self.used_bytes -= snapshot.data.len();
+        }
+    }
+
+    fn pop_back(&mut self) {
+        if let Some(snapshot) = self.snapshots.pop_back() {
+            self.used_bytes -= snapshot.data.len();
+
+            if snapshot.keyframe {
+                self.keyframe = None;
+            }
+        }
+    }
+
+    // Makes sure self.keyframe holds the newest keyframe, which is dropped whenever step_back() goes past it
+    fn decode_keyframe(&mut self) -> bool {
+        if self.keyframe.is_none() {
+            self.keyframe = self.snapshots.iter()
+                .rev()
+                .find(|snapshot| snapshot.keyframe)
+                .and_then(|snapshot| rle::decode(&snapshot.data));
+        }
+
+        self.keyframe.is_some()
+    }
+
+    fn decode(&self, index: usize) -> Option<Vec<u8>> {
+        let snapshot = &self.snapshots[index];
+        if snapshot.keyframe {
+            return rle::decode(&snapshot.data);
+        }
+
+        let keyframe = self.snapshots.range(..index).rev().find(|snapshot| snapshot.keyframe)?;
+        let keyframe = rle::decode(&keyframe.data)?;
+        let delta = rle::decode(&snapshot.data)?;
+
+        Some(keyframe.iter().zip(delta.iter()).map(|(a, b)| a ^ b).collect())
+    }
+}
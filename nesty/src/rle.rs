@@ -0,0 +1,70 @@
+/*
+Run length encoding for save states, which are mostly long stretches of zeros (empty RAM, unused nametables, SRAM...).
+The data is a sequence of packets, each starting with a control byte n:
+
+    n < $80     the next n + 1 bytes are copied as they are
+    n >= $80    the next byte is repeated n - $80 + 3 times
+
+so a run costs 2 bytes for up to 130 bytes, and data without runs grows by 1 byte in 128.
+*/
+const MAX_LITERAL: usize = 0x80;
+const MIN_RUN: usize = 3;
+const MAX_RUN: usize = 0x7F + MIN_RUN;
+
+pub fn encode(data: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(data.len() / 4);
+    let mut literal_start = 0;
+    let mut i = 0;
+
+    while i < data.len() {
+        let mut run = 1;
+        while i + run < data.len() && run < MAX_RUN && data[i + run] == data[i] {
+            run += 1;
+        }
+
+        if run >= MIN_RUN {
+            flush_literals(&mut encoded, &data[literal_start..i]);
+
+            encoded.push((0x80 + run - MIN_RUN) as u8);
+            encoded.push(data[i]);
+
+            i += run;
+            literal_start = i;
+        } else {
+            i += run;
+        }
+    }
+
+    flush_literals(&mut encoded, &data[literal_start..]);
+    encoded
+}
+
+fn flush_literals(encoded: &mut Vec<u8>, literals: &[u8]) {
+    for chunk in literals.chunks(MAX_LITERAL) {
+        encoded.push((chunk.len() - 1) as u8);
+        encoded.extend_from_slice(chunk);
+    }
+}
+
+// None if the data ends in the middle of a packet
+pub fn decode(encoded: &[u8]) -> Option<Vec<u8>> {
+    let mut data = Vec::with_capacity(encoded.len() * 4);
+    let mut i = 0;
+
+    while i < encoded.len() {
+        let n = encoded[i] as usize;
+        i += 1;
+
+        if n < 0x80 {
+            let literals = encoded.get(i..(i + n + 1))?;
+            data.extend_from_slice(literals);
+            i += n + 1;
+        } else {
+            let byte = *encoded.get(i)?;
+            data.resize(data.len() + n - 0x80 + MIN_RUN, byte);
+            i += 1;
+        }
+    }
+
+    Some(data)
+}
@@ -1,6 +1,7 @@
-use std::io::Cursor;
+use std::io::{self, Cursor};
 
+// Components write their fields in a fixed order and read them back the same way; see state.rs for the file around them
 pub trait Savable {
     fn save_state(&self, state: &mut Vec<u8>);
-    fn load_state(&mut self, state: &mut Cursor<Vec<u8>>);
+    fn load_state(&mut self, state: &mut Cursor<Vec<u8>>) -> io::Result<()>;
 }
@@ -0,0 +1,175 @@
+use std::io::{self, Cursor, Read};
+use std::fmt;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::savable::Savable;
+use crate::rle;
+
+/*
+Save state file layout (all numbers little endian):
+
+    "NSTY"          magic
+    u16             format version, bumped whenever any component changes what it writes
+    u64             hash of the ROM file the state belongs to (Cartridge::rom_hash)
+    u16             mapper number
+    u16             number of chunks
+    chunks          4 byte tag, u32 length, then the component's data run length encoded (see rle.rs)
+
+Whatever comes after the last chunk is left alone, so frontends can append their own data.
+*/
+pub const MAGIC: [u8; 4] = *b"NSTY";
+pub const VERSION: u16 = 2;
+
+pub type ChunkTag = [u8; 4];
+
+/// Reasons why a save state can't be loaded. The emulator is left untouched in every case.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StateError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    WrongRom { expected: u64, found: u64 },
+    WrongMapper { expected: u16, found: u16 },
+    Truncated,
+    MissingChunk(ChunkTag),
+    CorruptChunk(ChunkTag)
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "File is not a nesty save state"),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "Save state version {} is not supported (expected {})", version, VERSION)
+            }
+            StateError::WrongRom { expected, found } => {
+                write!(f, "Save state belongs to another game (ROM hash {:016x}, loaded {:016x})", found, expected)
+            }
+            StateError::WrongMapper { expected, found } => {
+                write!(f, "Save state is for mapper {} but the game uses mapper {}", found, expected)
+            }
+            StateError::Truncated => write!(f, "Save state is truncated"),
+            StateError::MissingChunk(tag) => write!(f, "Save state has no {} chunk", String::from_utf8_lossy(tag)),
+            StateError::CorruptChunk(tag) => write!(f, "Save state has a corrupt {} chunk", String::from_utf8_lossy(tag))
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+pub(crate) struct StateWriter<'a> {
+    state: &'a mut Vec<u8>,
+    count_pos: usize,
+    chunks: u16
+}
+
+impl<'a> StateWriter<'a> {
+    pub fn new(state: &'a mut Vec<u8>, rom_hash: u64, mapper: u16) -> Self {
+        state.extend_from_slice(&MAGIC);
+        state.write_u16::<LittleEndian>(VERSION).expect("Unable to save u16");
+        state.write_u64::<LittleEndian>(rom_hash).expect("Unable to save u64");
+        state.write_u16::<LittleEndian>(mapper).expect("Unable to save u16");
+
+        let count_pos = state.len();
+        state.write_u16::<LittleEndian>(0).expect("Unable to save u16"); // filled in by finish()
+
+        StateWriter {
+            state: state,
+            count_pos: count_pos,
+            chunks: 0
+        }
+    }
+
+    pub fn chunk(&mut self, tag: &ChunkTag, component: &dyn Savable) {
+        self.chunk_with(tag, |state| component.save_state(state));
+    }
+
+    pub fn chunk_with<F: FnOnce(&mut Vec<u8>)>(&mut self, tag: &ChunkTag, save: F) {
+        let mut data = Vec::new();
+        save(&mut data);
+        let data = rle::encode(&data);
+
+        self.state.extend_from_slice(tag);
+        self.state.write_u32::<LittleEndian>(data.len() as u32).expect("Unable to save u32");
+        self.state.extend_from_slice(&data);
+        self.chunks += 1;
+    }
+
+    pub fn finish(self) {
+        self.state[self.count_pos..(self.count_pos + 2)].copy_from_slice(&self.chunks.to_le_bytes());
+    }
+}
+
+pub(crate) struct StateReader {
+    chunks: Vec<(ChunkTag, Vec<u8>)>
+}
+
+impl StateReader {
+    // Checks the header and splits the state into chunks; nothing is loaded yet
+    pub fn parse(state: &mut Cursor<Vec<u8>>, rom_hash: u64, mapper: u16) -> Result<Self, StateError> {
+        let mut magic = [0; 4];
+        state.read_exact(&mut magic).map_err(|_| StateError::BadMagic)?;
+        if magic != MAGIC {
+            return Err(StateError::BadMagic);
+        }
+
+        let version = state.read_u16::<LittleEndian>().map_err(|_| StateError::Truncated)?;
+        if version != VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+
+        let found = state.read_u64::<LittleEndian>().map_err(|_| StateError::Truncated)?;
+        if found != rom_hash {
+            return Err(StateError::WrongRom { expected: rom_hash, found: found });
+        }
+
+        let found = state.read_u16::<LittleEndian>().map_err(|_| StateError::Truncated)?;
+        if found != mapper {
+            return Err(StateError::WrongMapper { expected: mapper, found: found });
+        }
+
+        let count = state.read_u16::<LittleEndian>().map_err(|_| StateError::Truncated)?;
+        let mut chunks = Vec::new();
+
+        for _i in 0..count {
+            let mut tag = [0; 4];
+            state.read_exact(&mut tag).map_err(|_| StateError::Truncated)?;
+
+            let len = state.read_u32::<LittleEndian>().map_err(|_| StateError::Truncated)? as usize;
+            let remaining = state.get_ref().len().saturating_sub(state.position() as usize);
+            if len > remaining {
+                return Err(StateError::Truncated);
+            }
+
+            let mut data = vec![0; len];
+            state.read_exact(&mut data).map_err(|_| StateError::Truncated)?;
+            chunks.push((tag, data));
+        }
+
+        Ok(StateReader {
+            chunks: chunks
+        })
+    }
+
+    // The component has to use up the chunk exactly, anything else means the data doesn't belong to it
+    pub fn load(&self, tag: &ChunkTag, component: &mut dyn Savable) -> Result<(), StateError> {
+        self.load_with(tag, |state| component.load_state(state))
+    }
+
+    pub fn load_with<F: FnOnce(&mut Cursor<Vec<u8>>) -> io::Result<()>>(&self, tag: &ChunkTag, load: F) -> Result<(), StateError> {
+        let encoded = self.chunks.iter()
+            .find(|(t, _)| t == tag)
+            .map(|(_, data)| data)
+            .ok_or(StateError::MissingChunk(*tag))?;
+
+        let data = rle::decode(encoded).ok_or(StateError::CorruptChunk(*tag))?;
+
+        let len = data.len() as u64;
+        let mut cursor = Cursor::new(data);
+
+        match load(&mut cursor) {
+            Ok(_) if cursor.position() == len => Ok(()),
+            _ => Err(StateError::CorruptChunk(*tag))
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+
+use crate::bus::Bus;
+use crate::m6502::{M6502, AddressingMode};
+use crate::disasm::{self, Instruction};
+
+/*
+Records every instruction before it executes, in the same layout as nestest.log:
+
+C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+
+Lines either go straight to a writer or are kept in a ring buffer holding only the last N of them,
+which can be dumped after something went wrong.
+*/
+pub struct Tracer {
+    writer: Option<Box<dyn Write>>,
+
+    lines: VecDeque<String>,
+    capacity: usize
+}
+
+impl Tracer {
+    pub fn new<W: Write + 'static>(writer: W) -> Self {
+        Tracer {
+            writer: Some(Box::new(writer)),
+
+            lines: VecDeque::new(),
+            capacity: 0
+        }
+    }
+
+    pub fn ring_buffer(capacity: usize) -> Self {
+        Tracer {
+            writer: None,
+
+            lines: VecDeque::with_capacity(capacity),
+            capacity: capacity
+        }
+    }
+
+    pub fn record(&mut self, line: String) {
+        if let Some(writer) = self.writer.as_mut() {
+            // a broken sink shouldn't bring the emulator down
+            let _ = writeln!(writer, "{}", line);
+            return;
+        }
+
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.lines.len() == self.capacity {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line);
+    }
+
+    /* Lines kept in ring buffer mode, oldest first */
+    pub fn lines(&self) -> impl Iterator<Item = &String> {
+        self.lines.iter()
+    }
+
+    pub fn dump<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for line in self.lines.iter() {
+            writeln!(writer, "{}", line)?;
+        }
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        match self.writer.as_mut() {
+            Some(writer) => writer.flush(),
+            None => Ok(())
+        }
+    }
+}
+
+// Formats the instruction at the cpu's PC, reading operands through the bus without side effects
+pub fn format_line(cpu: &M6502, bus: &Bus, scanline: i32, dot: u32) -> String {
+    let pc = cpu.pc();
+    let instruction = disasm::decode(bus, pc);
+
+    let bytes = instruction.bytes
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<String>>()
+        .join(" ");
+
+    let operand = format_operand(cpu, bus, &instruction);
+    let disasm = if operand.is_empty() {
+        instruction.mnemonic.to_string()
+    } else {
+        format!("{} {}", instruction.mnemonic, operand)
+    };
+
+    format!(
+        "{:04X}  {:<8} {}{:<32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+        pc,
+        bytes,
+        if instruction.official { ' ' } else { '*' },
+        disasm,
+        cpu.a(),
+        cpu.x(),
+        cpu.y(),
+        cpu.p(),
+        cpu.sp(),
+        if scanline < 0 { 261 } else { scanline }, // nestest.log numbers the pre-render line 261
+        dot,
+        cpu.total_cycles
+    )
+}
+
+// nestest.log (made with Nintendulator) doesn't show the contents of the PPU and APU registers
+fn peek_memory(bus: &Bus, addr: u16) -> u8 {
+    match addr {
+        0x2000..=0x401F => 0xFF,
+        _ => bus.peek_byte(addr)
+    }
+}
+
+// Like Instruction::operand_text, plus the effective address and the value found there
+fn format_operand(cpu: &M6502, bus: &Bus, instruction: &Instruction) -> String {
+    let nn = instruction.operand as u8;
+    let nnnn = instruction.operand;
+
+    let peek_word_zp = |addr: u8| -> u16 {
+        (bus.peek_byte(addr as u16) as u16) | ((bus.peek_byte(addr.wrapping_add(1) as u16) as u16) << 8)
+    };
+
+    match instruction.mode {
+        AddressingMode::Implied => String::new(),
+        AddressingMode::Accumulator => "A".to_string(),
+        AddressingMode::Immediate => format!("#${:02X}", nn),
+        AddressingMode::ZeroPage => format!("${:02X} = {:02X}", nn, bus.peek_byte(nn as u16)),
+        AddressingMode::ZeroPageX => {
+            let addr = nn.wrapping_add(cpu.x());
+            format!("${:02X},X @ {:02X} = {:02X}", nn, addr, peek_memory(bus, addr as u16))
+        }
+        AddressingMode::ZeroPageY => {
+            let addr = nn.wrapping_add(cpu.y());
+            format!("${:02X},Y @ {:02X} = {:02X}", nn, addr, peek_memory(bus, addr as u16))
+        }
+        AddressingMode::Absolute => {
+            if instruction.mnemonic == "JMP" || instruction.mnemonic == "JSR" {
+                format!("${:04X}", nnnn)
+            } else {
+                format!("${:04X} = {:02X}", nnnn, peek_memory(bus, nnnn))
+            }
+        }
+        AddressingMode::AbsoluteX | AddressingMode::AbsoluteXEc => {
+            let addr = nnnn.wrapping_add(cpu.x() as u16);
+            format!("${:04X},X @ {:04X} = {:02X}", nnnn, addr, peek_memory(bus, addr))
+        }
+        AddressingMode::AbsoluteY | AddressingMode::AbsoluteYEc => {
+            let addr = nnnn.wrapping_add(cpu.y() as u16);
+            format!("${:04X},Y @ {:04X} = {:02X}", nnnn, addr, peek_memory(bus, addr))
+        }
+        AddressingMode::Indirect => {
+            // the pointer's high byte is fetched without crossing the page
+            let lo = bus.peek_byte(nnnn) as u16;
+            let hi = bus.peek_byte((nnnn & 0xFF00) | (nnnn.wrapping_add(1) & 0xFF)) as u16;
+            format!("(${:04X}) = {:04X}", nnnn, (hi << 8) | lo)
+        }
+        AddressingMode::IndirectX => {
+            let ptr = nn.wrapping_add(cpu.x());
+            let addr = peek_word_zp(ptr);
+            format!("(${:02X},X) @ {:02X} = {:04X} = {:02X}", nn, ptr, addr, peek_memory(bus, addr))
+        }
+        AddressingMode::IndirectY | AddressingMode::IndirectYEc => {
+            let base = peek_word_zp(nn);
+            let addr = base.wrapping_add(cpu.y() as u16);
+            format!("(${:02X}),Y = {:04X} @ {:04X} = {:02X}", nn, base, addr, peek_memory(bus, addr))
+        }
+        AddressingMode::Relative => format!("${:04X}", nnnn)
+    }
+}
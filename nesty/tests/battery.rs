@@ -0,0 +1,54 @@
+mod common;
+
+use nesty::emulator::Emulator;
+
+const SRAM_SIZE: usize = 0x2000;
+
+// NROM image which only spins in place; flags 6 decides whether there's a battery
+fn nrom(flags6: u8) -> Vec<u8> {
+    common::nrom(&[0x4C, 0x00, 0xC0], flags6) // JMP $C000
+}
+
+#[test]
+fn battery_ram_round_trip() {
+    let mut emu = Emulator::new();
+    emu.load_rom(nrom(0b10)).unwrap();
+    emu.reset();
+
+    let mut sram = vec![0; SRAM_SIZE];
+    sram[0] = 0x12;
+    sram[SRAM_SIZE - 1] = 0x34;
+
+    assert!(emu.load_battery_ram(&sram));
+    assert!(!emu.load_battery_ram(&sram[1..]));
+
+    emu.update();
+
+    assert_eq!(emu.bus().peek_byte(0x6000), 0x12);
+    assert_eq!(emu.bus().peek_byte(0x7FFF), 0x34);
+    assert_eq!(emu.battery_ram(), Some(sram));
+}
+
+#[test]
+fn no_battery() {
+    let mut emu = Emulator::new();
+    emu.load_rom(nrom(0)).unwrap();
+    emu.reset();
+
+    assert_eq!(emu.battery_ram(), None);
+    assert!(!emu.load_battery_ram(&[0; SRAM_SIZE]));
+}
+
+#[test]
+fn rom_hash_tells_games_apart() {
+    let mut emu = Emulator::new();
+
+    emu.load_rom(nrom(0)).unwrap();
+    let hash = emu.rom_hash();
+
+    emu.load_rom(nrom(0b10)).unwrap();
+    assert_ne!(emu.rom_hash(), hash);
+
+    emu.load_rom(nrom(0)).unwrap();
+    assert_eq!(emu.rom_hash(), hash);
+}
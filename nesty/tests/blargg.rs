@@ -0,0 +1,224 @@
+mod common;
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use nesty::emulator::Emulator;
+
+/*
+Runs test ROMs that report through blargg's $6000 protocol (instr_test, ppu_vbl_nmi, sprite_hit, apu_test, mmc3_test, ...):
+
+    $6000       status: $80 while running, $81 when the ROM wants the reset button pressed, otherwise the result (0 = passed)
+    $6001-$6003 DE B0 61, so we know the status byte means anything
+    $6004-      zero terminated text, the same thing the ROM prints on screen
+
+The ROMs aren't part of the repo. Put them (in any folder layout) under roms/blargg, or point NESTY_TEST_ROMS somewhere else,
+and run `cargo test --release --test blargg -- --nocapture` for the table.
+*/
+const STATUS: u16 = 0x6000;
+const SIGNATURE: u16 = 0x6001;
+const TEXT: u16 = 0x6004;
+
+const RUNNING: u8 = 0x80;
+const RESET_REQUESTED: u8 = 0x81;
+
+const SIGNATURE_BYTES: [u8; 3] = [0xDE, 0xB0, 0x61];
+const MAX_TEXT_LEN: u16 = 0x1000;
+
+const RESET_DELAY_FRAMES: u64 = 10; // the ROMs ask for the button to be held at least 100ms
+const TIMEOUT_FRAMES: u64 = 60 * 60;
+
+#[derive(PartialEq, Debug)]
+enum Outcome {
+    Passed,
+    Failed(u8),
+    TimedOut,
+    Unsupported(String) /* the emulator couldn't even load it */
+}
+
+struct TestResult {
+    outcome: Outcome,
+    text: String
+}
+
+fn read_text(emu: &Emulator) -> String {
+    let bus = emu.bus();
+    let bytes: Vec<u8> = (0..MAX_TEXT_LEN)
+        .map(|i| bus.peek_byte(TEXT + i))
+        .take_while(|&b| b != 0)
+        .collect();
+
+    String::from_utf8_lossy(&bytes).trim().to_string()
+}
+
+fn run_test_rom(rom: Vec<u8>, timeout_frames: u64) -> TestResult {
+    let mut emu = Emulator::new();
+
+    if let Err(err) = emu.load_rom(rom) {
+        return TestResult { outcome: Outcome::Unsupported(err.to_string()), text: String::new() };
+    }
+    emu.reset();
+
+    let mut reset_at = None;
+    let mut restarting = false; /* the status still reads $81 for a while after the reset */
+
+    for frame in 0..timeout_frames {
+        // stop reasons only matter to debuggers; a jammed CPU just runs into the timeout
+        while emu.update().is_some() {}
+
+        let signature: Vec<u8> = (0..3).map(|i| emu.bus().peek_byte(SIGNATURE + i)).collect();
+        if signature != SIGNATURE_BYTES {
+            continue;
+        }
+
+        let status = emu.bus().peek_byte(STATUS);
+
+        match status {
+            RUNNING => restarting = false,
+            RESET_REQUESTED if restarting => {}
+            RESET_REQUESTED => match reset_at {
+                None => reset_at = Some(frame + RESET_DELAY_FRAMES),
+                Some(at) if frame >= at => {
+                    emu.soft_reset();
+                    reset_at = None;
+                    restarting = true;
+                }
+                Some(_) => {}
+            },
+            0 => return TestResult { outcome: Outcome::Passed, text: read_text(&emu) },
+            code => return TestResult { outcome: Outcome::Failed(code), text: read_text(&emu) }
+        }
+    }
+
+    TestResult { outcome: Outcome::TimedOut, text: read_text(&emu) }
+}
+
+fn find_roms(dir: &Path, roms: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+
+        if path.is_dir() {
+            find_roms(&path, roms);
+        } else if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("nes")) {
+            roms.push(path);
+        }
+    }
+}
+
+#[test]
+fn blargg_suite() {
+    let dir = match env::var_os("NESTY_TEST_ROMS") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("..").join("roms").join("blargg")
+    };
+
+    let mut roms = Vec::new();
+    find_roms(&dir, &mut roms);
+    roms.sort();
+
+    if roms.is_empty() {
+        println!("No test ROMs in {}, skipping", dir.display());
+        return;
+    }
+
+    let mut table = String::new();
+    let mut passed = 0;
+
+    for path in roms.iter() {
+        let name = path.strip_prefix(&dir).unwrap_or(path).display().to_string();
+        let rom = fs::read(path).expect("Unable to read the test ROM");
+        let result = run_test_rom(rom, TIMEOUT_FRAMES);
+
+        let verdict = match &result.outcome {
+            Outcome::Passed => "passed".to_string(),
+            Outcome::Failed(code) => format!("FAILED (#{})", code),
+            Outcome::TimedOut => "TIMED OUT".to_string(),
+            Outcome::Unsupported(err) => format!("UNSUPPORTED ({})", err)
+        };
+
+        if result.outcome == Outcome::Passed {
+            passed += 1;
+        }
+
+        // the text usually repeats the ROM name, the last line is the interesting one
+        let detail = result.text.lines().last().unwrap_or("");
+        table += &format!("{:<50} {:<24} {}\n", name, verdict, detail);
+    }
+
+    table += &format!("\n{} of {} passed\n", passed, roms.len());
+    println!("{}", table);
+
+    assert!(passed == roms.len(), "Some test ROMs failed\n{}", table);
+}
+
+/*
+A tiny NROM test ROM speaking the protocol, so the harness itself is tested without blargg's ROMs.
+It asks for a reset the first time through (counting runs in RAM, which survives the reset button),
+then reports `status` with the text "OK".
+*/
+fn protocol_rom(status: u8) -> Vec<u8> {
+    #[rustfmt::skip]
+    let code: [u8; 63] = [
+        0x78,                   // C000 SEI
+        0xA2, 0xFF,             // C001 LDX #$FF
+        0x9A,                   // C003 TXS
+        0xA9, 0xDE,             // C004 LDA #$DE
+        0x8D, 0x01, 0x60,       // C006 STA $6001
+        0xA9, 0xB0,             // C009 LDA #$B0
+        0x8D, 0x02, 0x60,       // C00B STA $6002
+        0xA9, 0x61,             // C00E LDA #$61
+        0x8D, 0x03, 0x60,       // C010 STA $6003
+        0xA9, 0x80,             // C013 LDA #$80
+        0x8D, 0x00, 0x60,       // C015 STA $6000
+        0xE6, 0x10,             // C018 INC $10
+        0xA5, 0x10,             // C01A LDA $10
+        0xC9, 0x02,             // C01C CMP #$02
+        0xB0, 0x08,             // C01E BCS $C028
+        0xA9, 0x81,             // C020 LDA #$81
+        0x8D, 0x00, 0x60,       // C022 STA $6000
+        0x4C, 0x25, 0xC0,       // C025 JMP $C025
+        0xA9, 0x4F,             // C028 LDA #'O'
+        0x8D, 0x04, 0x60,       // C02A STA $6004
+        0xA9, 0x4B,             // C02D LDA #'K'
+        0x8D, 0x05, 0x60,       // C02F STA $6005
+        0xA9, 0x00,             // C032 LDA #$00
+        0x8D, 0x06, 0x60,       // C034 STA $6006
+        0xA9, status,           // C037 LDA #status
+        0x8D, 0x00, 0x60,       // C039 STA $6000
+        0x4C, 0x3C, 0xC0        // C03C JMP $C03C
+    ];
+
+    common::nrom(&code, 0)
+}
+
+#[test]
+fn protocol_pass_after_reset() {
+    let result = run_test_rom(protocol_rom(0), 120);
+
+    assert_eq!(result.outcome, Outcome::Passed);
+    assert_eq!(result.text, "OK");
+}
+
+#[test]
+fn protocol_failure_code() {
+    let result = run_test_rom(protocol_rom(3), 120);
+
+    assert_eq!(result.outcome, Outcome::Failed(3));
+    assert_eq!(result.text, "OK");
+}
+
+#[test]
+fn protocol_timeout() {
+    let mut rom = protocol_rom(0);
+    rom[0x10 + 0x1D] = 0xFF; // CMP #$FF: keeps asking for resets until long after the timeout
+
+    let result = run_test_rom(rom, 120);
+
+    assert_eq!(result.outcome, Outcome::TimedOut);
+}
@@ -0,0 +1,49 @@
+// Shared by the integration tests; each test file only uses some of it
+#![allow(dead_code)]
+
+use nesty::emulator::Emulator;
+
+pub const PRG_BANK_SIZE: usize = 0x4000;
+pub const CHR_BANK_SIZE: usize = 0x2000;
+
+/* iNES image of the given mapper; flags 6 is taken as is apart from the mapper's low nibble */
+pub fn ines(mapper: u8, flags6: u8, prg_rom: &[u8], chr_rom: &[u8]) -> Vec<u8> {
+    let prg_banks = (prg_rom.len() / PRG_BANK_SIZE) as u8;
+    let chr_banks = (chr_rom.len() / CHR_BANK_SIZE) as u8;
+    let flags6 = (flags6 & 0x0F) | (mapper << 4);
+    let flags7 = mapper & 0xF0;
+
+    let mut rom = vec![b'N', b'E', b'S', 0x1A, prg_banks, chr_banks, flags6, flags7, 0, 0, 0, 0, 0, 0, 0, 0];
+    rom.extend_from_slice(prg_rom);
+    rom.extend_from_slice(chr_rom);
+    rom
+}
+
+/*
+One 16 KB bank of NOPs seen at $C000, starting with `code`; NMI, RESET and IRQ all point at $C000.
+8 KB of CHR ROM, all zeros.
+*/
+pub fn nrom(code: &[u8], flags6: u8) -> Vec<u8> {
+    let mut prg_rom = vec![0xEA; PRG_BANK_SIZE];
+    prg_rom[..code.len()].copy_from_slice(code);
+    set_vectors(&mut prg_rom, 0xC000, 0xC000, 0xC000);
+
+    ines(0, flags6, &prg_rom, &[0; CHR_BANK_SIZE])
+}
+
+/* Writes the NMI, RESET and IRQ vectors into the last 6 bytes of the PRG ROM */
+pub fn set_vectors(prg_rom: &mut [u8], nmi: u16, reset: u16, irq: u16) {
+    let end = prg_rom.len();
+
+    for (i, vector) in [nmi, reset, irq].iter().enumerate() {
+        prg_rom[end - 6 + i * 2] = *vector as u8;
+        prg_rom[end - 5 + i * 2] = (*vector >> 8) as u8;
+    }
+}
+
+pub fn emulator(rom: Vec<u8>) -> Emulator {
+    let mut emu = Emulator::new();
+    emu.load_rom(rom).expect("Unable to load the test ROM");
+    emu.reset();
+    emu
+}
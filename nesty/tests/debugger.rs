@@ -0,0 +1,255 @@
+mod common;
+
+use nesty::debugger::{BreakpointKind, Comparison, Condition, Register, StopReason};
+use nesty::emulator::Emulator;
+
+/*
+NROM image looping over
+
+    C000  LDA addr
+    C003  JMP $C000
+*/
+fn emulator(addr: u16) -> Emulator {
+    common::emulator(common::nrom(&[0xAD, addr as u8, (addr >> 8) as u8, 0x4C, 0x00, 0xC0], 0))
+}
+
+#[test]
+fn read_breakpoint_on_data() {
+    let mut emu = emulator(0x0200);
+    let id = emu.debugger().add_breakpoint(BreakpointKind::Read { start: 0x0200, end: 0x0200 }, None);
+
+    assert_eq!(emu.update(), Some(StopReason::Breakpoint(id)));
+}
+
+#[test]
+fn read_breakpoint_ignores_instruction_fetches() {
+    let mut emu = emulator(0x0200);
+    emu.debugger().add_breakpoint(BreakpointKind::Read { start: 0xC000, end: 0xC005 }, None);
+
+    for _ in 0..3 {
+        assert_eq!(emu.update(), None);
+    }
+}
+
+#[test]
+fn read_breakpoint_on_code_read_as_data() {
+    // the LDA reads the low byte of the JMP's operand
+    let mut emu = emulator(0xC004);
+    let id = emu.debugger().add_breakpoint(BreakpointKind::Read { start: 0xC004, end: 0xC004 }, None);
+
+    assert_eq!(emu.update(), Some(StopReason::Breakpoint(id)));
+}
+
+/*
+A main loop calling a subroutine which calls another one:
+
+    C000  SEI
+    C001  LDX #$FF
+    C003  TXS
+    C004  JSR $C010
+    C007  INX
+    C008  STX $0200
+    C00B  JMP $C004
+
+    C010  JSR $C020
+    C013  LDA #$01
+    C015  RTS
+
+    C020  LDY #$05
+    C022  RTS
+*/
+fn subroutines() -> Emulator {
+    let mut code = vec![0xEA; 0x23];
+    code[0x00..0x0E].copy_from_slice(&[0x78, 0xA2, 0xFF, 0x9A, 0x20, 0x10, 0xC0, 0xE8, 0x8E, 0x00, 0x02, 0x4C, 0x04, 0xC0]);
+    code[0x10..0x16].copy_from_slice(&[0x20, 0x20, 0xC0, 0xA9, 0x01, 0x60]);
+    code[0x20..0x23].copy_from_slice(&[0xA0, 0x05, 0x60]);
+
+    common::emulator(common::nrom(&code, 0))
+}
+
+fn break_at(emu: &mut Emulator, addr: u16) {
+    let id = emu.debugger().add_breakpoint(BreakpointKind::Execute(addr), None);
+    assert_eq!(emu.update(), Some(StopReason::Breakpoint(id)));
+    assert_eq!(emu.cpu().pc(), addr);
+    emu.debugger().clear_breakpoints();
+}
+
+#[test]
+fn step_over_a_subroutine_call() {
+    let mut emu = subroutines();
+    break_at(&mut emu, 0xC004);
+
+    emu.step_over();
+    assert_eq!(emu.update(), Some(StopReason::Step));
+    assert_eq!(emu.cpu().pc(), 0xC007);
+    assert_eq!(emu.cpu().sp(), 0xFF);
+    assert_eq!(emu.cpu().y(), 0x05); /* the subroutines did run */
+
+    // anything else is a single step
+    emu.step_over();
+    assert_eq!(emu.update(), Some(StopReason::Step));
+    assert_eq!(emu.cpu().pc(), 0xC008);
+}
+
+#[test]
+fn step_out_of_nested_subroutines() {
+    let mut emu = subroutines();
+    break_at(&mut emu, 0xC020);
+
+    emu.step_out();
+    assert_eq!(emu.update(), Some(StopReason::Step));
+    assert_eq!(emu.cpu().pc(), 0xC013);
+
+    emu.step_out();
+    assert_eq!(emu.update(), Some(StopReason::Step));
+    assert_eq!(emu.cpu().pc(), 0xC007);
+    assert_eq!(emu.cpu().sp(), 0xFF);
+}
+
+#[test]
+fn step_out_skips_calls_made_on_the_way() {
+    let mut emu = subroutines();
+    break_at(&mut emu, 0xC010);
+
+    // the JSR at $C010 and the RTS at $C022 don't count, only the RTS at $C015
+    emu.step_out();
+    assert_eq!(emu.update(), Some(StopReason::Step));
+    assert_eq!(emu.cpu().pc(), 0xC007);
+}
+
+#[test]
+fn conditional_write_breakpoint() {
+    let mut emu = subroutines();
+    let condition = "X==3".parse().unwrap();
+    let id = emu.debugger().add_breakpoint(BreakpointKind::Write { start: 0x0200, end: 0x0200 }, Some(condition));
+
+    assert_eq!(emu.update(), Some(StopReason::Breakpoint(id)));
+    assert_eq!(emu.cpu().pc(), 0xC00B); /* stops after the STX */
+    assert_eq!(emu.bus().peek_byte(0x0200), 3);
+
+    // a memory condition on an execute breakpoint
+    emu.debugger().clear_breakpoints();
+    let condition = "[0200]>=$10".parse().unwrap();
+    let id = emu.debugger().add_breakpoint(BreakpointKind::Execute(0xC004), Some(condition));
+
+    assert_eq!(emu.update(), Some(StopReason::Breakpoint(id)));
+    assert_eq!(emu.bus().peek_byte(0x0200), 0x10);
+}
+
+#[test]
+fn disabled_breakpoints_dont_fire() {
+    let mut emu = subroutines();
+    let id = emu.debugger().add_breakpoint(BreakpointKind::Write { start: 0x0200, end: 0x02FF }, None);
+    emu.debugger().set_breakpoint_enabled(id, false);
+
+    assert_eq!(emu.update(), None);
+
+    assert!(emu.debugger().remove_breakpoint(id));
+    assert!(!emu.debugger().remove_breakpoint(id));
+}
+
+#[test]
+fn ppu_register_breakpoints() {
+    // C000 LDA #$80, C002 STA $2000, C005 LDA $200A (a mirror of PPUSTATUS), C008 JMP $C000
+    let code = [0xA9, 0x80, 0x8D, 0x00, 0x20, 0xAD, 0x0A, 0x20, 0x4C, 0x00, 0xC0];
+    let mut emu = common::emulator(common::nrom(&code, 0));
+
+    let write = emu.debugger().add_breakpoint(BreakpointKind::PpuRegisterWrite(0), None);
+    let read = emu.debugger().add_breakpoint(BreakpointKind::PpuRegisterRead(2), None);
+    emu.debugger().add_breakpoint(BreakpointKind::PpuRegisterRead(0), None); /* never read */
+
+    assert_eq!(emu.update(), Some(StopReason::Breakpoint(write)));
+    assert_eq!(emu.cpu().pc(), 0xC005);
+
+    assert_eq!(emu.update(), Some(StopReason::Breakpoint(read)));
+    assert_eq!(emu.cpu().pc(), 0xC008);
+}
+
+// update() frames don't line up with the PPU's, so a stop can come in the frame after
+fn run_until_stop(emu: &mut Emulator) -> (usize, Option<StopReason>) {
+    let mut frames = 0;
+
+    loop {
+        match emu.update() {
+            None if frames < 10 => frames += 1,
+            reason => return (frames, reason)
+        }
+    }
+}
+
+#[test]
+fn run_to_scanline_and_next_frame() {
+    let mut emu = subroutines();
+    emu.update();
+
+    emu.run_to_scanline(100);
+    assert_eq!(emu.update(), Some(StopReason::Scanline(100)));
+    assert_eq!(emu.ppu().scanline(), 100);
+
+    // already on it, so it takes a whole frame
+    let cycles = emu.cpu().total_cycles;
+    emu.run_to_scanline(100);
+    assert_eq!(run_until_stop(&mut emu).1, Some(StopReason::Scanline(100)));
+    assert!(emu.cpu().total_cycles - cycles > 29000);
+
+    emu.run_to_next_frame();
+    assert_eq!(run_until_stop(&mut emu).1, Some(StopReason::Frame));
+    assert_eq!(emu.ppu().scanline(), -1);
+
+    // nothing is left over once it stopped
+    assert_eq!(emu.update(), None);
+}
+
+#[test]
+fn halt_is_reported_once() {
+    let mut emu = common::emulator(common::nrom(&[0x02], 0)); // KIL
+
+    assert_eq!(emu.update(), Some(StopReason::Halted));
+    assert!(emu.cpu().halted());
+
+    for _ in 0..3 {
+        assert_eq!(emu.update(), None);
+    }
+
+    // until it jams again after a reset
+    emu.soft_reset();
+    assert_eq!(emu.update(), Some(StopReason::Halted));
+}
+
+#[test]
+fn parse_conditions() {
+    let cases = [
+        ("X!=0", Condition::Register { register: Register::X, comparison: Comparison::NotEqual, value: 0 }),
+        ("a <= 5", Condition::Register { register: Register::A, comparison: Comparison::LessOrEqual, value: 5 }),
+        ("Y<5", Condition::Register { register: Register::Y, comparison: Comparison::Less, value: 5 }),
+        ("sp>=$80", Condition::Register { register: Register::SP, comparison: Comparison::GreaterOrEqual, value: 0x80 }),
+        ("P>0x10", Condition::Register { register: Register::P, comparison: Comparison::Greater, value: 0x10 }),
+        ("PC==C000", Condition::Register { register: Register::PC, comparison: Comparison::Equal, value: 0xC000 }),
+        ("[00FF]<$10", Condition::Memory { addr: 0x00FF, comparison: Comparison::Less, value: 0x10 }),
+        ("[$6000] == 81", Condition::Memory { addr: 0x6000, comparison: Comparison::Equal, value: 0x81 })
+    ];
+
+    for (text, condition) in cases.iter() {
+        assert_eq!(text.parse::<Condition>().as_ref(), Ok(condition), "{}", text);
+    }
+}
+
+#[test]
+fn parse_condition_errors() {
+    let cases = [
+        ("X", "No comparison in condition 'X'"),
+        ("X=1", "No comparison in condition 'X=1'"),
+        ("Q==1", "Unknown register 'Q'"),
+        ("==1", "Unknown register ''"),
+        ("X==zz", "Bad hex number 'zz'"),
+        ("X==", "Bad hex number ''"),
+        ("X==1<2", "Bad hex number '1<2'"), /* the leftmost operator is the comparison */
+        ("[zz]==1", "Bad hex number 'ZZ'"),
+        ("[0200]==100", "Bad hex number '100'"), /* memory holds bytes */
+        ("[10000]==1", "Bad hex number '10000'")
+    ];
+
+    for (text, error) in cases.iter() {
+        assert_eq!(text.parse::<Condition>().err().as_deref(), Some(*error), "{}", text);
+    }
+}
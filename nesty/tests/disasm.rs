@@ -0,0 +1,38 @@
+mod common;
+
+use nesty::disasm::disassemble_prg_bank;
+use nesty::emulator::Emulator;
+
+// Two banks of NOPs; NMI and RESET point into the last bank, IRQ into the first
+fn emulator() -> Emulator {
+    let mut prg_rom = vec![0xEA; 2 * common::PRG_BANK_SIZE];
+    common::set_vectors(&mut prg_rom, 0xC010, 0xC000, 0x8000);
+
+    common::emulator(common::ines(0, 0, &prg_rom, &[0; common::CHR_BANK_SIZE]))
+}
+
+fn labels(emu: &Emulator, bank: usize, base: u16) -> Vec<(u16, &'static str)> {
+    disassemble_prg_bank(&emu.cart(), bank, base)
+        .iter()
+        .filter_map(|line| line.label.map(|label| (line.instruction.address, label)))
+        .collect()
+}
+
+#[test]
+fn prg_bank_labels() {
+    let emu = emulator();
+
+    assert_eq!(labels(&emu, 0, 0x8000), vec![(0x8000, "IRQ")]);
+    assert_eq!(labels(&emu, 1, 0xC000), vec![(0xC000, "RESET"), (0xC010, "NMI")]);
+
+    let first = disassemble_prg_bank(&emu.cart(), 0, 0x8000);
+    assert_eq!(first[0].to_string(), "IRQ:\n8000  EA        NOP");
+}
+
+#[test]
+fn prg_bank_out_of_range() {
+    let emu = emulator();
+
+    assert!(disassemble_prg_bank(&emu.cart(), 2, 0x8000).is_empty());
+    assert!(disassemble_prg_bank(&emu.cart(), usize::MAX, 0x8000).is_empty());
+}
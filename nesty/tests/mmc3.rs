@@ -0,0 +1,75 @@
+mod common;
+
+use nesty::emulator::Emulator;
+use nesty::irq::IrqSource;
+
+const LATCH: u8 = 8;
+
+/*
+MMC3 board with 32 KB of PRG ROM; the program sits at the start of the last 8 KB bank, which is always seen at $E000.
+It waits for the second vblank, sets up the IRQ counter while still in vblank, turns rendering on with the
+sprites on the right pattern table so A12 rises once a scanline, then acknowledges the IRQ in the next vblank.
+*/
+fn mmc3_irq() -> Emulator {
+    let code = [
+        0x78,                   // E000: SEI
+        0x2C, 0x02, 0x20,       // E001: BIT $2002
+        0x10, 0xFB,             // E004: BPL $E001
+        0x2C, 0x02, 0x20,       // E006: BIT $2002
+        0x10, 0xFB,             // E009: BPL $E006
+        0xA9, 0x08,             // E00B: LDA #$08      ; sprites at $1000
+        0x8D, 0x00, 0x20,       // E00D: STA $2000
+        0xA9, LATCH,            // E010: LDA #LATCH
+        0x8D, 0x00, 0xC0,       // E012: STA $C000     ; IRQ latch
+        0x8D, 0x01, 0xC0,       // E015: STA $C001     ; IRQ reload
+        0x8D, 0x01, 0xE0,       // E018: STA $E001     ; IRQ enable
+        0xA9, 0x18,             // E01B: LDA #$18
+        0x8D, 0x01, 0x20,       // E01D: STA $2001     ; background and sprites on
+        0x2C, 0x02, 0x20,       // E020: BIT $2002
+        0x10, 0xFB,             // E023: BPL $E020
+        0x8D, 0x00, 0xE0,       // E025: STA $E000     ; IRQ disable and acknowledge
+        0x4C, 0x28, 0xE0        // E028: JMP $E028
+    ];
+
+    let mut prg_rom = vec![0xEA; 2 * common::PRG_BANK_SIZE];
+    prg_rom[0x6000..0x6000 + code.len()].copy_from_slice(&code);
+    common::set_vectors(&mut prg_rom, 0xE000, 0xE000, 0xE000);
+
+    common::emulator(common::ines(4, 0, &prg_rom, &[0; common::CHR_BANK_SIZE]))
+}
+
+fn run_to_scanline(emu: &mut Emulator, scanline: i32) {
+    while emu.ppu().scanline() != scanline {
+        emu.tick();
+    }
+}
+
+fn irq_asserted(emu: &Emulator) -> bool {
+    emu.irq().is_asserted(IrqSource::Mapper)
+}
+
+#[test]
+fn irq_after_rendered_scanlines() {
+    let mut emu = mmc3_irq();
+
+    while emu.cpu().pc() != 0xE020 {
+        emu.tick();
+    }
+    assert!(!irq_asserted(&emu));
+
+    // the pre-render line reloads the counter, then every visible line counts it down by one
+    run_to_scanline(&mut emu, LATCH as i32 - 1);
+    assert!(!irq_asserted(&emu));
+
+    run_to_scanline(&mut emu, LATCH as i32);
+    assert!(irq_asserted(&emu));
+
+    // nothing clears it but the program
+    run_to_scanline(&mut emu, 239);
+    assert!(irq_asserted(&emu));
+
+    // the $E000 write comes right after vblank starts
+    run_to_scanline(&mut emu, 242);
+    assert!(!irq_asserted(&emu));
+    assert_eq!(emu.cpu().pc(), 0xE028);
+}
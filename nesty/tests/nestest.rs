@@ -0,0 +1,109 @@
+use std::fs;
+use std::path::PathBuf;
+
+use nesty::emulator::Emulator;
+use nesty::trace::Tracer;
+
+fn rom_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("..").join("roms").join(name)
+}
+
+/* The CPU state before an instruction executes, as printed in nestest.log */
+#[derive(PartialEq, Debug)]
+struct CpuState {
+    pc: u16,
+    a: u8,
+    x: u8,
+    y: u8,
+    p: u8,
+    sp: u8,
+    cycles: u64
+}
+
+/*
+C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+*/
+fn parse_line(line: &str) -> CpuState {
+    let field = |name: &str| -> &str {
+        let start = line.find(name).unwrap_or_else(|| panic!("No {} in: {}", name, line)) + name.len();
+        line[start..].split_whitespace().next().unwrap()
+    };
+    let hex = |name: &str| u8::from_str_radix(field(name), 16).unwrap();
+
+    CpuState {
+        pc: u16::from_str_radix(&line[0..4], 16).unwrap(),
+        a: hex("A:"),
+        x: hex("X:"),
+        y: hex("Y:"),
+        p: hex("P:"),
+        sp: hex("SP:"),
+        cycles: field("CYC:").parse().unwrap()
+    }
+}
+
+fn cpu_state(emu: &Emulator) -> CpuState {
+    let cpu = emu.cpu();
+
+    CpuState {
+        pc: cpu.pc(),
+        a: cpu.a(),
+        x: cpu.x(),
+        y: cpu.y(),
+        p: cpu.p(),
+        sp: cpu.sp(),
+        cycles: cpu.total_cycles
+    }
+}
+
+#[test]
+fn nestest() {
+    let rom = fs::read(rom_path("nestest.nes")).expect("Unable to read nestest.nes");
+    let log = fs::read_to_string(rom_path("nestest.log")).expect("Unable to read nestest.log");
+
+    let mut emu = Emulator::new();
+    emu.load_rom(rom).expect("Unable to load nestest.nes");
+    emu.reset();
+
+    // automation mode starts at $C000 instead of the reset vector
+    emu.cpu().set_pc(0xC000);
+    emu.cpu().set_p(0x24);
+    emu.cpu().total_cycles = 7;
+
+    for (i, line) in log.lines().enumerate() {
+        let expected = parse_line(line);
+        let actual = cpu_state(&emu);
+
+        assert!(expected == actual, "nestest.log line {} differs\n  log: {}\n  expected: {:?}\n  actual:   {:?}", i + 1, line, expected, actual);
+
+        emu.tick();
+    }
+}
+
+#[test]
+fn nestest_trace() {
+    let rom = fs::read(rom_path("nestest.nes")).expect("Unable to read nestest.nes");
+    let log = fs::read_to_string(rom_path("nestest.log")).expect("Unable to read nestest.log");
+
+    let mut emu = Emulator::new();
+    emu.load_rom(rom).expect("Unable to load nestest.nes");
+    emu.reset();
+
+    emu.cpu().set_pc(0xC000);
+    emu.cpu().set_p(0x24);
+    emu.cpu().total_cycles = 7;
+    emu.cpu().set_tracer(Some(Tracer::ring_buffer(log.lines().count())));
+
+    for _ in log.lines() {
+        emu.tick();
+    }
+
+    let tracer = emu.cpu().set_tracer(None).unwrap();
+
+    // The PPU column is left out since the PPU isn't advanced during reset
+    for (i, (actual, expected)) in tracer.lines().zip(log.lines()).enumerate() {
+        let actual = actual.split(" PPU:").next().unwrap();
+        let expected = expected.split(" PPU:").next().unwrap();
+
+        assert_eq!(actual, expected, "trace differs from nestest.log at line {}", i + 1);
+    }
+}
@@ -0,0 +1,145 @@
+use std::fs;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use nesty::emulator::Emulator;
+use nesty::remote::RemoteServer;
+
+const MAX_POLLS: usize = 1000;
+
+fn rom_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("..").join("roms").join(name)
+}
+
+/* A client and the emulator side it talks to, driven from a single thread */
+struct Session {
+    emu: Emulator,
+    server: RemoteServer,
+
+    client: TcpStream,
+    reader: BufReader<TcpStream>
+}
+
+impl Session {
+    fn new() -> Self {
+        let rom = fs::read(rom_path("nestest.nes")).expect("Unable to read nestest.nes");
+
+        let mut emu = Emulator::new();
+        emu.load_rom(rom).expect("Unable to load nestest.nes");
+        emu.reset();
+
+        let mut server = RemoteServer::bind(0).expect("Unable to listen on localhost");
+        let client = TcpStream::connect(server.local_addr().unwrap()).expect("Unable to connect");
+        client.set_read_timeout(Some(Duration::from_millis(5))).unwrap();
+
+        for _ in 0..MAX_POLLS {
+            if server.connected() {
+                break;
+            }
+            server.poll(&mut emu);
+        }
+        assert!(server.connected() && server.paused());
+
+        let reader = BufReader::new(client.try_clone().unwrap());
+
+        Session {
+            emu: emu,
+            server: server,
+
+            client: client,
+            reader: reader
+        }
+    }
+
+    // Like a frontend's main loop: run while the server lets us, answering requests in between
+    fn next_line(&mut self) -> String {
+        let mut line = String::new();
+
+        for _ in 0..MAX_POLLS {
+            self.server.poll(&mut self.emu);
+
+            if !self.server.paused() {
+                if let Some(reason) = self.emu.update() {
+                    self.server.stopped(&mut self.emu, reason);
+                }
+            }
+
+            match self.reader.read_line(&mut line) {
+                Ok(_) if line.ends_with('\n') => return line.trim_end().to_string(),
+                Ok(_) => {}
+                Err(err) if err.kind() == ErrorKind::WouldBlock || err.kind() == ErrorKind::TimedOut => {}
+                Err(err) => panic!("{}", err)
+            }
+        }
+
+        panic!("No reply from the server");
+    }
+
+    fn request(&mut self, request: &str) -> String {
+        writeln!(self.client, "{}", request).unwrap();
+        self.next_line()
+    }
+}
+
+#[test]
+fn breakpoints_and_stepping() {
+    let mut session = Session::new();
+
+    assert_eq!(session.request("setreg PC C000"), "ok");
+    assert_eq!(session.request("break exec C5F5"), "ok 0");
+    assert_eq!(session.request("breakpoints"), "ok 0:exec:C5F5");
+
+    assert_eq!(session.request("continue"), "ok");
+    assert_eq!(session.next_line(), "stopped breakpoint 0 pc=C5F5");
+
+    // LDX #$00
+    assert_eq!(session.request("step"), "ok");
+    assert_eq!(session.next_line(), "stopped step pc=C5F7");
+
+    assert!(session.request("regs").contains(" X=00 Y=00 P=36 SP=FD PC=C5F7 "));
+
+    assert_eq!(session.request("delete 0"), "ok");
+    assert!(session.request("delete 0").starts_with("error"));
+}
+
+#[test]
+fn memory_and_registers() {
+    let mut session = Session::new();
+
+    assert_eq!(session.request("read C000 3"), "ok 4C F5 C5");
+
+    assert_eq!(session.request("write 0010 AB CD"), "ok");
+    assert_eq!(session.request("read $0010 2"), "ok AB CD");
+
+    assert_eq!(session.request("setreg A 42"), "ok");
+    assert!(session.request("regs").starts_with("ok A=42"));
+
+    assert!(session.request("setreg A 100").starts_with("error"));
+    assert!(session.request("break nowhere 0").starts_with("error"));
+    assert!(session.request("read 10 if A==0").starts_with("error"));
+    assert!(session.request("frobnicate").starts_with("error"));
+}
+
+#[test]
+fn screenshot_and_states() {
+    let mut session = Session::new();
+
+    let dir = std::env::temp_dir().join(format!("nesty-remote-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    let screenshot = dir.join("screen.ppm");
+    let state = dir.join("state.sav");
+
+    assert_eq!(session.request(&format!("screenshot {}", screenshot.display())), "ok");
+    assert!(fs::read(&screenshot).unwrap().starts_with(b"P6\n256 240\n255\n"));
+
+    assert_eq!(session.request("write 0300 11"), "ok");
+    assert_eq!(session.request(&format!("savestate {}", state.display())), "ok");
+    assert_eq!(session.request("write 0300 22"), "ok");
+    assert_eq!(session.request(&format!("loadstate {}", state.display())), "ok");
+    assert_eq!(session.request("read 0300"), "ok 11");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,72 @@
+use std::fs;
+use std::path::PathBuf;
+
+use nesty::emulator::Emulator;
+use nesty::rewind::Rewind;
+
+fn emulator() -> Emulator {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("..").join("roms").join("Super_Mario_Forever_Clean_Patch.nes");
+    let rom = fs::read(&path).expect("Unable to read the ROM");
+
+    let mut emu = Emulator::new();
+    emu.load_rom(rom).expect("Unable to load the ROM");
+    emu.reset();
+    emu
+}
+
+fn save(emu: &Emulator) -> Vec<u8> {
+    let mut state = Vec::new();
+    emu.save_state(&mut state);
+    state
+}
+
+#[test]
+fn plays_backwards_frame_by_frame() {
+    let mut emu = emulator();
+    let mut rewind = Rewind::new(1, 16 * 1024 * 1024);
+    let mut frames = Vec::new();
+    let mut states = Vec::new();
+
+    // past the boot screens, where the game turns rendering off and the picture isn't redrawn at all
+    for _ in 0..60 {
+        emu.update();
+    }
+
+    for _ in 0..60 {
+        emu.update();
+        rewind.capture(&emu);
+        frames.push(emu.ppu().pixels.clone());
+        states.push(save(&emu));
+    }
+
+    for back in 1..=40 {
+        assert!(rewind.step_back(&mut emu));
+        assert!(emu.ppu().pixels == frames[frames.len() - 1 - back], "wrong picture {} frames back", back);
+    }
+
+    // carrying on from the past rewrites the history
+    emu.update();
+    rewind.capture(&emu);
+    assert_eq!(rewind.len(), 21);
+
+    // the oldest two snapshots are only there to redraw the screen from
+    while rewind.step_back(&mut emu) {}
+    assert_eq!(rewind.len(), 3);
+    assert!(save(&emu) == states[2]);
+}
+
+#[test]
+fn stays_within_its_memory() {
+    let mut emu = emulator();
+    let max_bytes = 16 * 1024;
+    let mut rewind = Rewind::new(1, max_bytes);
+
+    for _ in 0..240 {
+        emu.update();
+        rewind.capture(&emu);
+        assert!(rewind.used_bytes() <= max_bytes);
+    }
+
+    assert!(rewind.len() > 10);
+    assert!(rewind.step_back(&mut emu));
+}
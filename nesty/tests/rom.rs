@@ -0,0 +1,91 @@
+mod common;
+
+use nesty::cartridge::RomError;
+use nesty::emulator::Emulator;
+
+fn load(rom: Vec<u8>) -> Result<(), RomError> {
+    Emulator::new().load_rom(rom)
+}
+
+#[test]
+fn bad_magic() {
+    assert_eq!(load(Vec::new()), Err(RomError::BadMagic));
+    assert_eq!(load(b"NES".to_vec()), Err(RomError::BadMagic));
+
+    let mut rom = common::nrom(&[], 0);
+    rom[3] = 0x1B;
+    assert_eq!(load(rom), Err(RomError::BadMagic));
+}
+
+#[test]
+fn truncated_header() {
+    let rom = common::nrom(&[], 0);
+    assert_eq!(load(rom[..15].to_vec()), Err(RomError::TruncatedHeader));
+}
+
+#[test]
+fn truncated_rom() {
+    let rom = common::nrom(&[], 0);
+
+    // 16 byte header, 16 KB PRG ROM, 8 KB CHR ROM
+    assert_eq!(load(rom[..0x2010].to_vec()), Err(RomError::TruncatedPrgRom { expected: 0x4000, found: 0x2000 }));
+    assert_eq!(load(rom[..0x5010].to_vec()), Err(RomError::TruncatedChrRom { expected: 0x2000, found: 0x1000 }));
+
+    // the trainer comes in front of the PRG ROM and takes up part of it
+    let mut trainer = rom.clone();
+    trainer[6] |= 0b100;
+    assert_eq!(load(trainer), Err(RomError::TruncatedChrRom { expected: 0x2000, found: 0x1E00 }));
+}
+
+#[test]
+fn unsupported_roms() {
+    let prg_rom = vec![0; common::PRG_BANK_SIZE];
+    let chr_rom = vec![0; common::CHR_BANK_SIZE];
+
+    assert_eq!(load(common::ines(9, 0, &prg_rom, &chr_rom)), Err(RomError::UnsupportedMapper { mapper: 9, submapper: 0 }));
+    assert_eq!(load(common::ines(0, 0, &[], &chr_rom)), Err(RomError::UnsupportedFormat("PRG ROM size must be a multiple of 16 KB")));
+
+    // NES 2.0 with an extended console type, and submapper 2 of mapper 260
+    let mut extended = common::ines(0, 0, &prg_rom, &chr_rom);
+    extended[7] = 0b0000_1011;
+    assert_eq!(load(extended), Err(RomError::UnsupportedFormat("extended console types are not emulated")));
+
+    let mut nes2 = common::ines(4, 0, &prg_rom, &chr_rom);
+    nes2[7] = 0b0000_1000;
+    nes2[8] = 0x21;
+    assert_eq!(load(nes2), Err(RomError::UnsupportedMapper { mapper: 0x104, submapper: 2 }));
+}
+
+#[test]
+fn failed_load_keeps_the_current_game() {
+    // C000 INC $10, C002 JMP $C000
+    let mut emu = common::emulator(common::nrom(&[0xE6, 0x10, 0x4C, 0x00, 0xC0], 0));
+    emu.update();
+
+    let hash = emu.rom_hash();
+    let mut before = Vec::new();
+    emu.save_state(&mut before);
+
+    let prg_rom = vec![0; common::PRG_BANK_SIZE];
+    let bad_roms = [
+        Vec::new(),
+        b"NES\x1A".to_vec(),
+        common::nrom(&[], 0)[..0x2010].to_vec(),
+        common::ines(9, 0, &prg_rom, &[0; common::CHR_BANK_SIZE])
+    ];
+
+    for rom in bad_roms.iter() {
+        assert!(emu.load_rom(rom.clone()).is_err());
+    }
+
+    assert_eq!(emu.rom_hash(), hash);
+
+    let mut after = Vec::new();
+    emu.save_state(&mut after);
+    assert!(after == before);
+
+    // and it keeps running
+    let count = emu.bus().peek_byte(0x10);
+    emu.update();
+    assert_ne!(emu.bus().peek_byte(0x10), count);
+}
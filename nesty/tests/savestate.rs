@@ -0,0 +1,215 @@
+mod common;
+
+use std::convert::TryInto;
+use std::fs;
+use std::io::Cursor;
+use std::path::PathBuf;
+
+use nesty::emulator::Emulator;
+use nesty::savable::Savable;
+use nesty::state::StateError;
+
+const HEADER_SIZE: usize = 18;
+
+fn emulator(name: &str) -> Emulator {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("..").join("roms").join(name);
+    let rom = fs::read(&path).expect("Unable to read the ROM");
+
+    let mut emu = Emulator::new();
+    emu.load_rom(rom).expect("Unable to load the ROM");
+    emu.reset();
+    emu
+}
+
+fn run(emu: &mut Emulator, frames: usize) -> Vec<u8> {
+    for _ in 0..frames {
+        emu.update();
+    }
+    emu.ppu().pixels.to_vec()
+}
+
+fn save(emu: &Emulator) -> Vec<u8> {
+    let mut state = Vec::new();
+    emu.save_state(&mut state);
+    state
+}
+
+// Offset of the chunk's length field
+fn find_chunk(state: &[u8], tag: &[u8; 4]) -> usize {
+    let mut pos = HEADER_SIZE;
+
+    loop {
+        let len = u32::from_le_bytes(state[(pos + 4)..(pos + 8)].try_into().unwrap()) as usize;
+        if &state[pos..(pos + 4)] == tag {
+            return pos + 4;
+        }
+        pos += 8 + len;
+    }
+}
+
+// Swaps a chunk's data for `data`, stored as plain literal packets (see rle.rs)
+fn replace_chunk(state: &mut Vec<u8>, tag: &[u8; 4], data: &[u8]) {
+    let len_pos = find_chunk(state, tag);
+    let len = u32::from_le_bytes(state[len_pos..(len_pos + 4)].try_into().unwrap()) as usize;
+
+    let mut encoded = Vec::new();
+    for packet in data.chunks(0x80) {
+        encoded.push((packet.len() - 1) as u8);
+        encoded.extend_from_slice(packet);
+    }
+
+    state.splice((len_pos + 4)..(len_pos + 4 + len), encoded.iter().cloned());
+    state[len_pos..(len_pos + 4)].copy_from_slice(&(encoded.len() as u32).to_le_bytes());
+}
+
+#[test]
+fn round_trip() {
+    let mut emu = emulator("Super_Mario_Forever_Clean_Patch.nes");
+    run(&mut emu, 60);
+
+    let mut state = save(&emu);
+    state.extend_from_slice(b"frontend data");
+
+    let expected = run(&mut emu, 60);
+
+    let mut cursor = Cursor::new(state);
+    emu.load_state(&mut cursor).unwrap();
+    assert_eq!(&cursor.get_ref()[(cursor.position() as usize)..], b"frontend data");
+
+    assert!(run(&mut emu, 60) == expected);
+}
+
+#[test]
+fn states_are_compressed() {
+    let mut emu = emulator("Super_Mario_Forever_Clean_Patch.nes");
+    run(&mut emu, 120);
+
+    // uncompressed it's over 20 KB, mostly zeros
+    assert!(save(&emu).len() < 4 * 1024);
+}
+
+#[test]
+fn rejects_other_games() {
+    let nestest = emulator("nestest.nes");
+    let mut emu = emulator("Super_Mario_Forever_Clean_Patch.nes");
+
+    let result = emu.load_state(&mut Cursor::new(save(&nestest)));
+    assert!(matches!(result, Err(StateError::WrongRom { .. })));
+}
+
+#[test]
+fn rejects_bad_headers() {
+    let mut emu = emulator("nestest.nes");
+    let state = save(&emu);
+
+    assert_eq!(emu.load_state(&mut Cursor::new(Vec::new())), Err(StateError::BadMagic));
+    assert_eq!(emu.load_state(&mut Cursor::new(b"NES\x1A".to_vec())), Err(StateError::BadMagic));
+
+    let mut newer = state.clone();
+    newer[4] += 1;
+    assert!(matches!(emu.load_state(&mut Cursor::new(newer)), Err(StateError::UnsupportedVersion(_))));
+}
+
+#[test]
+fn rejects_truncated_states() {
+    let mut emu = emulator("nestest.nes");
+    run(&mut emu, 10);
+    let state = save(&emu);
+
+    for len in [6, HEADER_SIZE - 1, HEADER_SIZE + 3, state.len() / 2, state.len() - 1] {
+        assert_eq!(emu.load_state(&mut Cursor::new(state[..len].to_vec())), Err(StateError::Truncated));
+    }
+
+    assert!(save(&emu) == state);
+}
+
+#[test]
+fn broken_chunk_leaves_the_emulator_alone() {
+    let mut emu = emulator("Super_Mario_Forever_Clean_Patch.nes");
+    run(&mut emu, 30);
+    let mut broken = save(&emu);
+
+    // the joypad chunk loses its last byte but the chunk list stays consistent, so it's only noticed after
+    // the CPU, PPU and the rest have already been loaded
+    let len_pos = find_chunk(&broken, b"JOYP");
+    let len = u32::from_le_bytes(broken[len_pos..(len_pos + 4)].try_into().unwrap());
+    broken[len_pos..(len_pos + 4)].copy_from_slice(&(len - 1).to_le_bytes());
+    broken.remove(len_pos + 4 + len as usize - 1);
+
+    run(&mut emu, 30);
+    let before = save(&emu);
+
+    assert_eq!(emu.load_state(&mut Cursor::new(broken)), Err(StateError::CorruptChunk(*b"JOYP")));
+    assert!(save(&emu) == before);
+}
+
+#[test]
+fn rejects_impossible_apu_timers() {
+    let mut emu = emulator("Super_Mario_Forever_Clean_Patch.nes");
+    run(&mut emu, 30);
+    let state = save(&emu);
+
+    let mut apu = Vec::new();
+    emu.apu().save_state(&mut apu);
+
+    // the unmodified data goes through the same path fine
+    let mut same = state.clone();
+    replace_chunk(&mut same, b"APU ", &apu);
+    assert_eq!(emu.load_state(&mut Cursor::new(same)), Ok(()));
+
+    // the DMC's timer period comes 2 bytes into its 20, which are followed by 7 bytes of frame counter
+    let dmc = apu.len() - 7 - 20;
+
+    let mut zero_period = apu.clone();
+    zero_period[(dmc + 2)..(dmc + 4)].copy_from_slice(&[0, 0]);
+
+    let mut zero_bits = apu.clone();
+    zero_bits[dmc + 8] = 0;
+
+    for data in [zero_period, zero_bits] {
+        let mut broken = state.clone();
+        replace_chunk(&mut broken, b"APU ", &data);
+
+        assert_eq!(emu.load_state(&mut Cursor::new(broken)), Err(StateError::CorruptChunk(*b"APU ")));
+        assert!(save(&emu) == state);
+    }
+
+    run(&mut emu, 30);
+}
+
+// PRG ROM which spins at $C000 in its last bank
+fn spin(banks: usize) -> Vec<u8> {
+    let mut prg_rom = vec![0xEA; banks * common::PRG_BANK_SIZE];
+    let start = prg_rom.len() - common::PRG_BANK_SIZE;
+
+    prg_rom[start..(start + 3)].copy_from_slice(&[0x4C, 0x00, 0xC0]); // JMP $C000
+    common::set_vectors(&mut prg_rom, 0xC000, 0xC000, 0xC000);
+    prg_rom
+}
+
+#[test]
+fn rejects_impossible_bank_numbers() {
+    // UxROM switching 4 PRG banks, CNROM switching 4 CHR banks and AxROM with a single 32 KB bank, all with CHR ROM.
+    // Their data is a CHR RAM flag followed by the u32 bank number, with a mirroring byte in front for AxROM
+    let uxrom = common::ines(2, 0, &spin(4), &[0; common::CHR_BANK_SIZE]);
+    let cnrom = common::ines(3, 0, &spin(1), &[0; 4 * common::CHR_BANK_SIZE]);
+    let axrom = common::ines(7, 0, &spin(2), &[0; common::CHR_BANK_SIZE]);
+
+    for (rom, bank_pos, banks) in [(uxrom, 1, 4u32), (cnrom, 1, 4), (axrom, 2, 1)] {
+        let mut emu = common::emulator(rom);
+        run(&mut emu, 2);
+        let state = save(&emu);
+
+        let mut cart = Vec::new();
+        emu.cart().save_state(&mut cart);
+        cart[bank_pos..(bank_pos + 4)].copy_from_slice(&banks.to_le_bytes());
+
+        let mut broken = state.clone();
+        replace_chunk(&mut broken, b"CART", &cart);
+
+        assert_eq!(emu.load_state(&mut Cursor::new(broken)), Err(StateError::CorruptChunk(*b"CART")));
+        assert!(save(&emu) == state);
+
+        run(&mut emu, 2);
+    }
+}
@@ -0,0 +1,178 @@
+use nesty::joypad;
+
+/*
+Scripted controller input. Each line holds a frame number and the buttons held from that frame on,
+until the next line changes them:
+
+    # frame  buttons
+    60       start
+    65       -
+    120      right+a
+    180      right+a+b
+
+Buttons are a, b, select, start, up, down, left and right joined with '+'; '-' releases everything.
+Blank lines and anything after '#' are ignored. Frames must be in increasing order.
+*/
+pub struct InputScript {
+    events: Vec<(u64, u8)>, /* (frame, button state with joypad::BUTTON_* bits) */
+    next: usize
+}
+
+impl InputScript {
+    pub fn empty() -> Self {
+        InputScript {
+            events: Vec::new(),
+            next: 0
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut events: Vec<(u64, u8)> = Vec::new();
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let mut words = line.split_whitespace();
+            let frame = words.next().unwrap_or("");
+            let buttons = words.next().unwrap_or("-");
+
+            if words.next().is_some() {
+                return Err(format!("line {}: expected a frame and buttons", i + 1));
+            }
+
+            let frame: u64 = frame.parse().map_err(|_| format!("line {}: bad frame number '{}'", i + 1, frame))?;
+
+            if let Some(&(prev, _)) = events.last() {
+                if frame <= prev {
+                    return Err(format!("line {}: frame {} doesn't come after frame {}", i + 1, frame, prev));
+                }
+            }
+
+            let state = InputScript::parse_buttons(buttons).map_err(|err| format!("line {}: {}", i + 1, err))?;
+            events.push((frame, state));
+        }
+
+        Ok(InputScript {
+            events: events,
+            next: 0
+        })
+    }
+
+    fn parse_buttons(text: &str) -> Result<u8, String> {
+        if text == "-" {
+            return Ok(0);
+        }
+
+        let mut state = 0;
+
+        for name in text.split('+') {
+            let button = match name.to_lowercase().as_str() {
+                "a" => joypad::BUTTON_A,
+                "b" => joypad::BUTTON_B,
+                "select" => joypad::BUTTON_SELECT,
+                "start" => joypad::BUTTON_START,
+                "up" => joypad::BUTTON_UP,
+                "down" => joypad::BUTTON_DOWN,
+                "left" => joypad::BUTTON_LEFT,
+                "right" => joypad::BUTTON_RIGHT,
+                _ => return Err(format!("unknown button '{}'", name))
+            };
+            state |= 1 << button;
+        }
+
+        Ok(state)
+    }
+
+    /* The button state that starts at this frame, if it changes */
+    pub fn at(&mut self, frame: u64) -> Option<u8> {
+        let mut state = None;
+
+        while self.next < self.events.len() && self.events[self.next].0 <= frame {
+            state = Some(self.events[self.next].1);
+            self.next += 1;
+        }
+
+        state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bits(buttons: &[u8]) -> u8 {
+        buttons.iter().fold(0, |state, button| state | (1 << button))
+    }
+
+    #[test]
+    fn buttons_hold_until_the_next_line() {
+        let mut script = InputScript::parse("
+            # frame  buttons
+            60       start
+            65       -
+
+            120      right+a   # jump
+        ").unwrap();
+
+        assert_eq!(script.at(0), None);
+        assert_eq!(script.at(59), None);
+        assert_eq!(script.at(60), Some(bits(&[joypad::BUTTON_START])));
+        assert_eq!(script.at(61), None);
+        assert_eq!(script.at(65), Some(0));
+        assert_eq!(script.at(120), Some(bits(&[joypad::BUTTON_RIGHT, joypad::BUTTON_A])));
+        assert_eq!(script.at(1000), None);
+    }
+
+    #[test]
+    fn skipped_frames_give_the_latest_state() {
+        let mut script = InputScript::parse("10 a\n20 b\n30 -").unwrap();
+
+        assert_eq!(script.at(25), Some(bits(&[joypad::BUTTON_B])));
+        assert_eq!(script.at(30), Some(0));
+    }
+
+    #[test]
+    fn button_names() {
+        let names = [
+            ("a", joypad::BUTTON_A),
+            ("b", joypad::BUTTON_B),
+            ("select", joypad::BUTTON_SELECT),
+            ("start", joypad::BUTTON_START),
+            ("up", joypad::BUTTON_UP),
+            ("down", joypad::BUTTON_DOWN),
+            ("left", joypad::BUTTON_LEFT),
+            ("right", joypad::BUTTON_RIGHT)
+        ];
+
+        for (name, button) in names.iter() {
+            assert_eq!(InputScript::parse_buttons(name), Ok(1 << button), "{}", name);
+        }
+
+        assert_eq!(InputScript::parse_buttons("Start+A"), Ok(bits(&[joypad::BUTTON_START, joypad::BUTTON_A])));
+        assert_eq!(InputScript::parse_buttons("a+b+select+start+up+down+left+right"), Ok(0xFF));
+        assert_eq!(InputScript::parse_buttons("-"), Ok(0));
+
+        // a frame on its own releases everything
+        assert_eq!(InputScript::parse("5").unwrap().at(5), Some(0));
+    }
+
+    #[test]
+    fn malformed_lines() {
+        let cases = [
+            ("10 a\nten b", "line 2: bad frame number 'ten'"),
+            ("-5 a", "line 1: bad frame number '-5'"),
+            ("10 a b", "line 1: expected a frame and buttons"),
+            ("10 jump", "line 1: unknown button 'jump'"),
+            ("10 a+", "line 1: unknown button ''"),
+            ("10 a\n10 b", "line 2: frame 10 doesn't come after frame 10"),
+            ("\n20 a\n10 b", "line 3: frame 10 doesn't come after frame 20")
+        ];
+
+        for (text, error) in cases.iter() {
+            assert_eq!(InputScript::parse(text).err().as_deref(), Some(*error), "{:?}", text);
+        }
+    }
+}
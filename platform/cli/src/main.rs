@@ -0,0 +1,199 @@
+mod input;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+use nesty::emulator::Emulator;
+use nesty::hash::fnv1a;
+use nesty::remote::RemoteServer;
+use nesty::ppu;
+
+use crate::input::InputScript;
+
+const USAGE: &str = "Usage: nesty-cli <rom> [options]
+
+Options:
+    --frames N          frames to run (default 60)
+    --input FILE        scripted controller input, see src/input.rs for the format
+    --screenshot FILE   save the last frame as .png or .ppm
+    --ram FILE          dump the 2 KB of internal RAM
+    --state FILE        write a save state
+    --hash              print a hash of the last frame
+    --remote PORT       wait for a remote debugger on 127.0.0.1 before running (see nesty::remote)";
+
+const DEFAULT_FRAMES: u64 = 60;
+const RAM_SIZE: u16 = 0x800;
+
+struct Options {
+    rom: PathBuf,
+    frames: u64,
+    input: Option<PathBuf>,
+    screenshot: Option<PathBuf>,
+    ram: Option<PathBuf>,
+    state: Option<PathBuf>,
+    hash: bool,
+    remote: Option<u16>
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut rom = None;
+    let mut options = Options {
+        rom: PathBuf::new(),
+        frames: DEFAULT_FRAMES,
+        input: None,
+        screenshot: None,
+        ram: None,
+        state: None,
+        hash: false,
+        remote: None
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+
+        match arg.as_str() {
+            "--frames" => options.frames = value()?.parse().map_err(|_| "--frames needs a number".to_string())?,
+            "--input" => options.input = Some(PathBuf::from(value()?)),
+            "--screenshot" => options.screenshot = Some(PathBuf::from(value()?)),
+            "--ram" => options.ram = Some(PathBuf::from(value()?)),
+            "--state" => options.state = Some(PathBuf::from(value()?)),
+            "--hash" => options.hash = true,
+            "--remote" => options.remote = Some(value()?.parse().map_err(|_| "--remote needs a port".to_string())?),
+            "-h" | "--help" => return Err(String::new()),
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+            _ if rom.is_none() => rom = Some(PathBuf::from(arg)),
+            _ => return Err(format!("Unexpected argument {}", arg))
+        }
+    }
+
+    options.rom = rom.ok_or_else(|| "No ROM given".to_string())?;
+    Ok(options)
+}
+
+fn save_screenshot(emu: &Emulator, path: &Path) -> Result<(), String> {
+    let mut file = BufWriter::new(File::create(path).map_err(|err| err.to_string())?);
+    let ppu = emu.ppu();
+
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("ppm") => ppu.write_ppm(&mut file).map_err(|err| err.to_string()),
+        Some("png") => {
+            let mut encoder = png::Encoder::new(file, ppu::WIDTH as u32, ppu::HEIGHT as u32);
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+
+            let mut writer = encoder.write_header().map_err(|err| err.to_string())?;
+            writer.write_image_data(&ppu.pixels).map_err(|err| err.to_string())
+        }
+        _ => Err("Screenshots have to be .png or .ppm".to_string())
+    }
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let rom = fs::read(&options.rom).map_err(|err| format!("Unable to read {}: {}", options.rom.display(), err))?;
+
+    let mut script = match &options.input {
+        Some(path) => {
+            let text = fs::read_to_string(path).map_err(|err| format!("Unable to read {}: {}", path.display(), err))?;
+            InputScript::parse(&text).map_err(|err| format!("{}: {}", path.display(), err))?
+        }
+        None => InputScript::empty()
+    };
+
+    let mut remote = match options.remote {
+        Some(port) => {
+            let server = RemoteServer::bind(port).map_err(|err| format!("Unable to listen on port {}: {}", port, err))?;
+            eprintln!("Remote debugging on {}", server.local_addr().unwrap());
+            Some(server)
+        }
+        None => None
+    };
+
+    let mut emu = Emulator::new();
+    emu.load_rom(rom).map_err(|err| format!("Unable to load {}: {}", options.rom.display(), err))?;
+    emu.reset();
+
+    if let Some(server) = remote.as_mut() {
+        while !server.connected() {
+            server.poll(&mut emu);
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    let mut frame = 0;
+
+    while frame < options.frames {
+        if let Some(server) = remote.as_mut() {
+            server.poll(&mut emu);
+
+            if server.paused() {
+                thread::sleep(Duration::from_millis(1));
+                continue;
+            }
+        }
+
+        if let Some(state) = script.at(frame) {
+            let mut joypad = emu.joypad();
+            for button in 0..8 {
+                if (state >> button) & 1 == 1 {
+                    joypad.press(button);
+                } else {
+                    joypad.release(button);
+                }
+            }
+        }
+
+        // a stop leaves the frame unfinished and the next update carries on with it
+        match emu.update() {
+            None => frame += 1,
+            Some(reason) => match remote.as_mut() {
+                Some(server) => server.stopped(&mut emu, reason),
+                None => eprintln!("Stopped at frame {}: {:?}", frame, reason)
+            }
+        }
+    }
+
+    if let Some(path) = &options.screenshot {
+        save_screenshot(&emu, path).map_err(|err| format!("Unable to save {}: {}", path.display(), err))?;
+    }
+
+    if let Some(path) = &options.ram {
+        let bus = emu.bus();
+        let ram: Vec<u8> = (0..RAM_SIZE).map(|addr| bus.peek_byte(addr)).collect();
+        fs::write(path, ram).map_err(|err| format!("Unable to save {}: {}", path.display(), err))?;
+    }
+
+    if let Some(path) = &options.state {
+        let mut state = Vec::new();
+        emu.save_state(&mut state);
+        fs::write(path, state).map_err(|err| format!("Unable to save {}: {}", path.display(), err))?;
+    }
+
+    if options.hash {
+        println!("{:016x}", fnv1a(&emu.ppu().pixels));
+    }
+
+    Ok(())
+}
+
+pub fn main() {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(err) => {
+            if !err.is_empty() {
+                eprintln!("{}\n", err);
+            }
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(err) = run(&options) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
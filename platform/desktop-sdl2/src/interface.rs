@@ -5,6 +5,8 @@ use std::io::prelude::*;
 use std::path::PathBuf;
 use std::collections::HashMap;
 
+use sdl2::AudioSubsystem;
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
 use sdl2::render::Texture;
 use sdl2::keyboard::Keycode;
 
@@ -12,7 +14,30 @@ use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use native_dialog::{FileDialog, MessageDialog, MessageType};
 
 use nesty::emulator::*;
-use nesty::{savable::Savable, ppu, joypad};
+use nesty::remote::RemoteServer;
+use nesty::rewind::Rewind;
+use nesty::{ppu, joypad};
+
+use crate::slots::{self, Slots, SLOT_COUNT, THUMBNAIL_WIDTH, THUMBNAIL_HEIGHT};
+
+const AUDIO_SAMPLE_RATE: i32 = 44100;
+const AUDIO_DEVICE_SAMPLES: u16 = 512;
+
+// Number of samples we try to keep queued up (about 3 frames); more means more latency, less means crackles
+const AUDIO_TARGET_QUEUED: u32 = 2048;
+
+// Maximum adjustment of the sample rate made by the dynamic rate control
+const MAX_RATE_DELTA: f64 = 0.005;
+
+// How often battery backed RAM gets written out while playing (it's also written when quitting or switching games)
+const BATTERY_SAVE_INTERVAL: u32 = 60 * 10;
+
+// Rewind history: a snapshot every frame for smooth playback, in at most 32 MB (a few minutes of play)
+const REWIND_INTERVAL: u32 = 1;
+const REWIND_MEMORY: usize = 32 * 1024 * 1024;
+
+// How long a slot's thumbnail stays on screen after selecting or saving it
+const SLOT_OVERLAY_FRAMES: u32 = 120;
 
 lazy_static! {
     static ref KEY_MAP: HashMap<Keycode, u8> = {
@@ -33,6 +58,21 @@ lazy_static! {
 
 pub struct Nesty {
     nes: Emulator,
+    audio: Option<AudioQueue<f32>>,
+    remote: Option<RemoteServer>,
+
+    rewind: Rewind,
+    rewinding: bool,
+
+    rom_path: Option<PathBuf>,
+    battery: Vec<u8>, /* what's in the .sav file right now, so unchanged RAM isn't written over and over */
+    battery_frames: u32,
+
+    slot: usize,
+    quick_save: Option<usize>, /* slot the pending save goes to, None when it goes to a file from the dialog */
+    slot_overlay: Vec<u8>,
+    slot_overlay_frames: u32,
+    title: Option<String>, /* new window title for main to pick up */
 
     saving: bool,
     wait_for_nmi: bool,
@@ -44,6 +84,18 @@ impl Nesty {
     pub fn new() -> Self {
         Nesty {
             nes: Emulator::new(),
+            audio: None,
+            remote: None,
+            rewind: Rewind::new(REWIND_INTERVAL, REWIND_MEMORY),
+            rewinding: false,
+            rom_path: None,
+            battery: Vec::new(),
+            battery_frames: 0,
+            slot: 0,
+            quick_save: None,
+            slot_overlay: Vec::new(),
+            slot_overlay_frames: 0,
+            title: None,
             saving: false,
             wait_for_nmi: false,
             path: None
@@ -54,16 +106,89 @@ impl Nesty {
         self.nes.reset();
     }
 
+    pub fn init_audio(&mut self, audio_subsystem: &AudioSubsystem) {
+        let desired_spec = AudioSpecDesired {
+            freq: Some(AUDIO_SAMPLE_RATE),
+            channels: Some(1),
+            samples: Some(AUDIO_DEVICE_SAMPLES)
+        };
+
+        // Without an audio device we just run silently and the main loop falls back to timer based pacing
+        if let Ok(queue) = audio_subsystem.open_queue::<f32, _>(None, &desired_spec) {
+            self.nes.apu().set_sample_rate(queue.spec().freq as f64);
+            queue.resume();
+            self.audio = Some(queue);
+        }
+    }
+
+    pub fn listen(&mut self, port: u16) {
+        match RemoteServer::bind(port) {
+            Ok(server) => {
+                println!("Remote debugging on {}", server.local_addr().unwrap());
+                self.remote = Some(server);
+            }
+            Err(err) => eprintln!("Unable to listen on port {}: {}", port, err)
+        }
+    }
+
+    pub fn has_audio(&self) -> bool {
+        self.audio.is_some()
+    }
+
+    // Used for frame pacing: the next frame should only be emulated once the audio device has played
+    // enough of the queued samples
+    pub fn audio_ahead(&self) -> bool {
+        match &self.audio {
+            Some(queue) => Nesty::queued_samples(queue) > AUDIO_TARGET_QUEUED,
+            None => false
+        }
+    }
+
+    fn queued_samples(queue: &AudioQueue<f32>) -> u32 {
+        queue.size() / (std::mem::size_of::<f32>() as u32)
+    }
+
+    fn queue_audio(&mut self) {
+        let mut samples = self.nes.apu().drain_samples();
+
+        // the frames replayed while rewinding sound wrong, but silence still has to be queued to keep the pace
+        if self.rewinding {
+            samples.iter_mut().for_each(|sample| *sample = 0.0);
+        }
+
+        if let Some(queue) = &self.audio {
+            let queued = Nesty::queued_samples(queue);
+
+            queue.queue(&samples);
+
+            // Dynamic rate control: produce slightly more samples when the queue is running low and slightly fewer
+            // when it fills up, so the emulator never drifts away from the audio clock
+            let fill = (queued as f64) / (AUDIO_TARGET_QUEUED as f64);
+            let delta = ((1.0 - fill) * MAX_RATE_DELTA).clamp(-MAX_RATE_DELTA, MAX_RATE_DELTA);
+
+            self.nes.apu().set_sample_rate((queue.spec().freq as f64) * (1.0 + delta));
+        }
+    }
+
     pub fn open_rom(&mut self) {
         let path = FileDialog::new()
             .add_filter(".nes ROM", &["nes"])
             .show_open_single_file()
             .expect("There are problems when creating a file dialog");
         if !path.is_none() {
-            let rom = fs::read(path.unwrap()).unwrap();
+            let path = path.unwrap();
+            let rom = fs::read(&path).unwrap();
+
+            self.save_battery();
+
             let result = self.nes.load_rom(rom);
             match result {
-                Ok(_) => self.nes.reset(),
+                Ok(_) => {
+                    self.nes.reset();
+                    self.rewind.clear();
+                    self.rom_path = Some(path);
+                    self.load_battery();
+                }
                 Err(err) => {
                     let _ = MessageDialog::new()
                         .set_type(MessageType::Error)
@@ -76,21 +201,115 @@ impl Nesty {
         }
     }
 
+    // Battery backed RAM lives next to the ROM, e.g. zelda.nes -> zelda.sav
+    fn battery_path(&self) -> Option<PathBuf> {
+        self.rom_path.as_ref().map(|path| path.with_extension("sav"))
+    }
+
+    fn load_battery(&mut self) {
+        if let Some(path) = self.battery_path() {
+            if let Ok(data) = fs::read(&path) {
+                if !self.nes.load_battery_ram(&data) {
+                    eprintln!("Ignoring {}: it doesn't fit this game", path.display());
+                }
+            }
+        }
+
+        self.battery = self.nes.battery_ram().unwrap_or_default();
+        self.battery_frames = 0;
+    }
+
+    pub fn save_battery(&mut self) {
+        if let (Some(path), Some(sram)) = (self.battery_path(), self.nes.battery_ram()) {
+            if sram != self.battery {
+                match fs::write(&path, &sram) {
+                    Ok(_) => self.battery = sram,
+                    Err(err) => eprintln!("Unable to save {}: {}", path.display(), err)
+                }
+            }
+        }
+    }
+
     pub fn save_state(&mut self) {
         let path = FileDialog::new()
-            .add_filter(".sav", &["sav"])
+            .add_filter(".state", &["state"])
             .show_save_single_file()
             .expect("There are problems when creating a file dialog");
         if !path.is_none() {
             self.path = path;
+            self.quick_save = None;
             self.saving = true;
             self.wait_for_nmi = true;
         }
     }
 
+    pub fn select_slot(&mut self, slot: usize) {
+        self.slot = slot % SLOT_COUNT;
+        self.show_slot();
+    }
+
+    pub fn save_slot(&mut self) {
+        self.quick_save = Some(self.slot);
+        self.saving = true;
+        self.wait_for_nmi = true;
+    }
+
+    pub fn load_slot(&mut self) {
+        let slots = Slots::new(self.nes.rom_hash());
+
+        match slots.state_path(self.slot) {
+            Ok(path) if path.exists() => {
+                self.path = Some(path);
+                self.saving = false;
+                self.wait_for_nmi = true;
+            }
+            _ => self.show_slot()
+        }
+    }
+
+    // Puts the slot's details in the title bar and its thumbnail in the corner of the screen
+    fn show_slot(&mut self) {
+        match Slots::new(self.nes.rom_hash()).info(self.slot) {
+            Some(info) => {
+                self.title = Some(format!("NESTY - slot {}: saved {}", self.slot, slots::describe_age(info.timestamp)));
+                self.slot_overlay = info.thumbnail;
+                self.slot_overlay_frames = SLOT_OVERLAY_FRAMES;
+            }
+            None => {
+                self.title = Some(format!("NESTY - slot {}: empty", self.slot));
+                self.slot_overlay_frames = 0;
+            }
+        }
+    }
+
+    pub fn take_title(&mut self) -> Option<String> {
+        self.title.take()
+    }
+
+    fn present(&mut self, texture: &mut Texture) {
+        if self.slot_overlay_frames == 0 {
+            texture.update(None, &self.nes.ppu().pixels, ppu::WIDTH * 4).unwrap();
+            return;
+        }
+
+        self.slot_overlay_frames -= 1;
+
+        let mut pixels = self.nes.ppu().pixels.clone();
+        let left = ppu::WIDTH - THUMBNAIL_WIDTH - 8;
+        let top = 8;
+
+        for y in 0..THUMBNAIL_HEIGHT {
+            let src = y * THUMBNAIL_WIDTH * 4;
+            let dst = ((top + y) * ppu::WIDTH + left) * 4;
+            pixels[dst..(dst + THUMBNAIL_WIDTH * 4)].copy_from_slice(&self.slot_overlay[src..(src + THUMBNAIL_WIDTH * 4)]);
+        }
+
+        texture.update(None, &pixels, ppu::WIDTH * 4).unwrap();
+    }
+
     pub fn load_state(&mut self) {
         let path = FileDialog::new()
-            .add_filter(".sav", &["sav"])
+            .add_filter(".state", &["state"])
             .show_open_single_file()
             .expect("There are problems when creating a file dialog");
         if !path.is_none() {
@@ -102,33 +321,102 @@ impl Nesty {
 
     pub fn update(&mut self, texture: &mut Texture) {
         let mut total: u64 = 0;
+        let mut stopped = false; /* a remote debugger stopped us before the frame was over */
+
+        if let Some(remote) = self.remote.as_mut() {
+            remote.poll(&mut self.nes);
+
+            if remote.paused() {
+                self.present(texture);
+                return;
+            }
+        }
+
+        if self.rewinding {
+            self.rewind.step_back(&mut self.nes);
+
+            self.present(texture);
+            self.queue_audio();
+            return;
+        }
 
         while total < CYCLES_PER_FRAME {
             if self.wait_for_nmi && self.nes.ppu().nmi {
                 if self.saving {
-                    let mut file = File::create(self.path.as_ref().unwrap()).expect("Unable to create save file");
                     let mut state = Vec::new();
 
                     self.nes.save_state(&mut state);
                     state.write_u64::<LittleEndian>(total).expect("Unable to save u64");
-                    file.write_all(&state).expect("Unable to write to the save file");
+
+                    match self.quick_save.take() {
+                        Some(slot) => {
+                            let result = Slots::new(self.nes.rom_hash()).save(slot, &state, &self.nes.ppu().pixels);
+                            match result {
+                                Ok(_) => self.show_slot(),
+                                Err(err) => eprintln!("Unable to save slot {}: {}", slot, err)
+                            }
+                        }
+                        None => {
+                            let mut file = File::create(self.path.as_ref().unwrap()).expect("Unable to create save file");
+                            file.write_all(&state).expect("Unable to write to the save file");
+                        }
+                    }
                 } else {
                     let mut file = File::open(self.path.as_ref().unwrap()).expect("Unable to open the save file");
                     let mut buffer = Vec::new();
                     file.read_to_end(&mut buffer).expect("Unable to read the save file");
                     let mut cursor = Cursor::new(buffer);
 
-                    self.nes.load_state(&mut cursor);
-                    total = cursor.read_u64::<LittleEndian>().expect("Unable to load u64");
+                    match self.nes.load_state(&mut cursor) {
+                        Ok(_) => {
+                            total = cursor.read_u64::<LittleEndian>().unwrap_or(0);
+                            self.rewind.clear();
+                        }
+                        Err(err) => {
+                            let _ = MessageDialog::new()
+                                .set_type(MessageType::Error)
+                                .set_title("Error loading state")
+                                .set_text(&format!("{}", err))
+                                .show_confirm()
+                                .unwrap();
+                        }
+                    }
                 }
     
                 self.wait_for_nmi = false;
             }
 
-            total += self.nes.tick();
+            let (cycles, reason) = self.nes.tick();
+            total += cycles;
+
+            if let Some(reason) = reason {
+                if let Some(remote) = self.remote.as_mut() {
+                    remote.stopped(&mut self.nes, reason);
+                }
+                stopped = true;
+                break;
+            }
         }
 
-        texture.update(None, &self.nes.ppu().pixels, ppu::WIDTH * 4).unwrap();
+        self.present(texture);
+
+        self.queue_audio();
+
+        // a snapshot has to be a whole frame after the last one, or stepping back wouldn't be a frame anymore
+        if !stopped {
+            self.rewind.capture(&self.nes);
+        }
+
+        self.battery_frames += 1;
+        if self.battery_frames >= BATTERY_SAVE_INTERVAL {
+            self.battery_frames = 0;
+            self.save_battery();
+        }
+    }
+
+    // Held down to play the game backwards
+    pub fn set_rewinding(&mut self, rewinding: bool) {
+        self.rewinding = rewinding;
     }
 
     pub fn press_key(&mut self, keycode: Keycode) {
@@ -4,7 +4,9 @@
 extern crate lazy_static;
 
 mod interface;
+mod slots;
 
+use std::env;
 use std::process;
 use std::thread;
 use std::time::Duration;
@@ -17,16 +19,42 @@ use nesty::ppu;
 
 use crate::interface::Nesty;
 
-const DELAY: u32 = 17; // 1000ms / 59.7fps
+const DELAY: u32 = 17; // 1000ms / 59.7fps, only used if there's no audio device
+
+// Number keys pick the quick save slot
+fn slot_key(keycode: Keycode) -> Option<usize> {
+    let keys = [
+        Keycode::Num0, Keycode::Num1, Keycode::Num2, Keycode::Num3, Keycode::Num4,
+        Keycode::Num5, Keycode::Num6, Keycode::Num7, Keycode::Num8, Keycode::Num9
+    ];
+
+    keys.iter().position(|&key| key == keycode)
+}
 
 pub fn main() {
     let mut nesty = Nesty::new();
 
     nesty.init();
 
+    // nesty-desktop-sdl2 [--remote PORT] lets outside tools debug the running game (see nesty::remote)
+    let args: Vec<String> = env::args().collect();
+    if let Some(i) = args.iter().position(|arg| arg == "--remote") {
+        match args.get(i + 1).and_then(|port| port.parse::<u16>().ok()) {
+            Some(port) => nesty.listen(port),
+            None => {
+                eprintln!("Usage: nesty-desktop-sdl2 [--remote PORT]");
+                process::exit(1);
+            }
+        }
+    }
+
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
 
+    if let Ok(audio_subsystem) = sdl_context.audio() {
+        nesty.init_audio(&audio_subsystem);
+    }
+
     let window = video_subsystem.window("NESTY", (ppu::WIDTH * 2) as u32, (ppu::HEIGHT * 2) as u32)
         .position_centered()
         .build()
@@ -51,7 +79,10 @@ pub fn main() {
     loop {
         for event in event_pump.poll_iter() {
             match event {
-                Event::Quit { .. } => process::exit(0),
+                Event::Quit { .. } => {
+                    nesty.save_battery();
+                    process::exit(0);
+                }
                 Event::KeyDown { keycode, .. } => {
                     if matches!(keycode.unwrap(), Keycode::F9) {
                         nesty.open_rom();
@@ -59,23 +90,48 @@ pub fn main() {
                         nesty.save_state();
                     } else if matches!(keycode.unwrap(), Keycode::F11) {
                         nesty.load_state();
+                    } else if matches!(keycode.unwrap(), Keycode::Backspace) {
+                        nesty.set_rewinding(true);
+                    } else if matches!(keycode.unwrap(), Keycode::F5) {
+                        nesty.save_slot();
+                    } else if matches!(keycode.unwrap(), Keycode::F7) {
+                        nesty.load_slot();
+                    } else if let Some(slot) = slot_key(keycode.unwrap()) {
+                        nesty.select_slot(slot);
                     } else {
                         nesty.press_key(keycode.unwrap());
                     }
                 }
                 Event::KeyUp { keycode, .. } => {
-                    nesty.release_key(keycode.unwrap());
+                    if matches!(keycode.unwrap(), Keycode::Backspace) {
+                        nesty.set_rewinding(false);
+                    } else {
+                        nesty.release_key(keycode.unwrap());
+                    }
                 }
                 _ => {}
             }
         }
 
+        // Frame pacing is driven by the audio clock: wait until the audio device has caught up with us
+        while nesty.audio_ahead() {
+            thread::sleep(Duration::from_millis(1));
+        }
+
         nesty.update(&mut texture);
 
+        if let Some(title) = nesty.take_title() {
+            canvas.window_mut().set_title(&title).unwrap();
+        }
+
         canvas.clear();
         canvas.copy(&texture, None, None).unwrap();
         canvas.present();
 
+        if nesty.has_audio() {
+            continue;
+        }
+
         let now = timer_subsystem.ticks();
         let delay = if now < next {
             next - now
@@ -0,0 +1,112 @@
+use std::fs;
+use std::io;
+use std::io::Cursor;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use nesty::ppu;
+
+pub const SLOT_COUNT: usize = 10;
+
+pub const THUMBNAIL_WIDTH: usize = ppu::WIDTH / 2;
+pub const THUMBNAIL_HEIGHT: usize = ppu::HEIGHT / 2;
+
+pub struct SlotInfo {
+    pub timestamp: u64, /* seconds since the unix epoch */
+    pub thumbnail: Vec<u8> /* RGBA, THUMBNAIL_WIDTH x THUMBNAIL_HEIGHT */
+}
+
+/*
+Quick save slots, kept in the user's data directory with a folder for every game:
+
+    <data dir>/nesty/states/<ROM hash>/slot3.state  the state, same as the ones saved through the file dialog
+    <data dir>/nesty/states/<ROM hash>/slot3.info   u64 timestamp, then the thumbnail
+
+The data directory is ~/.local/share on Linux, %APPDATA% on Windows and ~/Library/Application Support on macOS.
+*/
+pub struct Slots {
+    dir: Option<PathBuf>
+}
+
+impl Slots {
+    pub fn new(rom_hash: u64) -> Self {
+        Slots {
+            dir: dirs::data_dir().map(|dir| dir.join("nesty").join("states").join(format!("{:016x}", rom_hash)))
+        }
+    }
+
+    fn path(&self, slot: usize, extension: &str) -> io::Result<PathBuf> {
+        match &self.dir {
+            Some(dir) => Ok(dir.join(format!("slot{}.{}", slot, extension))),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "There's no data directory for save slots"))
+        }
+    }
+
+    pub fn state_path(&self, slot: usize) -> io::Result<PathBuf> {
+        self.path(slot, "state")
+    }
+
+    pub fn save(&self, slot: usize, state: &[u8], pixels: &[u8]) -> io::Result<()> {
+        let path = self.state_path(slot)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        fs::write(path, state)?;
+
+        let mut info = Vec::new();
+        info.write_u64::<LittleEndian>(unix_time()).expect("Unable to save u64");
+        info.extend(thumbnail(pixels));
+
+        fs::write(self.path(slot, "info")?, info)
+    }
+
+    pub fn info(&self, slot: usize) -> Option<SlotInfo> {
+        let mut info = Cursor::new(fs::read(self.path(slot, "info").ok()?).ok()?);
+        let timestamp = info.read_u64::<LittleEndian>().ok()?;
+
+        let thumbnail = info.get_ref()[(info.position() as usize)..].to_vec();
+        if thumbnail.len() != THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT * 4 {
+            return None;
+        }
+
+        Some(SlotInfo {
+            timestamp: timestamp,
+            thumbnail: thumbnail
+        })
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs())
+}
+
+// Every other pixel of every other line
+fn thumbnail(pixels: &[u8]) -> Vec<u8> {
+    let mut thumbnail = Vec::with_capacity(THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT * 4);
+
+    for y in 0..THUMBNAIL_HEIGHT {
+        for x in 0..THUMBNAIL_WIDTH {
+            let offset = ((y * 2) * ppu::WIDTH + x * 2) * 4;
+            thumbnail.extend_from_slice(&pixels[offset..(offset + 4)]);
+        }
+    }
+
+    thumbnail
+}
+
+/* "just now", "5 minutes ago", "3 days ago"... */
+pub fn describe_age(timestamp: u64) -> String {
+    let age = unix_time().saturating_sub(timestamp);
+
+    let (amount, unit) = match age {
+        0..=59 => return "just now".to_string(),
+        60..=3599 => (age / 60, "minute"),
+        3600..=86399 => (age / 3600, "hour"),
+        _ => (age / 86400, "day")
+    };
+
+    format!("{} {}{} ago", amount, unit, if amount == 1 { "" } else { "s" })
+}
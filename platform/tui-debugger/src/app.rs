@@ -0,0 +1,117 @@
+use nesty::emulator::Emulator;
+use nesty::debugger::StopReason;
+
+use crate::command::{self, Command};
+
+pub struct App {
+    pub emu: Emulator,
+
+    pub running: bool,
+    pub quit: bool,
+
+    pub input: String,
+    last_command: String,
+    pub message: String,
+
+    pub memory_base: u16
+}
+
+impl App {
+    pub fn new(emu: Emulator) -> Self {
+        App {
+            emu: emu,
+
+            running: false,
+            quit: false,
+
+            input: String::new(),
+            last_command: String::new(),
+            message: command::HELP.to_string(),
+
+            memory_base: 0x0000
+        }
+    }
+
+    /* An empty line repeats the previous command, so stepping is just pressing enter */
+    pub fn submit(&mut self) {
+        let line = std::mem::take(&mut self.input);
+        let line = if line.trim().is_empty() {
+            self.last_command.clone()
+        } else {
+            line
+        };
+
+        if line.trim().is_empty() {
+            return;
+        }
+
+        match command::parse(line.trim()) {
+            Ok(command) => {
+                self.execute(command);
+                self.last_command = line;
+            }
+            Err(err) => self.message = err
+        }
+    }
+
+    pub fn execute(&mut self, command: Command) {
+        match command {
+            Command::Step => self.run(|emu| emu.step_into()),
+            Command::Next => self.run(|emu| emu.step_over()),
+            Command::Out => self.run(|emu| emu.step_out()),
+            Command::Continue => self.run(|emu| emu.debugger().resume()),
+            Command::Pause => self.pause(),
+            Command::Frame => self.run(|emu| emu.run_to_next_frame()),
+            Command::Scanline(scanline) => self.run(|emu| emu.run_to_scanline(scanline)),
+            Command::Break(kind, condition) => {
+                let id = self.emu.debugger().add_breakpoint(kind, condition);
+                self.message = format!("Breakpoint {} set", id);
+            }
+            Command::Delete(id) => {
+                self.message = if self.emu.debugger().remove_breakpoint(id) {
+                    format!("Breakpoint {} deleted", id)
+                } else {
+                    format!("No breakpoint {}", id)
+                };
+            }
+            Command::Enable(id, enabled) => {
+                if !self.emu.debugger().set_breakpoint_enabled(id, enabled) {
+                    self.message = format!("No breakpoint {}", id);
+                }
+            }
+            Command::Memory(addr) => self.memory_base = addr & 0xFFF0,
+            Command::Reset => {
+                self.emu.reset();
+                self.message = "Reset".to_string();
+            }
+            Command::Help => self.message = command::HELP.to_string(),
+            Command::Quit => self.quit = true
+        }
+    }
+
+    fn run<F: FnOnce(&mut Emulator)>(&mut self, arm: F) {
+        arm(&mut self.emu);
+        self.running = true;
+        self.message = "Running".to_string();
+    }
+
+    pub fn pause(&mut self) {
+        self.emu.debugger().resume(); // forget any unfinished step
+        self.running = false;
+        self.message = "Paused".to_string();
+    }
+
+    // Runs what's left of the current frame, or until the debugger stops it
+    pub fn run_frame(&mut self) {
+        if let Some(reason) = self.emu.update() {
+            self.running = false;
+            self.message = match reason {
+                StopReason::Breakpoint(id) => format!("Hit breakpoint {}", id),
+                StopReason::Step => "Stepped".to_string(),
+                StopReason::Scanline(scanline) => format!("Reached scanline {}", scanline),
+                StopReason::Frame => "Reached the next frame".to_string(),
+                StopReason::Halted => "CPU halted (KIL)".to_string()
+            };
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use nesty::debugger::{BreakpointKind, Condition, parse_hex, parse_ppu_register, parse_range};
+
+pub const HELP: &str = "s step | n next | o out | c continue | f frame | sl N | b ADDR | rb/wb A[-B] | pr/pw REG | ... if COND | d/en/dis ID | m ADDR | reset | q";
+
+#[derive(PartialEq, Debug)]
+pub enum Command {
+    Step,
+    Next,
+    Out,
+    Continue,
+    Pause,
+    Frame,
+    Scanline(i32),
+    Break(BreakpointKind, Option<Condition>),
+    Delete(usize),
+    Enable(usize, bool),
+    Memory(u16),
+    Reset,
+    Help,
+    Quit
+}
+
+/*
+Parses one line typed at the prompt, e.g.
+
+    b C000
+    wb $0300-$03FF if A>=$80
+    pw PPUCTRL if [0000]==1
+    sl 241
+
+Addresses and values are hex (with or without $ or 0x); scanlines and breakpoint ids are decimal.
+*/
+pub fn parse(line: &str) -> Result<Command, String> {
+    let (line, condition) = match line.find(" if ") {
+        Some(i) => (&line[..i], Some(line[(i + 4)..].trim().parse::<Condition>()?)),
+        None => (line, None)
+    };
+
+    let mut words = line.split_whitespace();
+    let name = words.next().unwrap_or("");
+    let arg = words.next();
+
+    if words.next().is_some() {
+        return Err(format!("Too many arguments to '{}'", name));
+    }
+
+    let command = match name {
+        "s" | "step" => Command::Step,
+        "n" | "next" => Command::Next,
+        "o" | "out" => Command::Out,
+        "c" | "continue" => Command::Continue,
+        "p" | "pause" => Command::Pause,
+        "f" | "frame" => Command::Frame,
+        "sl" | "scanline" => Command::Scanline(parse_decimal(required(arg)?)?),
+        "b" | "break" => Command::Break(BreakpointKind::Execute(parse_hex(required(arg)?)?), condition),
+        "rb" => {
+            let (start, end) = parse_range(required(arg)?)?;
+            Command::Break(BreakpointKind::Read { start: start, end: end }, condition)
+        }
+        "wb" => {
+            let (start, end) = parse_range(required(arg)?)?;
+            Command::Break(BreakpointKind::Write { start: start, end: end }, condition)
+        }
+        "pr" => Command::Break(BreakpointKind::PpuRegisterRead(parse_ppu_register(required(arg)?)?), condition),
+        "pw" => Command::Break(BreakpointKind::PpuRegisterWrite(parse_ppu_register(required(arg)?)?), condition),
+        "d" | "delete" => Command::Delete(parse_decimal(required(arg)?)?),
+        "en" | "enable" => Command::Enable(parse_decimal(required(arg)?)?, true),
+        "dis" | "disable" => Command::Enable(parse_decimal(required(arg)?)?, false),
+        "m" | "mem" => Command::Memory(parse_hex(required(arg)?)?),
+        "reset" => Command::Reset,
+        "h" | "help" => Command::Help,
+        "q" | "quit" => Command::Quit,
+        _ => return Err(format!("Unknown command '{}', type 'help'", name))
+    };
+
+    if condition.is_some() && !matches!(command, Command::Break(..)) {
+        return Err("Only breakpoints take a condition".to_string());
+    }
+
+    Ok(command)
+}
+
+fn required(arg: Option<&str>) -> Result<&str, String> {
+    arg.ok_or_else(|| "Missing argument".to_string())
+}
+
+fn parse_decimal<T: std::str::FromStr>(text: &str) -> Result<T, String> {
+    text.parse().map_err(|_| format!("Bad number '{}'", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use nesty::debugger::{Comparison, Register};
+
+    use super::*;
+
+    #[test]
+    fn aliases() {
+        let cases = [
+            (["s", "step"], Command::Step),
+            (["n", "next"], Command::Next),
+            (["o", "out"], Command::Out),
+            (["c", "continue"], Command::Continue),
+            (["p", "pause"], Command::Pause),
+            (["f", "frame"], Command::Frame),
+            (["h", "help"], Command::Help),
+            (["q", "quit"], Command::Quit),
+            (["reset", "  reset  "], Command::Reset)
+        ];
+
+        for (names, command) in cases.iter() {
+            for name in names.iter() {
+                assert_eq!(parse(name).as_ref(), Ok(command), "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn arguments() {
+        // scanlines and ids are decimal, addresses hex
+        assert_eq!(parse("sl 241"), Ok(Command::Scanline(241)));
+        assert_eq!(parse("scanline -1"), Ok(Command::Scanline(-1)));
+        assert_eq!(parse("d 10"), Ok(Command::Delete(10)));
+        assert_eq!(parse("en 3"), Ok(Command::Enable(3, true)));
+        assert_eq!(parse("disable 3"), Ok(Command::Enable(3, false)));
+        assert_eq!(parse("m 10"), Ok(Command::Memory(0x10)));
+        assert_eq!(parse("mem $6000"), Ok(Command::Memory(0x6000)));
+        assert_eq!(parse("m 0xFF"), Ok(Command::Memory(0xFF)));
+    }
+
+    #[test]
+    fn breakpoints() {
+        assert_eq!(parse("b C000"), Ok(Command::Break(BreakpointKind::Execute(0xC000), None)));
+        assert_eq!(parse("break $8000"), Ok(Command::Break(BreakpointKind::Execute(0x8000), None)));
+        assert_eq!(parse("rb 0300"), Ok(Command::Break(BreakpointKind::Read { start: 0x300, end: 0x300 }, None)));
+        assert_eq!(parse("wb $0300-$03FF"), Ok(Command::Break(BreakpointKind::Write { start: 0x300, end: 0x3FF }, None)));
+        assert_eq!(parse("pr PPUSTATUS"), Ok(Command::Break(BreakpointKind::PpuRegisterRead(2), None)));
+        assert_eq!(parse("pw 2006"), Ok(Command::Break(BreakpointKind::PpuRegisterWrite(6), None)));
+        assert_eq!(parse("pw 7"), Ok(Command::Break(BreakpointKind::PpuRegisterWrite(7), None)));
+    }
+
+    #[test]
+    fn conditions() {
+        let a = Condition::Register { register: Register::A, comparison: Comparison::GreaterOrEqual, value: 0x80 };
+        assert_eq!(parse("wb $0300-$03FF if A>=$80"), Ok(Command::Break(BreakpointKind::Write { start: 0x300, end: 0x3FF }, Some(a))));
+
+        let memory = Condition::Memory { addr: 0, comparison: Comparison::Equal, value: 1 };
+        assert_eq!(parse("pw PPUCTRL if [0000]==1"), Ok(Command::Break(BreakpointKind::PpuRegisterWrite(0), Some(memory))));
+
+        let x = Condition::Register { register: Register::X, comparison: Comparison::NotEqual, value: 0 };
+        assert_eq!(parse("b C000 if  X != 0 "), Ok(Command::Break(BreakpointKind::Execute(0xC000), Some(x))));
+    }
+
+    #[test]
+    fn errors() {
+        let cases = [
+            ("", "Unknown command '', type 'help'"),
+            ("jump C000", "Unknown command 'jump', type 'help'"),
+            ("b", "Missing argument"),
+            ("sl", "Missing argument"),
+            ("b C000 C001", "Too many arguments to 'b'"),
+            ("b C000 if X", "No comparison in condition 'X'"),
+            ("s if X==1", "Only breakpoints take a condition"),
+            ("m 0300 if A==1", "Only breakpoints take a condition"),
+            ("b ZZZZ", "Bad hex number 'ZZZZ'"),
+            ("b 10000", "Bad hex number '10000'"),
+            ("wb 0300-ZZ", "Bad hex number 'ZZ'"),
+            ("pr 4000", "'4000' is not a PPU register"),
+            ("sl C0", "Bad number 'C0'"),
+            ("d -1", "Bad number '-1'")
+        ];
+
+        for (line, error) in cases.iter() {
+            assert_eq!(parse(line).err().as_deref(), Some(*error), "{}", line);
+        }
+    }
+}
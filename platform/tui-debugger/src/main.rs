@@ -0,0 +1,125 @@
+mod app;
+mod command;
+mod ui;
+
+use std::env;
+use std::fs;
+use std::io::{self, Stdout};
+use std::panic;
+use std::process;
+use std::time::{Duration, Instant};
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::execute;
+use crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+
+use ratatui::Terminal;
+use ratatui::backend::CrosstermBackend;
+
+use nesty::emulator::Emulator;
+
+use crate::app::App;
+use crate::command::Command;
+
+const FRAME_TIME: Duration = Duration::from_micros(16_639); // 1s / 60.1fps
+const IDLE_POLL: Duration = Duration::from_millis(250);
+
+fn restore_terminal() {
+    let _ = terminal::disable_raw_mode();
+    let _ = execute!(io::stdout(), LeaveAlternateScreen);
+}
+
+fn handle_key(app: &mut App, key: KeyEvent) {
+    match key.code {
+        KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => app.quit = true,
+        KeyCode::Char(c) => app.input.push(c),
+        KeyCode::Backspace => { app.input.pop(); }
+        KeyCode::Enter => app.submit(),
+        KeyCode::Esc => {
+            if app.running {
+                app.pause();
+            } else {
+                app.input.clear();
+            }
+        }
+        KeyCode::F(5) => app.execute(Command::Continue),
+        KeyCode::F(10) => app.execute(Command::Next),
+        KeyCode::F(11) => app.execute(Command::Step),
+        KeyCode::PageUp => app.memory_base = app.memory_base.wrapping_sub(0x100),
+        KeyCode::PageDown => app.memory_base = app.memory_base.wrapping_add(0x100),
+        _ => {}
+    }
+}
+
+fn run(terminal: &mut Terminal<CrosstermBackend<Stdout>>, app: &mut App) -> io::Result<()> {
+    let mut next_frame = Instant::now();
+
+    while !app.quit {
+        terminal.draw(|f| ui::draw(f, app))?;
+
+        let timeout = if app.running {
+            next_frame.saturating_duration_since(Instant::now())
+        } else {
+            IDLE_POLL
+        };
+
+        if event::poll(timeout)? {
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press {
+                    handle_key(app, key);
+                }
+            }
+        }
+
+        if app.running && Instant::now() >= next_frame {
+            app.run_frame();
+            next_frame = Instant::now() + FRAME_TIME;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn main() {
+    let path = match env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("Usage: nesty-tui-debugger <rom>");
+            process::exit(1);
+        }
+    };
+
+    let rom = fs::read(&path).unwrap_or_else(|err| {
+        eprintln!("Unable to read {}: {}", path, err);
+        process::exit(1);
+    });
+
+    let mut emu = Emulator::new();
+    if let Err(err) = emu.load_rom(rom) {
+        eprintln!("Unable to load {}: {}", path, err);
+        process::exit(1);
+    }
+    emu.reset();
+
+    let mut app = App::new(emu);
+
+    // leave the terminal usable even if the emulator panics
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        restore_terminal();
+        default_hook(info);
+    }));
+
+    terminal::enable_raw_mode().expect("Unable to enable raw mode");
+    execute!(io::stdout(), EnterAlternateScreen).expect("Unable to enter the alternate screen");
+
+    let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout())).expect("Unable to create the terminal");
+    let result = run(&mut terminal, &mut app);
+
+    restore_terminal();
+
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
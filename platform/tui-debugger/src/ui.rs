@@ -0,0 +1,235 @@
+use ratatui::Frame;
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Paragraph};
+
+use nesty::bus::Bus;
+use nesty::disasm;
+use nesty::debugger::{Breakpoint, BreakpointKind};
+use nesty::emulator::Emulator;
+
+use crate::app::App;
+
+const MEMORY_ROWS: u16 = 16;
+const FLAGS: &str = "NV-BDIZC";
+
+pub fn draw(f: &mut Frame, app: &mut App) {
+    let breakpoints = app.emu.debugger().breakpoints().to_vec();
+
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Min(10),
+            Constraint::Length(MEMORY_ROWS + 2),
+            Constraint::Length(4)
+        ])
+        .split(f.size());
+
+    let columns = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(55), Constraint::Percentage(45)])
+        .split(rows[0]);
+
+    let side = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(5),
+            Constraint::Length(6),
+            Constraint::Min(3),
+            Constraint::Min(3)
+        ])
+        .split(columns[1]);
+
+    let emu = &app.emu;
+
+    draw_disassembly(f, emu, &breakpoints, columns[0]);
+    draw_registers(f, emu, side[0]);
+    draw_ppu(f, emu, side[1]);
+    draw_stack(f, emu, side[2]);
+    draw_breakpoints(f, &breakpoints, side[3]);
+    draw_memory(f, emu, app.memory_base, rows[1]);
+    draw_command(f, app, rows[2]);
+}
+
+fn block(title: &str) -> Block<'_> {
+    Block::default().borders(Borders::ALL).title(title)
+}
+
+/*
+The 6502 can't be disassembled backwards, so try starting a little before PC
+and keep the furthest start whose instructions line up exactly with PC.
+*/
+fn sync_start(bus: &Bus, pc: u16) -> u16 {
+    for back in (1..=0x20u16).rev() {
+        let mut addr = pc.wrapping_sub(back);
+
+        while addr != pc && pc.wrapping_sub(addr) <= back {
+            addr = addr.wrapping_add(disasm::decode(bus, addr).size());
+        }
+
+        if addr == pc {
+            return pc.wrapping_sub(back);
+        }
+    }
+
+    pc
+}
+
+fn draw_disassembly(f: &mut Frame, emu: &Emulator, breakpoints: &[Breakpoint], area: Rect) {
+    let height = area.height.saturating_sub(2) as usize;
+
+    let bus = emu.bus();
+    let pc = emu.cpu().pc();
+
+    let start = sync_start(&bus, pc);
+    let listing = disasm::disassemble(&bus, start, pc.saturating_add((height * 3) as u16));
+
+    // keep about a third of the pane for what came before PC
+    let current = listing.iter().position(|line| line.instruction.address == pc).unwrap_or(0);
+    let first = current.saturating_sub(height / 3);
+
+    let mut lines = Vec::new();
+
+    for line in listing.iter().skip(first) {
+        if let Some(label) = line.label {
+            lines.push(Line::from(Span::styled(format!("{}:", label), Style::default().fg(Color::Yellow))));
+        }
+
+        let addr = line.instruction.address;
+        let bytes = line.instruction.bytes
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect::<Vec<String>>()
+            .join(" ");
+
+        let marker = if addr == pc { '>' } else { ' ' };
+        let dot = if breakpoints.iter().any(|bp| bp.enabled && bp.kind == BreakpointKind::Execute(addr)) { '*' } else { ' ' };
+        let text = format!("{}{} {:04X}  {:<8}  {}", marker, dot, addr, bytes, line.instruction);
+
+        let style = if addr == pc {
+            Style::default().add_modifier(Modifier::REVERSED)
+        } else if !line.instruction.official {
+            Style::default().fg(Color::DarkGray)
+        } else {
+            Style::default()
+        };
+
+        lines.push(Line::from(Span::styled(text, style)));
+    }
+
+    f.render_widget(Paragraph::new(lines).block(block("Disassembly")), area);
+}
+
+fn draw_registers(f: &mut Frame, emu: &Emulator, area: Rect) {
+    let cpu = emu.cpu();
+
+    let flags: String = FLAGS
+        .chars()
+        .enumerate()
+        .map(|(i, flag)| if (cpu.p() >> (7 - i)) & 1 == 1 { flag } else { '.' })
+        .collect();
+
+    let lines = vec![
+        Line::from(format!("A:{:02X}  X:{:02X}  Y:{:02X}  SP:{:02X}  PC:{:04X}", cpu.a(), cpu.x(), cpu.y(), cpu.sp(), cpu.pc())),
+        Line::from(format!("P:{:02X}  {}", cpu.p(), flags)),
+        Line::from(format!("CYC:{}{}", cpu.total_cycles, if cpu.halted() { "  HALTED" } else { "" }))
+    ];
+
+    f.render_widget(Paragraph::new(lines).block(block("CPU")), area);
+}
+
+fn draw_ppu(f: &mut Frame, emu: &Emulator, area: Rect) {
+    let ppu = emu.ppu();
+
+    let lines = vec![
+        Line::from(format!("Scanline:{:>4}  Dot:{:>4}", ppu.scanline(), ppu.cycle())),
+        Line::from(format!("v:{:04X}  t:{:04X}  x:{}  w:{}", ppu.vram_address(), ppu.temp_vram_address(), ppu.fine_x(), ppu.addr_latch() as u8)),
+        Line::from(format!("PPUCTRL:{:02X}  PPUMASK:{:02X}", ppu.control(), ppu.mask())),
+        Line::from(format!("PPUSTATUS:{:02X}", ppu.peek_register(2)))
+    ];
+
+    f.render_widget(Paragraph::new(lines).block(block("PPU")), area);
+}
+
+// From the top of page 1 down to the last byte pushed
+fn draw_stack(f: &mut Frame, emu: &Emulator, area: Rect) {
+    let bus = emu.bus();
+    let sp = emu.cpu().sp();
+
+    let lines: Vec<Line> = (sp as u16 + 1..=0xFF)
+        .rev()
+        .map(|offset| {
+            let addr = 0x0100 + offset;
+            Line::from(format!("{:04X}: {:02X}", addr, bus.peek_byte(addr)))
+        })
+        .collect();
+
+    // the most recently pushed bytes are the interesting ones
+    let skip = lines.len().saturating_sub(area.height.saturating_sub(2) as usize);
+    let lines: Vec<Line> = lines.into_iter().skip(skip).collect();
+
+    f.render_widget(Paragraph::new(lines).block(block("Stack")), area);
+}
+
+fn draw_breakpoints(f: &mut Frame, breakpoints: &[Breakpoint], area: Rect) {
+    let lines: Vec<Line> = breakpoints
+        .iter()
+        .map(|bp| {
+            let kind = match bp.kind {
+                BreakpointKind::Execute(addr) => format!("exec  ${:04X}", addr),
+                BreakpointKind::Read { start, end } => format!("read  ${:04X}-${:04X}", start, end),
+                BreakpointKind::Write { start, end } => format!("write ${:04X}-${:04X}", start, end),
+                BreakpointKind::PpuRegisterRead(register) => format!("read  $200{}", register),
+                BreakpointKind::PpuRegisterWrite(register) => format!("write $200{}", register)
+            };
+            let condition = match bp.condition {
+                Some(condition) => format!(" if {:?}", condition),
+                None => String::new()
+            };
+
+            Line::from(format!("{:>2} [{}] {}{}", bp.id, if bp.enabled { 'x' } else { ' ' }, kind, condition))
+        })
+        .collect();
+
+    f.render_widget(Paragraph::new(lines).block(block("Breakpoints")), area);
+}
+
+fn draw_memory(f: &mut Frame, emu: &Emulator, base: u16, area: Rect) {
+    let bus = emu.bus();
+
+    let lines: Vec<Line> = (0..MEMORY_ROWS)
+        .map(|row| {
+            let addr = base.wrapping_add(row * 16);
+            let bytes: Vec<u8> = (0..16).map(|i| bus.peek_byte(addr.wrapping_add(i))).collect();
+
+            let hex = bytes
+                .iter()
+                .map(|b| format!("{:02X}", b))
+                .collect::<Vec<String>>()
+                .join(" ");
+            let ascii: String = bytes
+                .iter()
+                .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+                .collect();
+
+            Line::from(format!("{:04X}  {}  {}", addr, hex, ascii))
+        })
+        .collect();
+
+    f.render_widget(Paragraph::new(lines).block(block("Memory")), area);
+}
+
+fn draw_command(f: &mut Frame, app: &App, area: Rect) {
+    let lines = vec![
+        Line::from(Span::styled(app.message.clone(), Style::default().fg(Color::Cyan))),
+        Line::from(format!("> {}", app.input))
+    ];
+
+    let title = if app.running { "Running (Esc to pause)" } else { "Paused" };
+    f.render_widget(Paragraph::new(lines).block(block(title)), area);
+
+    // put the terminal cursor at the end of the prompt
+    let x = area.x + 3 + app.input.len() as u16;
+    f.set_cursor(x.min(area.right().saturating_sub(2)), area.y + 2);
+}
@@ -0,0 +1,57 @@
+// Plays the samples produced by the emulator. The main thread sends one chunk of samples per emulated frame;
+// they are kept in a ring buffer until the audio thread pulls them.
+const BUFFER_SIZE = 16384;
+
+// How often (in render quanta of 128 samples) the buffer level is reported back to the main thread
+const REPORT_INTERVAL = 4;
+
+class NestyAudioProcessor extends AudioWorkletProcessor {
+    constructor() {
+        super();
+
+        this.buffer = new Float32Array(BUFFER_SIZE);
+        this.readPos = 0;
+        this.writePos = 0;
+        this.count = 0;
+        this.lastSample = 0;
+        this.quanta = 0;
+
+        this.port.onmessage = (e) => this.push(e.data);
+    }
+
+    push(samples) {
+        for (let i = 0; i < samples.length; i++) {
+            if (this.count === BUFFER_SIZE) {
+                break; // overflow, drop the rest
+            }
+
+            this.buffer[this.writePos] = samples[i];
+            this.writePos = (this.writePos + 1) % BUFFER_SIZE;
+            this.count++;
+        }
+    }
+
+    process(inputs, outputs) {
+        const output = outputs[0][0];
+
+        for (let i = 0; i < output.length; i++) {
+            if (this.count > 0) {
+                this.lastSample = this.buffer[this.readPos];
+                this.readPos = (this.readPos + 1) % BUFFER_SIZE;
+                this.count--;
+            }
+
+            // on underrun keep repeating the last sample, which pops less than dropping to zero
+            output[i] = this.lastSample;
+        }
+
+        this.quanta++;
+        if (this.quanta % REPORT_INTERVAL === 0) {
+            this.port.postMessage(this.count);
+        }
+
+        return true;
+    }
+}
+
+registerProcessor('nesty-audio', NestyAudioProcessor);
@@ -8,8 +8,62 @@ const selector = document.getElementById("samples-select");
 
 const nesty = NestyWeb.new();
 
+// Number of samples we try to keep buffered in the audio worklet (about 3 frames)
+const AUDIO_TARGET_BUFFERED = 2048;
+// Maximum adjustment of the sample rate made by the dynamic rate control
+const MAX_RATE_DELTA = 0.005;
+
+let audioCtx = null;
+let audioNode = null;
+let audioBuffered = 0;
+
+// Browsers only allow audio to start after a user gesture, so this is called from input handlers
+async function initAudio() {
+    if (audioCtx !== null) {
+        audioCtx.resume();
+        return;
+    }
+
+    audioCtx = new AudioContext();
+    await audioCtx.audioWorklet.addModule('./audio-worklet.js');
+
+    audioNode = new AudioWorkletNode(audioCtx, 'nesty-audio', { outputChannelCount: [1] });
+    audioNode.port.onmessage = (e) => { audioBuffered = e.data; };
+    audioNode.connect(audioCtx.destination);
+
+    nesty.set_sample_rate(audioCtx.sampleRate);
+}
+
+function pushAudio() {
+    const samples = nesty.take_audio_samples();
+
+    if (audioNode === null) {
+        return;
+    }
+
+    // Dynamic rate control: produce slightly more samples when the worklet is running low and slightly fewer
+    // when it fills up, so we never drift away from the audio clock
+    const fill = audioBuffered / AUDIO_TARGET_BUFFERED;
+    const delta = Math.min(Math.max((1 - fill) * MAX_RATE_DELTA, -MAX_RATE_DELTA), MAX_RATE_DELTA);
+    nesty.set_sample_rate(audioCtx.sampleRate * (1 + delta));
+
+    audioNode.port.postMessage(samples, [samples.buffer]);
+    audioBuffered += samples.length;
+}
+
 function renderLoop() {
-    nesty.update();
+    if (audioNode === null) {
+        nesty.update();
+        pushAudio();
+    } else {
+        // requestAnimationFrame doesn't necessarily fire at 60Hz, so let the audio clock decide how many
+        // frames to emulate: none if the worklet has plenty of samples, two if we've fallen behind
+        for (let frames = 0; frames < 2 && audioBuffered < AUDIO_TARGET_BUFFERED; frames++) {
+            nesty.update();
+            pushAudio();
+        }
+    }
+
     requestAnimationFrame(renderLoop);
 }
 
@@ -23,6 +77,8 @@ function openROM(e) {
         return;
     }
 
+    initAudio();
+
     const reader = new FileReader();
     reader.onload = function(e) {
         const rom = new Uint8Array(e.target.result);
@@ -33,6 +89,8 @@ function openROM(e) {
 }
 
 function openROM2(romPath) {
+    initAudio();
+
     var xhr = new XMLHttpRequest();
 
     xhr.open("GET", romPath, true);
@@ -56,13 +114,18 @@ function openROM2(romPath) {
 document.getElementById('rom-input').addEventListener('change', openROM, false);
 
 display.addEventListener('keydown', (event) => {
-    if (event.code == "F10")      nesty.save_state();
-    else if (event.code == "F11") nesty.load_state();
-    else                          nesty.press_key(event.keyCode);
+    initAudio();
+
+    if (event.code == "F10")            nesty.save_state();
+    else if (event.code == "F11")       nesty.load_state();
+    else if (event.code == "Backspace") nesty.set_rewinding(true);
+    else if (/^Digit[0-9]$/.test(event.code)) nesty.select_slot(Number(event.code.charAt(5)));
+    else                                nesty.press_key(event.keyCode);
 }, false);
 
 display.addEventListener('keyup', (event) => {
-    nesty.release_key(event.keyCode);
+    if (event.code == "Backspace") nesty.set_rewinding(false);
+    else                           nesty.release_key(event.keyCode);
 }, false);
 
 selector.addEventListener("change", () => {
@@ -72,8 +135,17 @@ selector.addEventListener("change", () => {
     }
 });
 
+// Battery backed RAM is saved every few seconds anyway, this catches the last bit of progress
+document.addEventListener("visibilitychange", () => {
+    if (document.visibilityState === "hidden") {
+        nesty.save_battery();
+    }
+});
+
+window.addEventListener("pagehide", () => nesty.save_battery());
+
 window.addEventListener("keydown", function(e) {
-    if(["Space","ArrowUp","ArrowDown","ArrowLeft","ArrowRight","F11"].indexOf(e.code) > -1) {
+    if(["Space","ArrowUp","ArrowDown","ArrowLeft","ArrowRight","F11","Backspace"].indexOf(e.code) > -1) {
         e.preventDefault();
     }
 }, false);
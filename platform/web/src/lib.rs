@@ -18,10 +18,20 @@ use web_sys::{
     HtmlCanvasElement,
     KeyEvent
 };
-use serde_json;
 
 use nesty::emulator::*;
-use nesty::{savable::Savable, ppu, joypad};
+use nesty::rewind::Rewind;
+use nesty::{ppu, joypad};
+
+// How often battery backed RAM gets written to local storage while playing
+const BATTERY_SAVE_INTERVAL: u32 = 60 * 10;
+
+// Rewind history: a snapshot every frame, in at most 16 MB
+const REWIND_INTERVAL: u32 = 1;
+const REWIND_MEMORY: usize = 16 * 1024 * 1024;
+
+// Save state slots, picked with the number keys
+const SLOT_COUNT: usize = 10;
 
 /* TODO keycodes are deprecated, need something else... */
 lazy_static! {
@@ -44,6 +54,11 @@ lazy_static! {
 #[wasm_bindgen]
 pub struct NestyWeb {
     emu: Emulator,
+    rewind: Rewind,
+    rewinding: bool,
+    battery: Vec<u8>, /* what's in local storage right now */
+    battery_frames: u32,
+    slot: usize,
     saving: bool,
     wait_for_nmi: bool
 }
@@ -53,20 +68,29 @@ impl NestyWeb {
     pub fn new() -> Self {
         NestyWeb {
             emu: Emulator::new(),
+            rewind: Rewind::new(REWIND_INTERVAL, REWIND_MEMORY),
+            rewinding: false,
+            battery: Vec::new(),
+            battery_frames: 0,
+            slot: 0,
             saving: false,
             wait_for_nmi: false
         }
     }
 
     pub fn load_rom(&mut self, rom_data: Uint8Array) {
+        self.save_battery();
+
         let result = self.emu.load_rom(rom_data.to_vec());
         match result {
             Ok(_) => {
                 self.emu.reset();
+                self.rewind.clear();
+                self.load_battery();
             },
             Err(err) => {
                 let window = web_sys::window().unwrap();
-                window.alert_with_message(&err);
+                window.alert_with_message(&err.to_string());
             }
         }
     }
@@ -75,6 +99,66 @@ impl NestyWeb {
         self.emu.reset();
     }
 
+    // Every game gets its own entry, keyed by the ROM's hash
+    fn battery_key(&self) -> String {
+        format!("nesty-sram-{:016x}", self.emu.rom_hash())
+    }
+
+    fn load_battery(&mut self) {
+        let storage = web_sys::window().unwrap().local_storage().unwrap().unwrap();
+
+        if let Ok(Some(text)) = storage.get_item(&self.battery_key()) {
+            if let Some(data) = utils::from_storage(&text) {
+                self.emu.load_battery_ram(&data);
+            }
+        }
+
+        self.battery = self.emu.battery_ram().unwrap_or_default();
+        self.battery_frames = 0;
+    }
+
+    // Also called from script.js when the page is hidden or closed
+    pub fn save_battery(&mut self) {
+        if let Some(sram) = self.emu.battery_ram() {
+            if sram != self.battery {
+                let storage = web_sys::window().unwrap().local_storage().unwrap().unwrap();
+                if storage.set_item(&self.battery_key(), &utils::to_storage(&sram)).is_ok() {
+                    self.battery = sram;
+                }
+            }
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.emu.apu().set_sample_rate(sample_rate);
+    }
+
+    // Returns every audio sample produced since the last call as a Float32Array; call this once per frame
+    pub fn take_audio_samples(&mut self) -> Vec<f32> {
+        let mut samples = self.emu.apu().drain_samples();
+
+        // the frames replayed while rewinding sound wrong, but silence still has to be played to keep the pace
+        if self.rewinding {
+            samples.iter_mut().for_each(|sample| *sample = 0.0);
+        }
+
+        samples
+    }
+
+    // Held down to play the game backwards
+    pub fn set_rewinding(&mut self, rewinding: bool) {
+        self.rewinding = rewinding;
+    }
+
+    // Like the battery, states are kept per game, so loading one never picks up another game's state
+    fn state_key(&self) -> String {
+        format!("nesty-state-{:016x}-{}", self.emu.rom_hash(), self.slot)
+    }
+
+    pub fn select_slot(&mut self, slot: usize) {
+        self.slot = slot % SLOT_COUNT;
+    }
+
     pub fn save_state(&mut self) {
         self.saving = true;
         self.wait_for_nmi = true;
@@ -88,6 +172,12 @@ impl NestyWeb {
     pub fn update(&mut self) {
         utils::set_panic_hook();
 
+        if self.rewinding {
+            self.rewind.step_back(&mut self.emu);
+            self.do_render();
+            return;
+        }
+
         let mut total: u64 = 0;
 
         while total < CYCLES_PER_FRAME {
@@ -100,26 +190,48 @@ impl NestyWeb {
                     self.emu.save_state(&mut state);
                     state.write_u64::<LittleEndian>(total).expect("Unable to save u64");
 
-                    let serialized_json = serde_json::to_string(&state).expect("Unable to create JSON");
-                    storage.set_item("nesty-save-state", &serialized_json);
-                    window.alert_with_message("State saved!");
+                    match storage.set_item(&self.state_key(), &utils::to_storage(&state)) {
+                        Ok(_) => window.alert_with_message(&format!("State saved to slot {}", self.slot)),
+                        Err(_) => window.alert_with_message("Unable to save state, local storage is full")
+                    };
                 } else {
-                    let serialized_json = storage.get_item("nesty-save-state").expect("Failed to retrieve save state").unwrap();
-                    let buffer: Vec<u8> = serde_json::from_str(&serialized_json).expect("JSON deserialization failed");
-                    let mut cursor = Cursor::new(buffer);
+                    match storage.get_item(&self.state_key()) {
+                        Ok(Some(text)) => {
+                            let buffer = utils::from_storage(&text).unwrap_or_default();
+                            let mut cursor = Cursor::new(buffer);
 
-                    self.emu.load_state(&mut cursor);
-                    total = cursor.read_u64::<LittleEndian>().expect("Unable to load u64");
-                    window.alert_with_message("State loaded!");
+                            match self.emu.load_state(&mut cursor) {
+                                Ok(_) => {
+                                    total = cursor.read_u64::<LittleEndian>().unwrap_or(0);
+                                    self.rewind.clear();
+                                    window.alert_with_message(&format!("State loaded from slot {}", self.slot));
+                                }
+                                Err(err) => {
+                                    window.alert_with_message(&err.to_string());
+                                }
+                            }
+                        }
+                        _ => {
+                            window.alert_with_message(&format!("No state saved in slot {}", self.slot));
+                        }
+                    }
                 }
     
                 self.wait_for_nmi = false;
             }
 
-            total += self.emu.tick();
+            let (cycles, _) = self.emu.tick();
+            total += cycles;
         }
 
         self.do_render();
+        self.rewind.capture(&self.emu);
+
+        self.battery_frames += 1;
+        if self.battery_frames >= BATTERY_SAVE_INTERVAL {
+            self.battery_frames = 0;
+            self.save_battery();
+        }
     }
 
     pub fn press_key(&mut self, keycode: u32) {
@@ -1,4 +1,6 @@
 use cfg_if::cfg_if;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
 
 cfg_if! {
     // When the `console_error_panic_hook` feature is enabled, we can call the
@@ -12,3 +14,12 @@ cfg_if! {
     }
 
 }
+
+// Local storage only holds strings. Base64 makes binary data a third bigger, a JSON array of numbers up to four times
+pub fn to_storage(data: &[u8]) -> String {
+    STANDARD.encode(data)
+}
+
+pub fn from_storage(text: &str) -> Option<Vec<u8>> {
+    STANDARD.decode(text).ok()
+}